@@ -0,0 +1,110 @@
+use crate::error::GeneratorError;
+use crate::template::Template;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Load every `*.nix` template in a `.tar.gz`, `.tgz` or `.zip` archive, given as a path or an
+/// http(s) URL. Each template is named after its file and described by its flake's `description`.
+///
+/// Extraction and downloads use the system `tar`, `unzip` and `curl`.
+pub fn load_template_archive(source: &str) -> Result<Vec<Template>> {
+    let workdir = scratch_dir()?;
+    let result = load_into(source, &workdir);
+    // Best effort: a leftover scratch directory in the temp dir is harmless
+    let _ = fs::remove_dir_all(&workdir);
+    result
+}
+
+fn load_into(source: &str, workdir: &Path) -> Result<Vec<Template>> {
+    let archive = if source.starts_with("http://") || source.starts_with("https://") {
+        let file_name = source.rsplit('/').next().unwrap_or("archive");
+        let download = workdir.join(file_name);
+        run(Command::new("curl").arg("-fsSL").arg("-o").arg(&download).arg(source), source)?;
+        download
+    } else {
+        PathBuf::from(source)
+    };
+
+    let extracted = workdir.join("templates");
+    fs::create_dir_all(&extracted)?;
+    let name = archive.to_string_lossy();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        run(Command::new("tar").arg("-xzf").arg(&archive).arg("-C").arg(&extracted), source)?;
+    } else if name.ends_with(".zip") {
+        run(Command::new("unzip").arg("-q").arg(&archive).arg("-d").arg(&extracted), source)?;
+    } else {
+        return Err(GeneratorError::InvalidArchive(format!(
+            "'{source}' is not a .tar.gz, .tgz or .zip archive"
+        ))
+        .into());
+    }
+
+    let mut files = Vec::new();
+    collect_nix_files(&extracted, &mut files)?;
+    files.sort();
+    if files.is_empty() {
+        return Err(GeneratorError::InvalidArchive(format!("'{source}' contains no .nix templates")).into());
+    }
+
+    files
+        .into_iter()
+        .map(|file| {
+            let name = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let flake_content = fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {name}.nix from template archive '{source}'"))?;
+            let fragments = nix_parser::extract_flake_fragments(&flake_content)
+                .map_err(|e| GeneratorError::Parse(format!("{name}.nix in '{source}': {e}")))?;
+            let description = if fragments.header.is_empty() {
+                format!("{name} template from {source}")
+            } else {
+                fragments.header
+            };
+            Ok(Template {
+                name,
+                description,
+                flake_content,
+                additional_files: HashMap::new(),
+                placeholders: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Run an extraction or download command, turning a failure into an `InvalidArchive` error
+fn run(command: &mut Command, source: &str) -> Result<()> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to run {program}; it is needed to read template archives"))?;
+    if !output.status.success() {
+        return Err(GeneratorError::InvalidArchive(format!(
+            "{program} could not read '{source}': {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn collect_nix_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_nix_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "nix") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn scratch_dir() -> Result<PathBuf> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let dir = std::env::temp_dir().join(format!("nix-flake-generator-{}-{nanos}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
@@ -0,0 +1,88 @@
+/// Formatter or linter a template's `--with-checks` entry runs over the flake's source
+struct CheckCommand {
+    template: &'static str,
+    /// nixpkgs attributes the script needs on PATH
+    packages: &'static [&'static str],
+    /// Shell script that fails when the source isn't formatted; runs from a copy of the source
+    script: &'static str,
+}
+
+const CHECK_COMMANDS: &[CheckCommand] = &[
+    CheckCommand {
+        template: "c-cpp",
+        packages: &["clang-tools"],
+        script: r"find . -name '*.[ch]' -o -name '*.[ch]pp' | xargs -r clang-format --dry-run --Werror",
+    },
+    CheckCommand {
+        template: "go",
+        packages: &["go"],
+        script: r#"test -z "$(gofmt -l .)""#,
+    },
+    CheckCommand {
+        template: "nix",
+        packages: &["nixfmt-rfc-style"],
+        script: r"find . -name '*.nix' | xargs -r nixfmt --check",
+    },
+    CheckCommand {
+        template: "node",
+        packages: &["nodePackages.prettier"],
+        script: "prettier --check .",
+    },
+    CheckCommand {
+        template: "python",
+        packages: &["black"],
+        script: "black --check .",
+    },
+    CheckCommand {
+        template: "rust",
+        packages: &["rustfmt"],
+        script: r"find . -name '*.rs' | xargs -r rustfmt --check --edition 2021",
+    },
+    CheckCommand {
+        template: "rust-toolchain",
+        packages: &["rustfmt"],
+        script: r"find . -name '*.rs' | xargs -r rustfmt --check --edition 2021",
+    },
+    CheckCommand {
+        template: "shell",
+        packages: &["shellcheck"],
+        script: r"find . -name '*.sh' | xargs -r shellcheck",
+    },
+    CheckCommand {
+        template: "zig",
+        packages: &["zig"],
+        script: "zig fmt --check .",
+    },
+];
+
+/// The `checks.<system>` entry for `template` as its name and Nix source, or `None` if the
+/// template has no formatter check
+pub fn format_check(template: &str) -> Option<(String, String)> {
+    let check = CHECK_COMMANDS.iter().find(|check| check.template == template)?;
+    let name = format!("{template}-fmt");
+    let derivation = format!(
+        "pkgs.runCommand \"{name}\" {{ nativeBuildInputs = with pkgs; [ {} ]; }} ''
+            cp -r ${{self}} source && chmod -R u+w source && cd source
+            {}
+            touch $out
+          ''",
+        check.packages.join(" "),
+        check.script,
+    );
+    Some((name, derivation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_check_parses() {
+        for check in CHECK_COMMANDS {
+            let (name, derivation) = format_check(check.template).unwrap();
+            assert_eq!(name, format!("{}-fmt", check.template));
+            nix_parser::parse_nix_expr(&derivation).expect("check derivation should parse");
+        }
+        assert!(format_check("haskell").is_none());
+    }
+}
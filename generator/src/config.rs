@@ -0,0 +1,52 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Defaults read from `$XDG_CONFIG_HOME/nix-flake-generator/config.toml` (or `~/.config/...`);
+/// command-line flags override them
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Banner placed above every generated flake.nix unless `--banner` is given
+    pub banner: Option<String>,
+}
+
+impl Config {
+    /// Load the config file, or the defaults if there is none
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .with_context(|| format!("Failed to parse config file {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("Failed to read config file {}", path.display())),
+        }
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let non_empty = |name| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let config_dir = non_empty("XDG_CONFIG_HOME").or_else(|| non_empty("HOME").map(|home| home.join(".config")))?;
+    Some(config_dir.join("nix-flake-generator").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parses_banner() {
+        let config: Config = toml::from_str("banner = \"Generated by dev-template-generator\"").unwrap();
+        assert_eq!(config.banner.as_deref(), Some("Generated by dev-template-generator"));
+
+        let empty: Config = toml::from_str("").unwrap();
+        assert_eq!(empty.banner, None);
+
+        assert!(toml::from_str::<Config>("baner = \"typo\"").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+/// Unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Unified diff of `old` against `new`, or an empty string if they have the same lines
+pub fn unified_diff(old_label: &str, new_label: &str, old: &str, new: &str) -> String {
+    let lines = diff_lines(&old.lines().collect::<Vec<_>>(), &new.lines().collect::<Vec<_>>());
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&i| !matches!(lines[i], Line::Same(_)))
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Group changes whose context would touch into one hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = format!("--- {old_label}\n+++ {new_label}\n");
+    for (start, end) in hunks {
+        // Line numbers of the hunk's first line in each file, counting from 1
+        let old_start = 1 + lines[..start].iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let new_start = 1 + lines[..start].iter().filter(|l| !matches!(l, Line::Removed(_))).count();
+        let hunk = &lines[start..end];
+        let old_count = hunk.iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let new_count = hunk.iter().filter(|l| !matches!(l, Line::Removed(_))).count();
+        output.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        ));
+        for line in hunk {
+            let (marker, text) = match line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            output.push(marker);
+            output.push_str(text);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// `start,count`, where an empty range names the line before it as diff does
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start - 1),
+        1 => start.to_string(),
+        _ => format!("{start},{count}"),
+    }
+}
+
+/// Line-by-line edit script from the longest common subsequence; templates are small enough
+/// for the quadratic table
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            // Removals go first, as in diff(1)
+            lines.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+        let new = "a\nb\nc\nD\ne\nf\ng\nh\ni\nj\nk\nl\nm\nn\n";
+        assert_eq!(
+            unified_diff("old", "new", old, new),
+            "--- old\n+++ new\n@@ -1,7 +1,7 @@\n a\n b\n c\n-d\n+D\n e\n f\n g\n@@ -11,3 +11,4 @@\n k\n l\n m\n+n\n"
+        );
+        assert_eq!(unified_diff("old", "new", "", "a\n"), "--- old\n+++ new\n@@ -0,0 +1 @@\n+a\n");
+        assert_eq!(unified_diff("old", "new", old, old), "");
+    }
+}
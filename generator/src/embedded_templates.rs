@@ -1,3 +1,4 @@
+use crate::placeholders::Placeholder;
 use once_cell::sync::Lazy;
 use rust_embed::RustEmbed;
 use serde::Deserialize;
@@ -10,6 +11,8 @@ struct Templates;
 #[derive(Deserialize)]
 struct TemplateMetadata {
     template: TemplateInfo,
+    #[serde(default)]
+    placeholders: Vec<Placeholder>,
 }
 
 #[derive(Deserialize)]
@@ -17,10 +20,17 @@ struct TemplateInfo {
     description: String,
 }
 
-pub static EMBEDDED_TEMPLATES: Lazy<HashMap<&'static str, (&'static str, &'static str)>> =
+/// A template compiled into the binary, with the placeholders its metadata declares
+pub struct EmbeddedTemplate {
+    pub description: &'static str,
+    pub content: &'static str,
+    pub placeholders: Vec<Placeholder>,
+}
+
+pub static EMBEDDED_TEMPLATES: Lazy<HashMap<&'static str, EmbeddedTemplate>> =
     Lazy::new(load_templates);
 
-fn load_templates() -> HashMap<&'static str, (&'static str, &'static str)> {
+fn load_templates() -> HashMap<&'static str, EmbeddedTemplate> {
     let mut templates = HashMap::new();
 
     // Get all embedded files
@@ -46,7 +56,14 @@ fn load_templates() -> HashMap<&'static str, (&'static str, &'static str)> {
                                 let name: &'static str =
                                     Box::leak(template_name.to_string().into_boxed_str());
 
-                                templates.insert(name, (description, content));
+                                templates.insert(
+                                    name,
+                                    EmbeddedTemplate {
+                                        description,
+                                        content,
+                                        placeholders: metadata.placeholders,
+                                    },
+                                );
                             }
                         }
                     }
@@ -70,11 +87,11 @@ mod tests {
         // Test that rust template exists
         assert!(templates.contains_key("rust"), "Rust template should exist");
 
-        if let Some((description, content)) = templates.get("rust") {
-            assert!(!description.is_empty(), "Description should not be empty");
-            assert!(!content.is_empty(), "Content should not be empty");
+        if let Some(template) = templates.get("rust") {
+            assert!(!template.description.is_empty(), "Description should not be empty");
+            assert!(!template.content.is_empty(), "Content should not be empty");
             assert!(
-                content.contains("rust-overlay"),
+                template.content.contains("rust-overlay"),
                 "Rust template should contain rust-overlay"
             );
         }
@@ -132,13 +149,13 @@ mod tests {
     #[test]
     fn test_rust_template_has_overlay() {
         let templates = &*EMBEDDED_TEMPLATES;
-        if let Some((_, content)) = templates.get("rust") {
+        if let Some(template) = templates.get("rust") {
             assert!(
-                content.contains("overlays.default"),
+                template.content.contains("overlays.default"),
                 "Rust template should have overlay"
             );
             assert!(
-                content.contains("rustToolchain"),
+                template.content.contains("rustToolchain"),
                 "Rust template should define rustToolchain"
             );
         }
@@ -147,11 +164,12 @@ mod tests {
     #[test]
     fn test_go_template_version() {
         let templates = &*EMBEDDED_TEMPLATES;
-        if let Some((_, content)) = templates.get("go") {
+        if let Some(template) = templates.get("go") {
             assert!(
-                content.contains("go"),
+                template.content.contains("go"),
                 "Go template should contain go package"
             );
+            assert_eq!(template.placeholders[0].binding, "goVersion");
         }
     }
 
@@ -161,10 +179,10 @@ mod tests {
         let java_templates = ["java", "kotlin", "scala"];
 
         for template_name in &java_templates {
-            if let Some((_, content)) = templates.get(template_name) {
+            if let Some(template) = templates.get(template_name) {
                 // Java templates should reference JDK in some form
                 assert!(
-                    content.contains("jdk") || content.contains("openjdk"),
+                    template.content.contains("jdk") || template.content.contains("openjdk"),
                     "{template_name} template should reference JDK"
                 );
             }
@@ -0,0 +1,92 @@
+use std::process::ExitCode;
+use thiserror::Error;
+
+/// Exit code for failures without a more specific category
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code when a requested template does not exist (user error)
+pub const EXIT_TEMPLATE_NOT_FOUND: u8 = 3;
+/// Exit code when a template or flake fails to parse (template bug)
+pub const EXIT_PARSE_ERROR: u8 = 4;
+/// Exit code for filesystem errors
+pub const EXIT_IO_ERROR: u8 = 5;
+
+#[derive(Error, Debug)]
+pub enum GeneratorError {
+    #[error("Template '{0}' not found")]
+    TemplateNotFound(String),
+    #[error("Failed to parse nix template: {0}")]
+    Parse(String),
+    #[error("Generated flake is not valid Nix (this is a generator bug): {0}")]
+    InvalidOutput(String),
+    #[error("Can't set placeholder {name} in template '{template}': {reason}")]
+    InvalidPlaceholder { template: String, name: String, reason: String },
+    #[error("No selected template has a placeholder named '{0}'")]
+    UnknownPlaceholder(String),
+    #[error("Invalid template archive: {0}")]
+    InvalidArchive(String),
+}
+
+impl GeneratorError {
+    /// Stable identifier for `--json-errors` output
+    fn kind(&self) -> &'static str {
+        match self {
+            GeneratorError::TemplateNotFound(_) => "template_not_found",
+            GeneratorError::Parse(_) => "parse_error",
+            GeneratorError::InvalidOutput(_) => "invalid_output",
+            GeneratorError::InvalidPlaceholder { .. } => "invalid_placeholder",
+            GeneratorError::UnknownPlaceholder(_) => "unknown_placeholder",
+            GeneratorError::InvalidArchive(_) => "invalid_archive",
+        }
+    }
+}
+
+/// Render an error as a JSON object with its `kind` and `message`, plus the `line` and
+/// `column` for parse errors that report a position
+pub fn json_error(error: &anyhow::Error) -> String {
+    let kind = error
+        .chain()
+        .find_map(|cause| {
+            if let Some(generator_error) = cause.downcast_ref::<GeneratorError>() {
+                Some(generator_error.kind())
+            } else {
+                cause.downcast_ref::<std::io::Error>().map(|_| "io_error")
+            }
+        })
+        .unwrap_or("error");
+    let message = format!("{error:#}");
+
+    let mut object = serde_json::json!({ "kind": kind, "message": message });
+    if let Some((line, column)) = (kind == "parse_error").then(|| parse_location(&message)).flatten() {
+        object["line"] = line.into();
+        object["column"] = column.into();
+    }
+    object.to_string()
+}
+
+/// The `line N, column M` position nix-parser includes in its error messages
+fn parse_location(message: &str) -> Option<(u64, u64)> {
+    let rest = &message[message.find("line ")? + "line ".len()..];
+    let (line, rest) = rest.split_once(", column ")?;
+    let column: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    Some((line.parse().ok()?, column.parse().ok()?))
+}
+
+/// Map an error chain to the exit code of the first categorized cause
+pub fn exit_code_for(error: &anyhow::Error) -> ExitCode {
+    for cause in error.chain() {
+        if let Some(generator_error) = cause.downcast_ref::<GeneratorError>() {
+            return ExitCode::from(match generator_error {
+                GeneratorError::TemplateNotFound(_) => EXIT_TEMPLATE_NOT_FOUND,
+                GeneratorError::Parse(_) => EXIT_PARSE_ERROR,
+                GeneratorError::InvalidOutput(_)
+                | GeneratorError::InvalidPlaceholder { .. }
+                | GeneratorError::UnknownPlaceholder(_)
+                | GeneratorError::InvalidArchive(_) => EXIT_FAILURE,
+            });
+        }
+        if cause.downcast_ref::<std::io::Error>().is_some() {
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    }
+    ExitCode::from(EXIT_FAILURE)
+}
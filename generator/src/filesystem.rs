@@ -0,0 +1,82 @@
+use std::io;
+use std::path::Path;
+
+/// File operations `TemplateManager` uses to write generated files, so the write logic can be
+/// tested without touching the disk
+pub trait FileSystem: Send + Sync {
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    fn write(&self, path: &Path, contents: &str) -> io::Result<()>;
+    fn exists(&self, path: &Path) -> bool;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Whether paths name real files, which external tools such as formatters can open
+    fn is_on_disk(&self) -> bool;
+}
+
+/// The real filesystem, through `std::fs`
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn is_on_disk(&self) -> bool {
+        true
+    }
+}
+
+/// Files held in memory, shared between clones; directories are implied by the files in them
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    files: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, String>>>,
+}
+
+#[cfg(test)]
+impl MemoryFileSystem {
+    pub fn with_file(self, path: impl Into<std::path::PathBuf>, contents: &str) -> Self {
+        self.files.lock().unwrap().insert(path.into(), contents.to_string());
+        self
+    }
+
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<String> {
+        self.files.lock().unwrap().get(path.as_ref()).cloned()
+    }
+}
+
+#[cfg(test)]
+impl FileSystem for MemoryFileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.file(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.display().to_string()))
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> io::Result<()> {
+        self.files.lock().unwrap().insert(path.to_path_buf(), contents.to_string());
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().keys().any(|file| file.starts_with(path))
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn is_on_disk(&self) -> bool {
+        false
+    }
+}
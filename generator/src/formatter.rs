@@ -0,0 +1,53 @@
+use clap::ValueEnum;
+use std::path::Path;
+use std::process::Command;
+
+/// External command used to format the generated flake.nix
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Formatter {
+    #[default]
+    Nixfmt,
+    Alejandra,
+    /// `nix fmt`, using the formatter configured by the surrounding flake
+    NixFmt,
+    /// Leave the generated file as-is
+    None,
+}
+
+impl Formatter {
+    /// Name shown in messages and the binary that has to be on PATH
+    pub fn name(self) -> &'static str {
+        match self {
+            Formatter::Nixfmt => "nixfmt",
+            Formatter::Alejandra => "alejandra",
+            Formatter::NixFmt => "nix fmt",
+            Formatter::None => "none",
+        }
+    }
+
+    /// Command that probes whether the formatter is installed, or `None` if nothing runs
+    pub fn version_command(self) -> Option<Command> {
+        let mut command = Command::new(self.program()?);
+        command.arg("--version");
+        Some(command)
+    }
+
+    /// Command that formats `file_path` in place, or `None` if nothing runs
+    pub fn format_command(self, file_path: &Path) -> Option<Command> {
+        let mut command = Command::new(self.program()?);
+        if self == Formatter::NixFmt {
+            command.arg("fmt");
+        }
+        command.arg(file_path);
+        Some(command)
+    }
+
+    fn program(self) -> Option<&'static str> {
+        match self {
+            Formatter::Nixfmt => Some("nixfmt"),
+            Formatter::Alejandra => Some("alejandra"),
+            Formatter::NixFmt => Some("nix"),
+            Formatter::None => None,
+        }
+    }
+}
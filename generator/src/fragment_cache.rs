@@ -0,0 +1,75 @@
+use crate::error::GeneratorError;
+use anyhow::{Result, anyhow};
+use nix_parser::{extract_flake_fragments_lenient, FlakeFragments};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Extraction results keyed by a hash of the template content, so identical
+/// content is only parsed once per process
+#[derive(Default)]
+pub struct FragmentCache {
+    inner: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<u64, FlakeFragments>,
+    parse_count: usize,
+}
+
+impl FragmentCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn extract(&self, content: &str) -> Result<FlakeFragments> {
+        let key = content_hash(content);
+        let mut state = self.inner.lock().map_err(|_| anyhow!("Fragment cache lock poisoned"))?;
+
+        if let Some(fragments) = state.entries.get(&key) {
+            return Ok(fragments.clone());
+        }
+
+        let fragments = extract_flake_fragments_lenient(content)
+            .map_err(|e| GeneratorError::Parse(e.to_string()))?;
+        state.parse_count += 1;
+        state.entries.insert(key, fragments.clone());
+        Ok(fragments)
+    }
+
+    /// Number of extractions that actually ran the parser (cache misses)
+    #[cfg(test)]
+    pub fn parse_count(&self) -> usize {
+        self.inner.lock().map(|state| state.parse_count).unwrap_or(0)
+    }
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedded_templates::EMBEDDED_TEMPLATES;
+
+    #[test]
+    fn test_second_extraction_is_served_from_cache() {
+        let cache = FragmentCache::new();
+        let content = EMBEDDED_TEMPLATES.get("rust").expect("rust template should exist").content;
+
+        let first = cache.extract(content).unwrap();
+        let second = cache.extract(content).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(cache.parse_count(), 1);
+
+        let python_content = EMBEDDED_TEMPLATES.get("python").expect("python template should exist").content;
+        cache.extract(python_content).unwrap();
+        assert_eq!(cache.parse_count(), 2);
+    }
+}
@@ -0,0 +1,21 @@
+//! Generate Nix flake development environments from language templates, merging several
+//! templates into one flake. The `nix-flake-generator` binary is a CLI over this library.
+
+pub mod archive;
+pub mod checks;
+pub mod config;
+pub mod diff;
+pub mod embedded_templates;
+pub mod error;
+pub mod filesystem;
+pub mod formatter;
+pub mod fragment_cache;
+pub mod markers;
+pub mod merger;
+pub mod placeholders;
+pub mod skeleton;
+pub mod template;
+pub mod upgrade;
+
+pub use merger::{generate_flake, merge_to_ast, GenerateOptions};
+pub use template::{Template, TemplateManager};
@@ -1,17 +1,42 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Read;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
-mod embedded_templates;
-mod merger;
-mod template;
+use nix_flake_generator::embedded_templates::EMBEDDED_TEMPLATES;
+use nix_flake_generator::formatter::Formatter;
+use nix_flake_generator::merger::{
+    fragment_summary, parse_package_pin, GenerateOptions, PackagePin, SortOrder, DEFAULT_PACKAGE_WARNING_THRESHOLD,
+    DEFAULT_PROFILE,
+};
+use nix_flake_generator::placeholders::parse_placeholder_value;
+use nix_flake_generator::template::TemplateManager;
+use nix_flake_generator::{archive, config, diff, error, skeleton, upgrade};
 
-use template::TemplateManager;
+/// Directory `init` writes to when `--path` is not given
+const TARGET_ENV_VAR: &str = "DEV_TEMPLATE_TARGET";
 
 #[derive(Parser)]
 #[command(name = "nix-flake-generator")]
 #[command(about = "Generate development environments from nix templates")]
 struct Cli {
+    /// Suppress informational output
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Log the sections recognized while extracting each template
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Also load the *.nix templates in a .tar.gz or .zip archive (path or URL), overriding
+    /// embedded templates of the same name
+    #[arg(long, global = true, value_name = "PATH_OR_URL")]
+    template_archive: Option<String>,
+    /// Report errors on stderr as a JSON object with `kind`, `message` and, for parse errors, `line` and `column`
+    #[arg(long, global = true)]
+    json_errors: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -22,45 +47,368 @@ enum Commands {
     Init {
         /// Template name(s) - single template (e.g., 'rust') or comma-separated list for multi-language (e.g., 'rust,go,node')
         templates: String,
-        /// Target directory (defaults to current directory)
+        /// Target directory (defaults to $DEV_TEMPLATE_TARGET, then the current directory)
         #[arg(short, long)]
         path: Option<PathBuf>,
+        /// Warn when a multi-language merge has more than this many distinct packages
+        #[arg(long, default_value_t = DEFAULT_PACKAGE_WARNING_THRESHOLD)]
+        max_packages: usize,
+        /// Pin packages via the generated overlay: 'go=1.22' resolves to go_1_22, 'python3=attr:python312' to python312
+        #[arg(long, value_delimiter = ',', value_parser = parse_package_pin)]
+        pin_packages: Vec<PackagePin>,
+        /// Shell script whose contents are appended to the generated shellHook
+        #[arg(long)]
+        shell_hook_file: Option<PathBuf>,
+        /// Annotate the generated flake with explanatory comments
+        #[arg(long)]
+        explain: bool,
+        /// Wrap generated sections in marker comments so re-running init keeps edits outside them
+        #[arg(long)]
+        markers: bool,
+        /// Command used to format the generated flake.nix
+        #[arg(long, value_enum, default_value_t = Formatter::Nixfmt)]
+        formatter: Formatter,
+        /// Replace the supported systems (e.g. 'x86_64-linux,aarch64-darwin')
+        #[arg(long, value_delimiter = ',')]
+        systems: Vec<String>,
+        /// Force config.allowUnfree on or off instead of following the templates
+        #[arg(long)]
+        allow_unfree: Option<bool>,
+        /// Only emit the dev shell, dropping apps, package outputs and extra attributes; allows one --systems entry
+        #[arg(long)]
+        minimal: bool,
+        /// Comment placed at the top of the generated flake.nix, overriding the config file's `banner`
+        #[arg(long)]
+        banner: Option<String>,
+        /// Also write a shell.nix shim for non-flake `nix-shell` users
+        #[arg(long)]
+        compat: bool,
+        /// Inline overlay packages into the shell instead of emitting overlays.default
+        #[arg(long)]
+        no_overlay: bool,
+        /// Make every overlay input follow the single top-level nixpkgs, warning when templates pin different ones
+        #[arg(long)]
+        dedupe_inputs: bool,
+        /// Print the files that would be written, and whether each already exists, without writing
+        #[arg(long)]
+        list_files: bool,
+        /// Add a checks output running each language's formatter, so `nix flake check` lints the source
+        #[arg(long)]
+        with_checks: bool,
+        /// Order of inputs and packages: alphabetical, nixpkgs and overlay inputs first, or as the templates declare them
+        #[arg(long, value_enum, default_value_t = SortOrder::Alpha)]
+        sort: SortOrder,
+        /// Take packages from each template's dev shell of this name (e.g. 'minimal'); 'full' keeps them all
+        #[arg(long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+        /// Write one flake per template into ./<template>/ plus a root flake referencing them, instead of merging
+        #[arg(long)]
+        split: bool,
+        /// Fill a template placeholder, overriding its default (e.g. 'GO_VERSION=22'); repeatable
+        #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_placeholder_value)]
+        set: Vec<(String, String)>,
+    },
+    /// Print the packages a template combination includes, without generating a flake
+    Packages {
+        /// Template name(s), comma-separated (e.g. 'rust,go')
+        templates: String,
+        /// Print a JSON array instead of one package per line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a template's description and packages
+    Info {
+        /// Template name (e.g. 'go')
+        template: String,
+        /// Resolve package versions with `nix eval` (requires nix)
+        #[arg(long)]
+        versions: bool,
+    },
+    /// Parse a flake.nix and summarize what extraction recognizes in it
+    Check {
+        /// Flake file to check (defaults to ./flake.nix)
+        file: Option<PathBuf>,
+        /// Read the flake from standard input instead of a file
+        #[arg(long, conflicts_with = "file")]
+        stdin: bool,
+    },
+    /// Point the nixpkgs inputs of an existing flake.nix at a new ref
+    Upgrade {
+        /// Directory containing flake.nix (defaults to current directory)
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+        /// Branch, tag or commit to use for nixpkgs (e.g. 'nixos-24.05')
+        #[arg(long)]
+        nixpkgs_ref: String,
+        /// Also upgrade inputs whose name matches this pattern, where '*' matches anything
+        /// (e.g. 'nixpkgs-*'; repeatable)
+        #[arg(long = "input", value_name = "PATTERN")]
+        inputs: Vec<String>,
+        /// Print the upgraded flake instead of writing it
+        #[arg(long)]
+        dry_run: bool,
     },
     /// List available templates
-    List,
+    List {
+        /// Only show templates that require `config.allowUnfree = true`
+        #[arg(long)]
+        unfree: bool,
+    },
+    /// Show how a template from --template-archive differs from the embedded one
+    DiffTemplates {
+        /// Template name (e.g., 'go')
+        name: String,
+    },
+    /// Write a skeleton <name>.nix to start a custom template from
+    NewTemplate {
+        /// Template name, used for the file name and description (e.g. 'mylang')
+        name: String,
+        /// Directory to write the template into (defaults to current directory)
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+    },
+    /// Check that every embedded template parses and yields inputs and packages
+    #[command(hide = true)]
+    Doctor,
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
-    let manager = TemplateManager::new().await?;
+    let json_errors = cli.json_errors;
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            if json_errors {
+                eprintln!("{}", error::json_error(&err));
+            } else {
+                eprintln!("Error: {err:#}");
+            }
+            error::exit_code_for(&err)
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let markers = matches!(cli.command, Commands::Init { markers: true, .. });
+    let compat = matches!(cli.command, Commands::Init { compat: true, .. });
+    let placeholder_values: HashMap<String, String> = match &cli.command {
+        Commands::Init { set, .. } => set.iter().cloned().collect(),
+        _ => HashMap::new(),
+    };
+    let archived_templates = cli
+        .template_archive
+        .as_deref()
+        .map(archive::load_template_archive)
+        .transpose()?
+        .unwrap_or_default();
+    let manager = TemplateManager::new()
+        .await?
+        .with_templates(archived_templates.clone())
+        .with_parse_cache()
+        .with_quiet(cli.quiet)
+        .with_markers(markers)
+        .with_compat(compat)
+        .with_placeholder_values(placeholder_values);
 
     match cli.command {
-        Commands::Init { templates, path } => {
-            let target_path = path.unwrap_or_else(|| PathBuf::from("."));
+        Commands::Init {
+            templates,
+            path,
+            max_packages,
+            pin_packages,
+            shell_hook_file,
+            explain,
+            formatter,
+            systems,
+            allow_unfree,
+            minimal,
+            banner,
+            no_overlay,
+            dedupe_inputs,
+            list_files,
+            with_checks,
+            sort,
+            profile,
+            split,
+            ..
+        } => {
+            let banner = match banner {
+                Some(banner) => Some(banner),
+                None => config::Config::load()?.banner,
+            };
+            let target_path = path
+                .or_else(|| env::var_os(TARGET_ENV_VAR).filter(|dir| !dir.is_empty()).map(PathBuf::from))
+                .unwrap_or_else(|| PathBuf::from("."));
+            if list_files {
+                let template_list: Vec<&str> = templates.split(',').map(|s| s.trim()).collect();
+                manager.list_files(&template_list, &target_path)?;
+                return Ok(());
+            }
+            let shell_hook = shell_hook_file
+                .map(|file| {
+                    fs::read_to_string(&file).with_context(|| {
+                        format!("Failed to read shell hook file {}", file.display())
+                    })
+                })
+                .transpose()?;
+            let options = GenerateOptions {
+                package_warning_threshold: max_packages,
+                pinned_packages: pin_packages,
+                shell_hook,
+                explain,
+                verbose: cli.verbose,
+                systems,
+                allow_unfree,
+                minimal,
+                formatter,
+                banner,
+                quiet: cli.quiet,
+                no_overlay,
+                dedupe_inputs,
+                with_checks,
+                sort,
+                profile,
+            };
 
             // Check if it's a single template or multiple templates
-            if templates.contains(',') {
+            if split {
+                let template_list: Vec<&str> = templates.split(',').map(|s| s.trim()).collect();
+                manager
+                    .init_split(&template_list, &target_path, &options)
+                    .await?;
+                if !cli.quiet {
+                    println!(
+                        "Initialized split templates ({}) in {}",
+                        templates,
+                        target_path.display()
+                    );
+                }
+            } else if templates.contains(',') {
                 // Multi-language template
                 let template_list: Vec<&str> = templates.split(',').map(|s| s.trim()).collect();
-                manager.init_multi(&template_list, &target_path).await?;
-                println!(
-                    "Initialized multi-language template ({}) in {}",
-                    templates,
-                    target_path.display()
-                );
+                manager
+                    .init_multi(&template_list, &target_path, &options)
+                    .await?;
+                if !cli.quiet {
+                    println!(
+                        "Initialized multi-language template ({}) in {}",
+                        templates,
+                        target_path.display()
+                    );
+                }
             } else {
                 // Single template
-                manager.init_single(&templates, &target_path).await?;
-                println!(
-                    "Initialized {} template in {}",
-                    templates,
-                    target_path.display()
-                );
+                manager
+                    .init_single(&templates, &target_path, &options)
+                    .await?;
+                if !cli.quiet {
+                    println!(
+                        "Initialized {} template in {}",
+                        templates,
+                        target_path.display()
+                    );
+                }
+            }
+        }
+        Commands::Packages { templates, json } => {
+            let template_list: Vec<&str> = templates.split(',').map(|s| s.trim()).collect();
+            let packages = manager.merged_packages(&template_list)?;
+            if json {
+                println!("{}", serde_json::to_string(&packages)?);
+            } else {
+                for package in packages {
+                    println!("{package}");
+                }
+            }
+        }
+        Commands::Info { template, versions } => {
+            manager.template_info(&template, versions)?;
+        }
+        Commands::Check { file, stdin } => {
+            let (source, content) = if stdin {
+                let mut content = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut content)
+                    .context("Failed to read flake from standard input")?;
+                ("<stdin>".to_string(), content)
+            } else {
+                let file = file.unwrap_or_else(|| PathBuf::from("flake.nix"));
+                let content = fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                (file.display().to_string(), content)
+            };
+            let fragments = nix_parser::extract_flake_fragments(&content)
+                .map_err(|e| error::GeneratorError::Parse(e.to_string()))?;
+            println!("{source}: ok");
+            for line in fragment_summary(&fragments) {
+                println!("  {line}");
+            }
+        }
+        Commands::Upgrade { path, nixpkgs_ref, inputs, dry_run } => {
+            let flake_path = path.unwrap_or_else(|| PathBuf::from(".")).join("flake.nix");
+            let content = fs::read_to_string(&flake_path)
+                .with_context(|| format!("Failed to read {}", flake_path.display()))?;
+            let (upgraded, changes) = upgrade::upgrade_nixpkgs(&content, &nixpkgs_ref, &inputs)?;
+            if dry_run {
+                // stdout carries only the flake, so it can be redirected or diffed
+                for (old, new) in &changes {
+                    eprintln!("Would upgrade {old} -> {new}");
+                }
+                print!("{upgraded}");
+                return Ok(());
+            }
+            fs::write(&flake_path, upgraded)?;
+            if !cli.quiet {
+                for (old, new) in &changes {
+                    println!("Upgraded {old} -> {new}");
+                }
+                if changes.is_empty() {
+                    println!("{} already uses nixpkgs {nixpkgs_ref}", flake_path.display());
+                }
+            }
+        }
+        Commands::List { unfree } => {
+            manager.list_templates(unfree)?;
+        }
+        Commands::DiffTemplates { name } => {
+            let embedded = EMBEDDED_TEMPLATES
+                .get(name.as_str())
+                .ok_or_else(|| error::GeneratorError::TemplateNotFound(name.clone()))?;
+            let archived = archived_templates.iter().find(|template| template.name == name);
+            match (archived, cli.template_archive.as_deref()) {
+                (Some(template), Some(source)) => {
+                    let diff = diff::unified_diff(
+                        &format!("embedded/{name}.nix"),
+                        &format!("{source}/{name}.nix"),
+                        embedded.content,
+                        &template.flake_content,
+                    );
+                    if diff.is_empty() {
+                        println!("Template '{name}' in {source} matches the embedded version");
+                    } else {
+                        print!("{diff}");
+                    }
+                }
+                (None, Some(source)) => println!("{source} has no '{name}' template to compare"),
+                _ => println!("No other version of '{name}' to compare; pass --template-archive with a newer copy"),
+            }
+        }
+        Commands::NewTemplate { name, path } => {
+            let skeleton = skeleton::template_skeleton(&name)?;
+            let template_path = path.unwrap_or_else(|| PathBuf::from(".")).join(format!("{name}.nix"));
+            if template_path.exists() {
+                return Err(anyhow::anyhow!("{} already exists", template_path.display()));
+            }
+            fs::write(&template_path, skeleton)
+                .with_context(|| format!("Failed to write {}", template_path.display()))?;
+            if !cli.quiet {
+                println!("Created {}", template_path.display());
             }
         }
-        Commands::List => {
-            manager.list_templates();
+        Commands::Doctor => {
+            manager.doctor()?;
         }
     }
 
@@ -0,0 +1,84 @@
+pub const BEGIN_MARKER: &str = "# BEGIN dev-template-generator";
+pub const END_MARKER: &str = "# END dev-template-generator";
+
+/// Wrap the body of a generated flake's top-level attribute set in marker comments,
+/// leaving room for user bindings after the END marker
+pub fn wrap_in_markers(flake: &str) -> String {
+    // Skip leading comments such as a banner, which stay above the markers
+    let code_start = leading_comments_len(flake);
+    let (Some(open), Some(close)) = (flake[code_start..].find('{').map(|i| i + code_start), flake.rfind('}')) else {
+        return flake.to_string();
+    };
+    if open >= close {
+        return flake.to_string();
+    }
+
+    let body = flake[open + 1..close].trim_matches('\n');
+    format!(
+        "{}\n  {BEGIN_MARKER}\n{body}\n  {END_MARKER}\n{}",
+        &flake[..=open],
+        &flake[close..]
+    )
+}
+
+/// Length of the blank and `#` comment lines at the start of `content`
+fn leading_comments_len(content: &str) -> usize {
+    let mut len = 0;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            break;
+        }
+        len += line.len();
+    }
+    len
+}
+
+/// Replace the marked region of `existing` with the marked region of `generated`.
+///
+/// Returns `None` when either side lacks a complete BEGIN/END pair, in which case
+/// the caller should fall back to writing `generated` as-is.
+pub fn splice_generated(existing: &str, generated: &str) -> Option<String> {
+    let (existing_start, existing_end) = marked_region(existing)?;
+    let (generated_start, generated_end) = marked_region(generated)?;
+
+    Some(format!(
+        "{}{}{}",
+        &existing[..existing_start],
+        &generated[generated_start..generated_end],
+        &existing[existing_end..]
+    ))
+}
+
+pub fn has_markers(content: &str) -> bool {
+    marked_region(content).is_some()
+}
+
+/// Byte range from the start of the BEGIN marker to the end of the END marker
+fn marked_region(content: &str) -> Option<(usize, usize)> {
+    let start = content.find(BEGIN_MARKER)?;
+    let end = start + content[start..].find(END_MARKER)? + END_MARKER.len();
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splice_keeps_content_outside_markers() {
+        let original = wrap_in_markers("{\n  description = \"old\";\n}\n");
+        let edited = original.replace(
+            &format!("{END_MARKER}\n"),
+            &format!("{END_MARKER}\n  nixConfig.sandbox = true;\n"),
+        );
+        let regenerated = wrap_in_markers("{\n  description = \"new\";\n}\n");
+
+        let spliced = splice_generated(&edited, &regenerated).unwrap();
+
+        assert!(spliced.contains("description = \"new\";"));
+        assert!(!spliced.contains("description = \"old\";"));
+        assert!(spliced.contains("nixConfig.sandbox = true;"));
+        assert!(splice_generated("{ }", &regenerated).is_none());
+    }
+}
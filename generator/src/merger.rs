@@ -1,75 +1,919 @@
+use crate::error::GeneratorError;
+use crate::formatter::Formatter;
+use crate::fragment_cache::FragmentCache;
 use crate::template::Template;
 use anyhow::{Result, anyhow};
-use nix_parser::{extract_flake_fragments, Binding, AttrPath, AttrPathPart};
+use clap::ValueEnum;
+use nix_parser::{
+    escape_indented_string, free_identifiers, parse_nix_expr, AttrPath, AttrPathPart, Binding,
+    InputFollows, LambdaParam, NixExpr, PatternParam, StringPart,
+};
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
+#[derive(Default)]
 pub struct FlakeFragments {
     pub header: String,
     pub inputs: HashMap<String, String>,
+    pub input_order: Vec<String>, // Input names in first-seen order, for `--sort source`
     pub overlays: HashMap<String, Vec<Binding>>, // Now using AST bindings
+    pub recursive_overlay: bool, // Emit `rec` only when a contributing overlay needs it
     pub packages: HashSet<String>,
+    pub package_order: Vec<String>, // Packages in first-seen order, for `--sort source`
+    pub inputs_from: HashSet<String>, // Rendered `inputsFrom` entries of the dev shell
+    pub shell_attrs: HashMap<String, NixExpr>, // Extra `mkShell` attributes such as `hardeningDisable`
     pub env_vars: HashMap<String, String>,
-    pub shell_hooks: HashSet<String>,
+    pub shell_hooks: Vec<String>, // Bodies of the templates' indented-string shell hooks, in template order
+    pub user_shell_hook: Option<String>,
+    pub profile: Option<String>, // Dev shell whose packages are taken from templates that define it
     pub allow_unfree: bool,
     pub let_bindings: HashMap<String, String>,
+    pub apps: HashMap<String, String>,
+    pub package_outputs: HashMap<String, String>, // `packages.<system>.<name>` build outputs
+    pub checks: HashMap<String, String>, // `checks.<system>.<name>` derivations added by --with-checks
+    pub passthrough: HashMap<String, String>, // Unrecognized top-level attributes, rendered as Nix source
+    pub input_follows: HashMap<String, Vec<InputFollows>>,
+    pub input_overlays: HashMap<String, Vec<String>>, // Input -> overlays of it applied to nixpkgs, e.g. `default`
+    pub nixpkgs_pins: Vec<(String, String)>, // Template -> nixpkgs URL it declared, in merge order
+    pub overlay_sources: HashMap<String, String>, // Overlay attribute -> template whose definition was kept
+    pub warnings: Vec<String>,
 }
 
-pub fn merge_templates(templates: &[Template]) -> Result<String> {
+/// nixpkgs input used when no contributing template declares one
+pub const DEFAULT_NIXPKGS_URL: &str = "github:NixOS/nixpkgs/nixos-unstable";
+
+/// Systems used when no contributing template defines `supportedSystems` or has a `systems` input
+const DEFAULT_SUPPORTED_SYSTEMS: &str =
+    r#"[ "x86_64-linux" "aarch64-linux" "x86_64-darwin" "aarch64-darwin" ]"#;
+
+/// Names Nix binds in every scope, which an inlined overlay value may refer to
+const NIX_GLOBALS: &[&str] = &[
+    "abort", "baseNameOf", "builtins", "derivation", "dirOf", "fetchTarball", "import", "isNull",
+    "map", "placeholder", "removeAttrs", "throw", "toString",
+];
+
+/// Profile that keeps every package of every dev shell a template defines
+pub const DEFAULT_PROFILE: &str = "full";
+
+/// Distinct package count above which a merged shell is considered too heavy
+pub const DEFAULT_PACKAGE_WARNING_THRESHOLD: usize = 20;
+
+/// Everything that shapes a generated flake, shared by the single and multi-template paths
+pub struct GenerateOptions {
+    /// Warn on stderr when the merged shell has more distinct packages than this
+    pub package_warning_threshold: usize,
+    /// Packages pinned to a specific version through the generated overlay
+    pub pinned_packages: Vec<PackagePin>,
+    /// Extra shell script appended to the generated `shellHook`
+    pub shell_hook: Option<String>,
+    /// Annotate the generated flake with explanatory comments
+    pub explain: bool,
+    /// Log the sections recognized in each template to stderr
+    pub verbose: bool,
+    /// Replace the templates' `supportedSystems` with these systems, if any are given
+    pub systems: Vec<String>,
+    /// Force `config.allowUnfree` on or off instead of following the templates
+    pub allow_unfree: Option<bool>,
+    /// Only emit the dev shell, dropping apps, package outputs, checks and passthrough attributes.
+    /// Targets one system directly, so at most one entry in `systems` is allowed.
+    pub minimal: bool,
+    /// Command used to format the flake once it is written
+    pub formatter: Formatter,
+    /// Comment placed above the flake, e.g. to mark it as generated
+    pub banner: Option<String>,
+    /// Suppress merge warnings such as conflicting overlay attributes
+    pub quiet: bool,
+    /// Inline overlay definitions into the package list instead of emitting `overlays.default`
+    pub no_overlay: bool,
+    /// Make every overlay input follow the top-level nixpkgs so it is only evaluated once
+    pub dedupe_inputs: bool,
+    /// Add a formatter check to `checks` for every template that has one
+    pub with_checks: bool,
+    /// Order of the generated inputs and packages
+    pub sort: SortOrder,
+    /// Take each template's packages from its dev shell of this name, e.g. `minimal`, when it has one
+    pub profile: String,
+}
+
+/// How inputs and packages are ordered in the generated flake
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// Alphabetically
+    #[default]
+    Alpha,
+    /// Inputs as nixpkgs, then overlay inputs, then the rest alphabetically; packages alphabetically
+    NixpkgsFirst,
+    /// In the order the templates declare them, earlier templates first
+    Source,
+}
+
+impl SortOrder {
+    fn compare_inputs(self, a: &str, b: &str, fragments: &FlakeFragments) -> Ordering {
+        // nixpkgs, then inputs whose overlays are applied, then everything else
+        let group = |name: &str| match name {
+            "nixpkgs" => 0,
+            _ if fragments.input_overlays.get(name).is_some_and(|overlays| !overlays.is_empty()) => 1,
+            _ => 2,
+        };
+        match self {
+            SortOrder::NixpkgsFirst => group(a).cmp(&group(b)).then_with(|| a.cmp(b)),
+            _ => self.compare_packages(a, b, &fragments.input_order),
+        }
+    }
+
+    fn compare_packages(self, a: &str, b: &str, seen: &[String]) -> Ordering {
+        // Names missing from `seen`, e.g. follows-only inputs, go last
+        let position = |name: &str| seen.iter().position(|s| s == name).unwrap_or(seen.len());
+        match self {
+            SortOrder::Source => position(a).cmp(&position(b)).then_with(|| a.cmp(b)),
+            _ => a.cmp(b),
+        }
+    }
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            package_warning_threshold: DEFAULT_PACKAGE_WARNING_THRESHOLD,
+            pinned_packages: Vec::new(),
+            shell_hook: None,
+            explain: false,
+            verbose: false,
+            systems: Vec::new(),
+            allow_unfree: None,
+            minimal: false,
+            formatter: Formatter::default(),
+            banner: None,
+            quiet: false,
+            no_overlay: false,
+            dedupe_inputs: false,
+            with_checks: false,
+            sort: SortOrder::default(),
+            profile: DEFAULT_PROFILE.to_string(),
+        }
+    }
+}
+
+impl FlakeFragments {
+    /// Declared inputs whose overlays a template applies to nixpkgs, e.g. through
+    /// `fenix.overlays.default`, in alphabetical order
+    fn overlay_inputs(&self) -> Vec<&String> {
+        let mut inputs: Vec<_> = self
+            .input_overlays
+            .iter()
+            .filter(|(input, overlays)| !overlays.is_empty() && self.inputs.contains_key(*input))
+            .map(|(input, _)| input)
+            .collect();
+        inputs.sort();
+        inputs
+    }
+
+    /// Inputs declared only as `<input>.follows = "<target>"`, with no URL of their own
+    fn follows_only_inputs(&self) -> Vec<(&String, &String)> {
+        self.input_follows
+            .iter()
+            .filter(|(name, _)| !self.inputs.contains_key(*name))
+            .filter_map(|(name, follows)| {
+                let own = follows.iter().find(|f| f.path.is_empty())?;
+                Some((name, &own.target))
+            })
+            .collect()
+    }
+}
+
+impl GenerateOptions {
+    /// Whether a single template has to be regenerated rather than copied verbatim
+    fn customizes_output(&self) -> bool {
+        !self.pinned_packages.is_empty()
+            || self.shell_hook.is_some()
+            || self.explain
+            || !self.systems.is_empty()
+            || self.allow_unfree.is_some()
+            || self.minimal
+            || self.no_overlay
+            || self.dedupe_inputs
+            || self.with_checks
+            || self.sort != SortOrder::Alpha
+            || self.profile != DEFAULT_PROFILE
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackagePin {
+    pub name: String,
+    pub target: PinTarget,
+}
+
+/// What a pinned package resolves to in nixpkgs
+#[derive(Debug, Clone, PartialEq)]
+pub enum PinTarget {
+    /// A version such as `1.22`, resolving to `name_1_22`
+    Version(String),
+    /// An attribute named as is, given as `attr:python312`
+    Attribute(String),
+}
+
+impl PackagePin {
+    /// Overlay binding pinning the package, e.g. `nodejs = final.nodejs_22;`
+    fn to_binding(&self) -> Binding {
+        let versioned = match &self.target {
+            PinTarget::Version(version) => format!("{}_{}", self.name, version.replace('.', "_")),
+            PinTarget::Attribute(attribute) => attribute.clone(),
+        };
+        Binding {
+            path: AttrPath {
+                parts: vec![AttrPathPart::Identifier(self.name.clone())],
+            },
+            value: NixExpr::Select {
+                expr: Box::new(NixExpr::Identifier("final".to_string())),
+                path: AttrPath {
+                    parts: vec![AttrPathPart::Identifier(versioned)],
+                },
+                default: None,
+            },
+        }
+    }
+}
+
+/// Parse a `name=version` or `name=attr:attribute` pin as accepted by `--pin-packages`
+pub fn parse_package_pin(spec: &str) -> Result<PackagePin> {
+    let (name, version) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid package pin '{}': expected name=version", spec))?;
+    let (name, version) = (name.trim(), version.trim());
+
+    let valid_name = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    if !valid_name {
+        return Err(anyhow!("Invalid package name '{}' in pin '{}'", name, spec));
+    }
+
+    if let Some(attribute) = version.strip_prefix("attr:") {
+        let valid_attribute = attribute.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && attribute.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if !valid_attribute {
+            return Err(anyhow!("Invalid attribute '{}' in pin '{}'", attribute, spec));
+        }
+        return Ok(PackagePin {
+            name: name.to_string(),
+            target: PinTarget::Attribute(attribute.to_string()),
+        });
+    }
+
+    let valid_version = version.chars().next().is_some_and(|c| c.is_ascii_digit())
+        && version.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+    if !valid_version {
+        return Err(anyhow!(
+            "Invalid version '{}' in pin '{}': expected a version such as 1.22, or attr:<attribute> to name the package attribute",
+            version,
+            spec
+        ));
+    }
+
+    Ok(PackagePin {
+        name: name.to_string(),
+        target: PinTarget::Version(version.to_string()),
+    })
+}
+
+/// Generate a flake from one or more templates, reusing extraction results from `cache` when provided
+pub fn generate_flake(
+    templates: &[Template],
+    options: &GenerateOptions,
+    cache: Option<&FragmentCache>,
+) -> Result<String> {
+    let flake = generate_flake_body(templates, options, cache)?;
+    self_check(&flake)?;
+    Ok(match &options.banner {
+        Some(banner) => format!("{}{flake}", banner_comment(banner)),
+        None => flake,
+    })
+}
+
+/// Merge templates with the default options into a flake AST, so callers embedding the generator
+/// can add or rewrite attributes before rendering it with [`NixExpr::to_nix_string`]. The CLI
+/// writes [`generate_flake`]'s text instead, which keeps its layout and `--explain` comments.
+pub fn merge_to_ast(templates: &[Template]) -> Result<NixExpr> {
+    let options = GenerateOptions { quiet: true, ..GenerateOptions::default() };
+    let flake = generate_flake(templates, &options, None)?;
+    parse_nix_expr(&flake).map_err(|e| GeneratorError::InvalidOutput(e.to_string()).into())
+}
+
+/// Generate a root flake for `--split`, exposing each template's flake in `./<name>` as a dev shell
+pub fn generate_split_flake(template_names: &[&str], options: &GenerateOptions) -> Result<String> {
+    let Some(first) = template_names.first() else {
+        return Err(anyhow!("No templates provided for splitting"));
+    };
+
+    let inputs: String = template_names
+        .iter()
+        .map(|name| format!("    {name}.url = \"path:./{name}\";\n"))
+        .collect();
+    let params: String = template_names.iter().map(|name| format!("      {name},\n")).collect();
+    let shells: String = template_names
+        .iter()
+        .map(|name| format!("        {name} = {name}.devShells.${{system}}.default;\n"))
+        .collect();
+    let shell_inputs: String = template_names
+        .iter()
+        .map(|name| format!("            {name}.devShells.${{system}}.default\n"))
+        .collect();
+
+    // Systems come from the first template's flake; `nix develop .#<name>` enters each shell, and
+    // the default shell, which `nix develop` and the `--compat` shim use, has every template's tools
+    let flake = format!(
+        r#"{{
+  description = "Multi-language development environment ({})";
+
+  inputs = {{
+{inputs}  }};
+
+  outputs =
+    {{
+      self,
+{params}    }}:
+    {{
+      devShells = builtins.mapAttrs (system: _: {{
+{shells}        default = {first}.inputs.nixpkgs.legacyPackages.${{system}}.mkShell {{
+          inputsFrom = [
+{shell_inputs}          ];
+        }};
+      }}) {first}.devShells;
+    }};
+}}
+"#,
+        template_names.join(", ")
+    );
+
+    self_check(&flake)?;
+    Ok(match &options.banner {
+        Some(banner) => format!("{}{flake}", banner_comment(banner)),
+        None => flake,
+    })
+}
+
+/// Re-parse generated output so generator bugs, such as unrendered AST nodes, surface before
+/// anything is written
+fn self_check(flake: &str) -> Result<()> {
+    parse_nix_expr(flake).map_err(|e| GeneratorError::InvalidOutput(e.to_string()))?;
+    Ok(())
+}
+
+/// Render `text` as one `#` comment line per line of text
+fn banner_comment(text: &str) -> String {
+    text.lines()
+        .map(|line| if line.is_empty() { "#\n".to_string() } else { format!("# {line}\n") })
+        .collect()
+}
+
+fn generate_flake_body(
+    templates: &[Template],
+    options: &GenerateOptions,
+    cache: Option<&FragmentCache>,
+) -> Result<String> {
     if templates.is_empty() {
         return Err(anyhow!("No templates provided for merging"));
     }
+    if options.minimal && options.systems.len() > 1 {
+        return Err(anyhow!(
+            "--minimal supports a single system, but --systems lists {} ({}); pass one system or drop --minimal",
+            options.systems.len(),
+            options.systems.join(", ")
+        ));
+    }
 
-    if templates.len() == 1 {
+    if templates.len() == 1 && !options.customizes_output() {
+        if options.verbose {
+            trace_fragments(&templates[0].name, &extract_fragments(&templates[0].flake_content, cache)?);
+        }
         return Ok(templates[0].flake_content.clone());
     }
 
     let mut fragments = FlakeFragments {
-        header: String::new(),
-        inputs: HashMap::new(),
-        overlays: HashMap::new(),
-        packages: HashSet::new(),
-        env_vars: HashMap::new(),
-        shell_hooks: HashSet::new(),
-        allow_unfree: false,
-        let_bindings: HashMap::new(),
+        user_shell_hook: options.shell_hook.clone(),
+        profile: (options.profile != DEFAULT_PROFILE).then(|| options.profile.clone()),
+        ..FlakeFragments::default()
     };
 
-    let descriptions: Vec<&str> = templates.iter().map(|t| t.name.as_str()).collect();
-    fragments.header = format!(
-        "Multi-language development environment ({})",
-        descriptions.join(", ")
-    );
+    fragments.header = if let [template] = templates {
+        template.description.clone()
+    } else {
+        let descriptions: Vec<&str> = templates.iter().map(|t| t.name.as_str()).collect();
+        format!(
+            "Multi-language development environment ({})",
+            descriptions.join(", ")
+        )
+    };
 
     for template in templates {
-        parse_template_with_nix_parser(&template.flake_content, &mut fragments)?;
+        parse_template_with_nix_parser(template, &mut fragments, cache, options.verbose)?;
+    }
+
+    // The generated outputs always reference nixpkgs, so it must be bound even if no template declared it
+    fragments
+        .inputs
+        .entry("nixpkgs".to_string())
+        .or_insert_with(|| DEFAULT_NIXPKGS_URL.to_string());
+
+    // Likewise forEachSupportedSystem always references supportedSystems
+    // No template listed its systems; a nix-systems style `systems` input supplies them if present
+    let default_systems = if fragments.inputs.contains_key("systems") {
+        "import systems"
+    } else {
+        DEFAULT_SUPPORTED_SYSTEMS
+    };
+    fragments
+        .let_bindings
+        .entry("supportedSystems".to_string())
+        .or_insert_with(|| default_systems.to_string());
+
+    if !options.systems.is_empty() {
+        let systems = options.systems.iter().cloned().map(NixExpr::String).collect();
+        fragments
+            .let_bindings
+            .insert("supportedSystems".to_string(), NixExpr::List(systems).to_nix_string());
+    }
+
+    if let Some(allow_unfree) = options.allow_unfree {
+        fragments.allow_unfree = allow_unfree;
+    }
+
+    // --minimal builds the shell for its one system directly, so the system list goes unused
+    let minimal_system = if options.minimal {
+        fragments.apps.clear();
+        fragments.package_outputs.clear();
+        fragments.passthrough.clear();
+        let supported_systems = fragments.let_bindings.remove("supportedSystems").unwrap_or_default();
+        Some(minimal_system(&supported_systems)?)
+    } else {
+        None
+    };
+
+    apply_package_pins(&options.pinned_packages, &mut fragments);
+
+    if options.no_overlay {
+        inline_overlays(&mut fragments)?;
+    }
+
+    if options.dedupe_inputs {
+        dedupe_nixpkgs(&mut fragments);
+    }
+
+    if options.with_checks && !options.minimal {
+        for template in templates {
+            match crate::checks::format_check(&template.name) {
+                Some((name, check)) => {
+                    fragments.checks.insert(name, check);
+                }
+                None => fragments
+                    .warnings
+                    .push(format!("no formatter check for template '{}'", template.name)),
+            }
+        }
+    }
+
+    warn_on_large_merge(&mut fragments, options.package_warning_threshold);
+
+    let flake = generate_merged_flake(&fragments, minimal_system.as_deref(), options.explain, options.sort)?;
+    for name in unused_outputs_params(&flake) {
+        fragments
+            .warnings
+            .push(format!("input '{name}' is passed to outputs but never referenced"));
+    }
+
+    if !options.quiet {
+        for warning in &fragments.warnings {
+            eprintln!("Warning: {warning}");
+        }
+    }
+    Ok(flake)
+}
+
+/// Parameters and body of the `outputs` function in a flake
+fn outputs_function(flake: &NixExpr) -> Option<(&[PatternParam], &NixExpr)> {
+    let NixExpr::AttrSet { bindings, .. } = flake else {
+        return None;
+    };
+    let outputs = bindings.iter().find(|binding| {
+        matches!(binding.path.parts.as_slice(), [AttrPathPart::Identifier(name)] if name == "outputs")
+    })?;
+    match &outputs.value {
+        NixExpr::Lambda { param: LambdaParam::Pattern { params, .. }, body } => Some((params, body)),
+        _ => None,
+    }
+}
+
+/// Outputs function parameters, other than `self`, that the outputs body never references
+fn unused_outputs_params(flake: &str) -> Vec<String> {
+    let Ok(expr) = parse_nix_expr(flake) else {
+        return Vec::new();
+    };
+    let Some((params, body)) = outputs_function(&expr) else {
+        return Vec::new();
+    };
+    let referenced = free_identifiers(body);
+    params
+        .iter()
+        .filter(|param| param.name != "self" && !referenced.contains(&param.name))
+        .map(|param| param.name.clone())
+        .collect()
+}
+
+fn apply_package_pins(pins: &[PackagePin], fragments: &mut FlakeFragments) {
+    if pins.is_empty() {
+        return;
+    }
+
+    let bindings = fragments.overlays.entry("default".to_string()).or_default();
+    for pin in pins {
+        // A pin replaces any template-provided binding for the same package
+        let binding = pin.to_binding();
+        bindings.retain(|existing| existing.path != binding.path);
+        bindings.push(binding);
+    }
+}
+
+/// Replace packages defined by the overlay with the overlay's own definition and drop the overlay.
+/// References to sibling overlay attributes are replaced by the sibling's definition, so every
+/// package is expressed against `pkgs`, which still has the overlays taken from inputs applied.
+/// Fails when that isn't possible, e.g. for an attribute referring to itself.
+fn inline_overlays(fragments: &mut FlakeFragments) -> Result<()> {
+    if fragments.overlays.is_empty() {
+        return Ok(());
+    }
+
+    let mut siblings = Siblings { definitions: HashMap::new(), recursive: fragments.recursive_overlay };
+    for binding in fragments.overlays.values().flatten() {
+        match (binding.path.parts.as_slice(), &binding.value) {
+            ([AttrPathPart::Identifier(keyword)], NixExpr::Inherit { from, attrs }) if keyword == "inherit" => {
+                for name in attrs {
+                    siblings.definitions.insert(name.clone(), inherited_value(from.as_deref(), name));
+                }
+            }
+            ([AttrPathPart::Identifier(name)], value) => {
+                siblings.definitions.insert(name.clone(), value.clone());
+            }
+            _ => {
+                return Err(anyhow!(
+                    "--no-overlay can't inline overlay attribute '{}'",
+                    format_attr_path(&binding.path)
+                ));
+            }
+        }
+    }
+
+    let mut definitions = HashMap::new();
+    for (name, value) in &siblings.definitions {
+        let value = rebase_on_pkgs(value, &siblings, 0)?;
+        if let Some(unbound) = free_identifiers(&value)
+            .into_iter()
+            .find(|id| id != "pkgs" && !NIX_GLOBALS.contains(&id.as_str()) && !fragments.let_bindings.contains_key(id))
+        {
+            return Err(anyhow!(
+                "--no-overlay can't inline overlay attribute '{name}': it refers to '{unbound}'"
+            ));
+        }
+        definitions.insert(name.clone(), package_reference(&value));
+    }
+
+    fragments.packages = fragments
+        .packages
+        .drain()
+        .map(|package| definitions.get(&package).cloned().unwrap_or(package))
+        .collect();
+    for package in &mut fragments.package_order {
+        if let Some(definition) = definitions.get(package) {
+            *package = definition.clone();
+        }
+    }
+    fragments.overlays.clear();
+    fragments.recursive_overlay = false;
+    Ok(())
+}
+
+/// The value `inherit name;` or `inherit (from) name;` binds
+fn inherited_value(from: Option<&NixExpr>, name: &str) -> NixExpr {
+    match from {
+        Some(from) => NixExpr::Select {
+            expr: Box::new(from.clone()),
+            path: AttrPath { parts: vec![AttrPathPart::Identifier(name.to_string())] },
+            default: None,
+        },
+        None => NixExpr::Identifier(name.to_string()),
+    }
+}
+
+/// Make every overlay input follow the top-level nixpkgs, so only one nixpkgs is evaluated,
+/// and report templates that pinned a different nixpkgs than the one kept. Other inputs that are
+/// nixpkgs under another name, e.g. `nixpkgs-unstable`, become aliases of the kept one.
+fn dedupe_nixpkgs(fragments: &mut FlakeFragments) {
+    let kept = fragments.inputs["nixpkgs"].clone();
+    for (template, url) in &fragments.nixpkgs_pins {
+        if *url != kept {
+            fragments.warnings.push(format!(
+                "{template} pins nixpkgs to {url}; using {kept} for every input instead"
+            ));
+        }
+    }
+
+    let mut aliases: Vec<_> = fragments
+        .inputs
+        .iter()
+        .filter(|(name, url)| *name != "nixpkgs" && crate::upgrade::is_nixpkgs_url(url))
+        .map(|(name, _)| name.clone())
+        .collect();
+    aliases.sort();
+    for alias in aliases {
+        let url = fragments.inputs.remove(&alias).unwrap_or_default();
+        if url != kept {
+            fragments.warnings.push(format!(
+                "input '{alias}' pins nixpkgs to {url}; it now follows the top-level nixpkgs"
+            ));
+        }
+        fragments.input_follows.insert(
+            alias,
+            vec![InputFollows { path: Vec::new(), target: "nixpkgs".to_string() }],
+        );
+    }
+
+    let overlay_inputs: Vec<_> = fragments.overlay_inputs().into_iter().cloned().collect();
+    for input in overlay_inputs {
+        let follows = fragments.input_follows.entry(input.clone()).or_default();
+        match follows.iter_mut().find(|f| f.path == ["nixpkgs"]) {
+            Some(entry) if entry.target != "nixpkgs" => {
+                fragments.warnings.push(format!(
+                    "input '{input}' followed '{}' for nixpkgs; it now follows the top-level nixpkgs",
+                    entry.target
+                ));
+                entry.target = "nixpkgs".to_string();
+            }
+            Some(_) => {}
+            None => follows.push(InputFollows {
+                path: vec!["nixpkgs".to_string()],
+                target: "nixpkgs".to_string(),
+            }),
+        }
     }
+}
+
+/// The attributes an overlay defines, for [`rebase_on_pkgs`]
+struct Siblings {
+    definitions: HashMap<String, NixExpr>,
+    recursive: bool,
+}
+
+/// Express an overlay value against the shell's `pkgs`: sibling overlay attributes, whether named
+/// directly or through `final`, become their own definitions, and other `final`/`prev` references
+/// point at `pkgs`. Bare sibling names only refer to siblings in a `rec` overlay. `depth` counts the
+/// sibling definitions being expanded, to stop at a cycle.
+fn rebase_on_pkgs(expr: &NixExpr, siblings: &Siblings, depth: usize) -> Result<NixExpr> {
+    let rebase = |expr: &NixExpr| rebase_on_pkgs(expr, siblings, depth).map(Box::new);
+    let sibling = |name: &str| -> Result<NixExpr> {
+        if depth >= siblings.definitions.len() {
+            return Err(anyhow!("--no-overlay can't inline overlay attribute '{name}': it refers to itself"));
+        }
+        rebase_on_pkgs(&siblings.definitions[name], siblings, depth + 1)
+    };
+    Ok(match expr {
+        NixExpr::Identifier(name) if siblings.recursive && siblings.definitions.contains_key(name) => sibling(name)?,
+        NixExpr::Identifier(name) if name == "final" || name == "prev" => NixExpr::Identifier("pkgs".to_string()),
+        NixExpr::Select { expr: target, path, default } if **target == NixExpr::Identifier("final".to_string()) => {
+            match path.parts.split_first() {
+                Some((AttrPathPart::Identifier(name), rest)) if siblings.definitions.contains_key(name) => {
+                    let definition = sibling(name)?;
+                    if rest.is_empty() && default.is_none() {
+                        definition
+                    } else {
+                        NixExpr::Select {
+                            expr: Box::new(definition),
+                            path: AttrPath { parts: rest.to_vec() },
+                            default: default.as_deref().map(rebase).transpose()?,
+                        }
+                    }
+                }
+                _ => NixExpr::Select {
+                    expr: rebase(target)?,
+                    path: path.clone(),
+                    default: default.as_deref().map(rebase).transpose()?,
+                },
+            }
+        }
+        NixExpr::Select { expr, path, default } => NixExpr::Select {
+            expr: rebase(expr)?,
+            path: path.clone(),
+            default: default.as_deref().map(rebase).transpose()?,
+        },
+        NixExpr::FunctionCall { function, argument } => NixExpr::FunctionCall {
+            function: rebase(function)?,
+            argument: rebase(argument)?,
+        },
+        NixExpr::BinaryOp { left, op, right } => NixExpr::BinaryOp {
+            left: rebase(left)?,
+            op: op.clone(),
+            right: rebase(right)?,
+        },
+        NixExpr::List(items) => NixExpr::List(
+            items
+                .iter()
+                .map(|item| rebase_on_pkgs(item, siblings, depth))
+                .collect::<Result<_>>()?,
+        ),
+        NixExpr::AttrSet { recursive, bindings } => NixExpr::AttrSet {
+            recursive: *recursive,
+            bindings: rebase_bindings(bindings, siblings, depth)?,
+        },
+        NixExpr::LetIn { bindings, body } => NixExpr::LetIn {
+            bindings: rebase_bindings(bindings, siblings, depth)?,
+            body: rebase(body)?,
+        },
+        NixExpr::If { condition, then_expr, else_expr } => NixExpr::If {
+            condition: rebase(condition)?,
+            then_expr: rebase(then_expr)?,
+            else_expr: rebase(else_expr)?,
+        },
+        NixExpr::InterpolatedString(parts) => NixExpr::InterpolatedString(
+            parts
+                .iter()
+                .map(|part| match part {
+                    StringPart::Interpolation(expr) => rebase(expr).map(StringPart::Interpolation),
+                    StringPart::Literal(text) => Ok(StringPart::Literal(text.clone())),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+/// [`rebase_on_pkgs`] for the bindings of an attribute set or `let`
+fn rebase_bindings(bindings: &[Binding], siblings: &Siblings, depth: usize) -> Result<Vec<Binding>> {
+    let mut rebased = Vec::with_capacity(bindings.len());
+    for binding in bindings {
+        match &binding.value {
+            // `inherit jdk;` would no longer see the sibling, so spell the value out
+            NixExpr::Inherit { from, attrs } => {
+                for name in attrs {
+                    rebased.push(Binding {
+                        path: AttrPath { parts: vec![AttrPathPart::Identifier(name.clone())] },
+                        value: rebase_on_pkgs(&inherited_value(from.as_deref(), name), siblings, depth)?,
+                    });
+                }
+            }
+            value => rebased.push(Binding {
+                path: binding.path.clone(),
+                value: rebase_on_pkgs(value, siblings, depth)?,
+            }),
+        }
+    }
+    Ok(rebased)
+}
+
+/// Render an expression so it can stand as a single item of the package list
+fn package_reference(expr: &NixExpr) -> String {
+    match expr {
+        NixExpr::Identifier(_) | NixExpr::Select { default: None, .. } => expr.to_nix_string(),
+        _ => format!("({})", expr.to_nix_string()),
+    }
+}
 
-    generate_merged_flake(&fragments)
+fn warn_on_large_merge(fragments: &mut FlakeFragments, threshold: usize) {
+    let package_count = fragments.packages.len();
+    if package_count > threshold {
+        fragments.warnings.push(format!(
+            "merged environment contains {package_count} packages (threshold {threshold}); \
+             consider splitting it into multiple dev shells"
+        ));
+    }
 }
 
-fn parse_template_with_nix_parser(content: &str, fragments: &mut FlakeFragments) -> Result<()> {
-    let parsed_fragments = extract_flake_fragments(content)
-        .map_err(|e| anyhow!("Failed to parse nix template: {}", e))?;
+fn parse_template_with_nix_parser(
+    template: &Template,
+    fragments: &mut FlakeFragments,
+    cache: Option<&FragmentCache>,
+    verbose: bool,
+) -> Result<()> {
+    let mut parsed_fragments = extract_fragments(&template.flake_content, cache)?;
+    if verbose {
+        trace_fragments(&template.name, &parsed_fragments);
+    }
 
     // Merge inputs
+    if let Some(url) = parsed_fragments.inputs.get("nixpkgs") {
+        fragments.nixpkgs_pins.push((template.name.clone(), url.clone()));
+    }
     for (key, value) in parsed_fragments.inputs {
         fragments.inputs.insert(key, value);
     }
+    for name in parsed_fragments.input_order {
+        if !fragments.input_order.contains(&name) {
+            fragments.input_order.push(name);
+        }
+    }
+
+    // Merge follows overrides; the first template to override a nested input wins
+    for (input, follows) in parsed_fragments.input_follows {
+        let existing = fragments.input_follows.entry(input).or_default();
+        for entry in follows {
+            if !existing.iter().any(|e| e.path == entry.path) {
+                existing.push(entry);
+            }
+        }
+    }
+
+    // Merge which overlays each input is applied through, e.g. `rust-overlay.overlays.rust-overlay`
+    for (input, overlays) in parsed_fragments.input_overlays {
+        let existing = fragments.input_overlays.entry(input).or_default();
+        for overlay in overlays {
+            if !existing.contains(&overlay) {
+                existing.push(overlay);
+            }
+        }
+    }
 
     // Merge overlays - deduplicate bindings at AST level
     for (key, bindings) in parsed_fragments.overlays {
-        if let Some(existing_bindings) = fragments.overlays.get_mut(&key) {
-            merge_overlay_bindings(existing_bindings, bindings);
+        // Sibling references need `rec` in the merged output. A `rec` overlay only needs it if it actually
+        // refers to its own attributes; in any other overlay a name its template's `let` doesn't bind can
+        // only mean a sibling.
+        let needs_rec = if parsed_fragments.recursive_overlays.contains(&key) {
+            overlay_self_references(&bindings, &HashMap::<String, String>::new())
         } else {
-            fragments.overlays.insert(key, bindings);
+            overlay_self_references(&bindings, &parsed_fragments.let_bindings)
+        };
+        if needs_rec {
+            fragments.recursive_overlay = true;
+        }
+        let existing_bindings = fragments.overlays.entry(key).or_default();
+        let conflicts = merge_overlay_bindings(existing_bindings, bindings);
+        for attr in existing_bindings.iter().flat_map(overlay_attr_names) {
+            fragments.overlay_sources.entry(attr).or_insert_with(|| template.name.clone());
+        }
+        for attr in conflicts {
+            let kept = &fragments.overlay_sources[&attr];
+            fragments.warnings.push(format!(
+                "overlay attribute '{attr}' is defined differently by {kept} and {}; keeping {kept}'s definition",
+                template.name
+            ));
         }
     }
 
     // Merge packages (convert Vec to HashSet)
     // Note: We don't filter out overlay-defined packages anymore since they're actually 
     // available for use once the overlay is applied
-    for package in parsed_fragments.packages {
-        fragments.packages.insert(package);
+    // A selected profile narrows the packages to that dev shell's own, if the template has one
+    let packages = match &fragments.profile {
+        Some(profile) => match parsed_fragments.shell_packages.remove(profile) {
+            Some(packages) => packages,
+            None => {
+                fragments.warnings.push(format!(
+                    "{} has no '{profile}' dev shell; using all of its packages",
+                    template.name
+                ));
+                parsed_fragments.packages
+            }
+        },
+        None => parsed_fragments.packages,
+    };
+    for package in packages {
+        if fragments.packages.insert(package.clone()) {
+            fragments.package_order.push(package);
+        }
+    }
+    fragments.inputs_from.extend(parsed_fragments.inputs_from);
+
+    // Merge extra mkShell attributes: lists are unioned and compiler/linker flags joined
+    let mut shell_attrs: Vec<_> = parsed_fragments.shell_attrs.into_iter().collect();
+    shell_attrs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (name, value) in shell_attrs {
+        let Some(existing) = fragments.shell_attrs.get_mut(&name) else {
+            fragments.shell_attrs.insert(name, value);
+            continue;
+        };
+        match (existing, value) {
+            (NixExpr::List(items), NixExpr::List(new_items)) => {
+                for item in new_items {
+                    if !items.contains(&item) {
+                        items.push(item);
+                    }
+                }
+            }
+            (NixExpr::String(flags), NixExpr::String(new_flags))
+                if name.ends_with("FLAGS_COMPILE") || name.ends_with("LDFLAGS") =>
+            {
+                for flag in new_flags.split_whitespace() {
+                    if !flags.split_whitespace().any(|f| f == flag) {
+                        flags.push(' ');
+                        flags.push_str(flag);
+                    }
+                }
+            }
+            (existing, value) if *existing != value => {
+                fragments.warnings.push(format!(
+                    "mkShell attribute '{name}' from {} conflicts with an earlier template; keeping the earlier value",
+                    template.name
+                ));
+            }
+            _ => {}
+        }
     }
 
     // Merge environment variables
@@ -77,9 +921,11 @@ fn parse_template_with_nix_parser(content: &str, fragments: &mut FlakeFragments)
         fragments.env_vars.insert(key, value);
     }
 
-    // Merge shell hooks (convert Vec to HashSet)
+    // Merge shell hooks, running a hook shared by several templates once
     for hook in parsed_fragments.shell_hooks {
-        fragments.shell_hooks.insert(hook);
+        if !fragments.shell_hooks.contains(&hook) {
+            fragments.shell_hooks.push(hook);
+        }
     }
 
     // Set allow_unfree if any template requires it
@@ -87,9 +933,30 @@ fn parse_template_with_nix_parser(content: &str, fragments: &mut FlakeFragments)
         fragments.allow_unfree = true;
     }
 
-    // Merge let bindings
+    // Merge let bindings; a restricted system list must not be widened by a later template
     for (key, value) in parsed_fragments.let_bindings {
-        fragments.let_bindings.insert(key, value);
+        match fragments.let_bindings.get(&key) {
+            Some(existing) if key == "supportedSystems" => {
+                let merged = intersect_systems(existing, &value);
+                fragments.let_bindings.insert(key, merged);
+            }
+            _ => {
+                fragments.let_bindings.insert(key, value);
+            }
+        }
+    }
+
+    // Merge apps and package outputs, namespacing conflicting names with the template name
+    for (name, app) in parsed_fragments.apps {
+        insert_namespaced(&mut fragments.apps, &template.name, name, app);
+    }
+    for (name, package) in parsed_fragments.package_outputs {
+        insert_namespaced(&mut fragments.package_outputs, &template.name, name, package);
+    }
+
+    // Carry unrecognized top-level attributes through verbatim
+    for (name, value) in parsed_fragments.passthrough {
+        fragments.passthrough.insert(name, value.to_nix_string());
     }
 
     Ok(())
@@ -97,22 +964,125 @@ fn parse_template_with_nix_parser(content: &str, fragments: &mut FlakeFragments)
 
 
 
-fn generate_merged_flake(fragments: &FlakeFragments) -> Result<String> {
+/// Insert `value` under `name`, or under `{template_name}-{name}` if a different value already has it
+fn insert_namespaced(map: &mut HashMap<String, String>, template_name: &str, name: String, value: String) {
+    match map.get(&name) {
+        Some(existing) if *existing != value => {
+            map.insert(format!("{template_name}-{name}"), value);
+        }
+        _ => {
+            map.insert(name, value);
+        }
+    }
+}
+
+fn extract_fragments(content: &str, cache: Option<&FragmentCache>) -> Result<nix_parser::FlakeFragments> {
+    match cache {
+        Some(cache) => cache.extract(content),
+        None => Ok(nix_parser::extract_flake_fragments_lenient(content)
+            .map_err(|e| GeneratorError::Parse(e.to_string()))?),
+    }
+}
+
+/// Print what extraction recognized in a template, for `--verbose`
+fn trace_fragments(template_name: &str, fragments: &nix_parser::FlakeFragments) {
+    for line in fragment_summary(fragments) {
+        eprintln!("[{template_name}] {line}");
+    }
+}
+
+/// One line per kind of section extraction recognized, e.g. `packages: go, gopls`
+pub fn fragment_summary(fragments: &nix_parser::FlakeFragments) -> Vec<String> {
+    let sorted = |names: Vec<&String>| {
+        let mut names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+        names.sort_unstable();
+        names.join(", ")
+    };
+
+    let mut lines = vec![format!("inputs: {}", sorted(fragments.inputs.keys().collect()))];
+    let mut overlays: Vec<_> = fragments.overlays.iter().collect();
+    overlays.sort_by_key(|(name, _)| *name);
+    for (name, bindings) in overlays {
+        let attrs: Vec<String> = bindings.iter().flat_map(overlay_attr_names).collect();
+        lines.push(format!("overlay {name}: {}", sorted(attrs.iter().collect())));
+    }
+    lines.push(format!("packages: {}", fragments.packages.join(", ")));
+    lines.push(format!("env vars: {}", sorted(fragments.env_vars.keys().collect())));
+    if !fragments.inputs_from.is_empty() {
+        lines.push(format!("inputsFrom: {}", fragments.inputs_from.join(", ")));
+    }
+    if !fragments.shell_attrs.is_empty() {
+        lines.push(format!("mkShell attributes: {}", sorted(fragments.shell_attrs.keys().collect())));
+    }
+    lines.push(format!("shell hooks: {}", fragments.shell_hooks.len()));
+    if fragments.allow_unfree {
+        lines.push("allowUnfree: true".to_string());
+    }
+    lines
+}
+
+/// The system `--minimal` builds its shell for: the first one `supportedSystems` lists
+fn minimal_system(supported_systems: &str) -> Result<String> {
+    if let Ok(NixExpr::List(systems)) = nix_parser::parse_nix_expr(supported_systems) {
+        if let Some(NixExpr::String(system)) = systems.into_iter().next() {
+            return Ok(system);
+        }
+    }
+    Err(anyhow!(
+        "--minimal needs a single system, but supportedSystems is '{}'; pass one with --systems",
+        supported_systems
+    ))
+}
+
+/// Render the merged flake; with `minimal_system`, only that system's dev shell is emitted
+fn generate_merged_flake(
+    fragments: &FlakeFragments,
+    minimal_system: Option<&str>,
+    explain: bool,
+    sort: SortOrder,
+) -> Result<String> {
+    // Explanatory comment placed on its own line before a section, when --explain is set
+    let note = |indent: &str, text: &str| {
+        if explain {
+            format!("{indent}# {text}\n")
+        } else {
+            String::new()
+        }
+    };
+
     let mut inputs_fragment = String::new();
 
     // Generate inputs from extracted data
-    let mut sorted_inputs: Vec<_> = fragments.inputs.iter().collect();
-    sorted_inputs.sort_by_key(|(name, _)| *name);
+    let mut sorted_inputs: Vec<_> = fragments.inputs.iter().map(|(name, url)| (name, Ok(url))).collect();
+    sorted_inputs.extend(fragments.follows_only_inputs().into_iter().map(|(name, target)| (name, Err(target))));
+    sorted_inputs.sort_by(|(a, _), (b, _)| sort.compare_inputs(a, b, fragments));
     
-    for (key, url) in sorted_inputs {
-        if key.contains("overlay") {
-            // Handle overlay inputs with follows pattern
+    for (key, source) in &sorted_inputs {
+        let url = match source {
+            Ok(url) => url,
+            // Inputs that are only another input, e.g. `nixpkgs-lib.follows = "nixpkgs";`
+            Err(target) => {
+                inputs_fragment.push_str(&format!("    {key}.follows = \"{target}\";\n"));
+                continue;
+            }
+        };
+        let nested: Vec<_> = fragments
+            .input_follows
+            .get(*key)
+            .into_iter()
+            .flatten()
+            .filter(|f| !f.path.is_empty())
+            .collect();
+        if !nested.is_empty() {
+            // Inputs with follows overrides use the structured form
+            let mut follows: Vec<_> = nested
+                .iter()
+                .map(|f| format!("      inputs.{}.follows = \"{}\";\n", f.path.join(".inputs."), f.target))
+                .collect();
+            follows.sort();
             inputs_fragment.push_str(&format!(
-                r#"    {key} = {{
-      url = "{url}";
-      inputs.nixpkgs.follows = "nixpkgs";
-    }};
-"#
+                "    {key} = {{\n      url = \"{url}\";\n{}    }};\n",
+                follows.concat()
             ));
         } else {
             // Simple URL inputs
@@ -125,71 +1095,108 @@ fn generate_merged_flake(fragments: &FlakeFragments) -> Result<String> {
 
     let mut overlays_fragment = String::new();
     if !fragments.overlays.is_empty() {
-        overlays_fragment.push_str("      overlays.default = final: prev: rec {\n");
+        overlays_fragment.push_str(&note(
+            "      ",
+            "overlays.default: customizes packages in nixpkgs before they are added to the shell",
+        ));
+        let rec_keyword = if fragments.recursive_overlay { "rec " } else { "" };
+        overlays_fragment.push_str(&format!("      overlays.default = final: prev: {rec_keyword}{{\n"));
 
         // Generate overlay content from AST bindings
         let mut sorted_overlays: Vec<_> = fragments.overlays.iter().collect();
         sorted_overlays.sort_by_key(|(name, _)| *name);
         
-        for (_, bindings) in sorted_overlays {
-            for binding in bindings {
-                overlays_fragment.push_str(&format!("        {} = {};\n", 
-                    format_attr_path(&binding.path), 
-                    binding.value.to_nix_string()));
+        let bindings: Vec<&Binding> = sorted_overlays.into_iter().flat_map(|(_, bindings)| bindings).collect();
+        for binding in bindings {
+            if let NixExpr::Inherit { .. } = binding.value {
+                overlays_fragment.push_str(&format!("        {};\n", binding.value.to_nix_string()));
+                continue;
             }
+            overlays_fragment.push_str(&format!("        {} = {};\n", 
+                format_attr_path(&binding.path), 
+                binding.value.to_nix_string()));
         }
 
         overlays_fragment.push_str("      };\n");
     }
 
+    // The mkShell attributes sit deeper inside forEachSupportedSystem than in a minimal flake
+    let attr_indent = if minimal_system.is_some() { "        " } else { "            " };
+    let item_indent = format!("{attr_indent}  ");
+
     let mut packages_fragment = String::new();
     let mut sorted_packages: Vec<_> = fragments.packages.iter().collect();
-    sorted_packages.sort();
+    sorted_packages.sort_by(|a, b| sort.compare_packages(a, b, &fragments.package_order));
     for package in sorted_packages {
-        packages_fragment.push_str(&format!("              {package}\n"));
+        packages_fragment.push_str(&format!("{item_indent}{package}\n"));
     }
 
+    let inputs_from_fragment = if !fragments.inputs_from.is_empty() {
+        let mut inputs_from: Vec<_> = fragments.inputs_from.iter().collect();
+        inputs_from.sort();
+        let entries: String = inputs_from
+            .into_iter()
+            .map(|entry| format!("{item_indent}{entry}\n"))
+            .collect();
+        format!("\n{attr_indent}inputsFrom = with pkgs; [\n{entries}{attr_indent}];")
+    } else {
+        String::new()
+    };
+
+    let mut shell_attrs: Vec<_> = fragments.shell_attrs.iter().collect();
+    shell_attrs.sort_by_key(|(name, _)| *name);
+    let shell_attrs_fragment: String = shell_attrs
+        .into_iter()
+        .map(|(name, value)| format!("\n{attr_indent}{name} = {};", value.to_nix_string()))
+        .collect();
+
     let env_fragment = if !fragments.env_vars.is_empty() {
-        let mut env_content = String::from("\n            env = {\n");
-        for (key, value) in &fragments.env_vars {
-            env_content.push_str(&format!("              {key} = {value};\n"));
+        let mut env_content = format!("\n{attr_indent}env = {{\n");
+        let mut env_vars: Vec<_> = fragments.env_vars.iter().collect();
+        env_vars.sort_by_key(|(name, _)| *name);
+        for (key, value) in env_vars {
+            env_content.push_str(&format!("{item_indent}{key} = {value};\n"));
         }
-        env_content.push_str("            };");
+        env_content.push_str(&format!("{attr_indent}}};"));
         env_content
     } else {
         String::new()
     };
 
-    let shell_hook_fragment = if !fragments.shell_hooks.is_empty() {
-        let mut hook_content = String::new();
-        for hook in &fragments.shell_hooks {
-            if hook.as_str() == "python-venv" {
-                hook_content.push_str(
-                    r#"
-            shellHook = ''
-              # Create virtual environment if it doesn't exist
-              if [ ! -d ".venv" ]; then
-                python -m venv .venv
-              fi
-              
-              # Activate virtual environment
-              source .venv/bin/activate
-              
-              # Upgrade pip in virtual environment
-              pip install --upgrade pip
-            '';"#,
-                );
+    let mut hook_content = String::new();
+    for hook in &fragments.shell_hooks {
+        // Parsing already stripped the template's indentation, so indent the hook for this shell
+        let lines: Vec<&str> = hook.lines().skip_while(|line| line.trim().is_empty()).collect();
+        let lines = &lines[..lines.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |last| last + 1)];
+        for line in lines {
+            hook_content.push('\n');
+            if !line.trim().is_empty() {
+                hook_content.push_str(&item_indent);
+                hook_content.push_str(line);
+            }
+        }
+    }
+    // User-provided hooks run after the template hooks
+    if let Some(user_hook) = &fragments.user_shell_hook {
+        for line in escape_indented_string(user_hook).lines() {
+            hook_content.push('\n');
+            if !line.is_empty() {
+                hook_content.push_str(&item_indent);
+                hook_content.push_str(line);
             }
         }
-        hook_content
+    }
+    let shell_hook_fragment = if !hook_content.is_empty() {
+        format!("\n{attr_indent}shellHook = ''{hook_content}\n{attr_indent}'';")
     } else {
         String::new()
     };
 
-    let input_names = fragments
-        .inputs
-        .keys()
-        .filter(|k| *k != "nixpkgs")
+    // Outputs parameters in the same order as the inputs they bind
+    let input_names = sorted_inputs
+        .iter()
+        .map(|(name, _)| name)
+        .filter(|k| **k != "nixpkgs")
         .map(|k| format!("\n      {k},"))
         .collect::<String>();
 
@@ -207,20 +1214,59 @@ fn generate_merged_flake(fragments: &FlakeFragments) -> Result<String> {
         String::new()
     };
 
-    let flake = format!(
-        r#"{{
-  description = "{}";
+    let mut passthrough_fragment = String::new();
+    let mut sorted_passthrough: Vec<_> = fragments.passthrough.iter().collect();
+    sorted_passthrough.sort_by_key(|(name, _)| *name);
 
-  inputs = {{
-{}  }};
+    for (name, value) in sorted_passthrough {
+        passthrough_fragment.push_str(&format!("  {name} = {value};\n\n"));
+    }
 
-  outputs =
-    {{
-      self,
-      nixpkgs,{}
-    }}:
-    let
-{}      forEachSupportedSystem =
+    let extra_outputs_fragment = format!(
+        "{}{}{}",
+        per_system_output("apps", &fragments.apps),
+        per_system_output("packages", &fragments.package_outputs),
+        per_system_output("checks", &fragments.checks)
+    );
+
+    let shell_fragment = format!(
+        "{attr_indent}packages = with pkgs; [\n{packages_fragment}{attr_indent}];{inputs_from_fragment}{shell_attrs_fragment}{env_fragment}{shell_hook_fragment}"
+    );
+
+    let (pkgs_fragment, outputs_fragment) = match minimal_system {
+        Some(system) => {
+            // Systems such as x86_64-linux are valid identifiers, anything else is quoted
+            let is_identifier = system.starts_with(|c: char| c.is_ascii_alphabetic())
+                && system.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            let system_part = if is_identifier {
+                AttrPathPart::Identifier(system.to_string())
+            } else {
+                AttrPathPart::String(system.to_string())
+            };
+            let dev_shell = AttrPath {
+                parts: vec![
+                    AttrPathPart::Identifier("devShells".to_string()),
+                    system_part,
+                    AttrPathPart::Identifier("default".to_string()),
+                ],
+            };
+            (
+                format!(
+                    "{}      pkgs = import nixpkgs {{\n        system = {};{}\n      }};\n",
+                    note("      ", "pkgs: nixpkgs for the one system this shell is built for"),
+                    NixExpr::String(system.to_string()).to_nix_string(),
+                    generate_pkgs_config(fragments, "        ")
+                ),
+                format!(
+                    "{}      {} = pkgs.mkShell {{\n{shell_fragment}\n      }};",
+                    note("      ", "devShells.<system>.default: the environment entered with `nix develop`"),
+                    format_attr_path(&dev_shell)
+                ),
+            )
+        }
+        None => (
+            format!(
+                r#"{}      forEachSupportedSystem =
         f:
         nixpkgs.lib.genAttrs supportedSystems (
           system:
@@ -230,92 +1276,1272 @@ fn generate_merged_flake(fragments: &FlakeFragments) -> Result<String> {
             }};
           }}
         );
-    in
-    {{
-{}
-      devShells = forEachSupportedSystem (
+"#,
+                note("      ", "forEachSupportedSystem: builds an attribute set for every system in supportedSystems"),
+                generate_pkgs_config(fragments, "              ")
+            ),
+            format!(
+                r#"{}      devShells = forEachSupportedSystem (
         {{ pkgs }}:
         {{
           default = pkgs.mkShell {{
-            packages = with pkgs; [
-{}            ];{}{}
+{shell_fragment}
           }};
         }}
-      );
+      );{extra_outputs_fragment}"#,
+                note("      ", "devShells.default: the environment entered with `nix develop`")
+            ),
+        ),
+    };
+
+    let flake = format!(
+        r#"{{
+  description = "{}";
+
+{}{}  inputs = {{
+{}  }};
+
+{}  outputs =
+    {{
+      self,
+      nixpkgs,{}
+    }}:
+    let
+{}{}    in
+    {{
+{}
+{}
     }};
 }}
 "#,
         fragments.header,
+        passthrough_fragment,
+        note("  ", "inputs: the flakes this environment depends on; nixpkgs is the main package set"),
         inputs_fragment,
+        note("  ", "outputs: a function from the resolved inputs to everything this flake provides"),
         input_names,
         let_bindings_fragment,
-        generate_pkgs_config(fragments),
+        pkgs_fragment,
         overlays_fragment,
-        packages_fragment,
-        env_fragment,
-        shell_hook_fragment
+        outputs_fragment
     );
 
     Ok(flake)
 }
 
 
-fn merge_overlay_bindings(existing: &mut Vec<Binding>, new_bindings: Vec<Binding>) {
-    let mut existing_paths = HashSet::new();
-    
-    // Track existing binding paths
-    for binding in existing.iter() {
-        existing_paths.insert(format_attr_path(&binding.path));
+/// Keep only the systems listed in both `supportedSystems` values, in the order of `existing`.
+///
+/// Falls back to `existing` if either value is not a plain list of strings or nothing is shared.
+fn intersect_systems(existing: &str, incoming: &str) -> String {
+    let systems = |value: &str| -> Option<Vec<String>> {
+        match nix_parser::parse_nix_expr(value).ok()? {
+            NixExpr::List(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    NixExpr::String(system) => Some(system),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    };
+
+    let (Some(existing_systems), Some(incoming_systems)) = (systems(existing), systems(incoming)) else {
+        return existing.to_string();
+    };
+    let shared: Vec<_> = existing_systems
+        .iter()
+        .filter(|system| incoming_systems.contains(system))
+        .collect();
+    if shared.is_empty() {
+        return existing.to_string();
     }
-    
-    // Add new bindings that don't conflict
+
+    NixExpr::List(shared.into_iter().cloned().map(NixExpr::String).collect()).to_nix_string()
+}
+
+/// Render `name = forEachSupportedSystem (...)` for per-system outputs such as apps, or nothing if empty
+fn per_system_output(name: &str, entries: &HashMap<String, String>) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+
+    let mut content = format!("\n      {name} = forEachSupportedSystem (\n        {{ pkgs }}:\n        {{\n");
+    let mut sorted_entries: Vec<_> = entries.iter().collect();
+    sorted_entries.sort_by_key(|(name, _)| *name);
+
+    for (entry_name, value) in sorted_entries {
+        content.push_str(&format!("          {entry_name} = {value};\n"));
+    }
+    content.push_str("        }\n      );");
+    content
+}
+
+/// Add `new_bindings` to `existing`, keeping the existing binding when both define an attribute.
+///
+/// Returns the attributes that were defined differently and so had a definition dropped.
+fn merge_overlay_bindings(existing: &mut Vec<Binding>, new_bindings: Vec<Binding>) -> Vec<String> {
+    let mut conflicts = Vec::new();
     for binding in new_bindings {
-        let path_str = format_attr_path(&binding.path);
-        if !existing_paths.contains(&path_str) {
-            existing_paths.insert(path_str);
-            existing.push(binding);
+        // `inherit` bindings all share a synthetic path, so they are merged by the names they bring in:
+        // a name inherited from the same place is already there, anything else providing it conflicts
+        if let NixExpr::Inherit { from, attrs } = &binding.value {
+            let mut fresh = Vec::new();
+            for attr in attrs {
+                match existing.iter().find(|e| overlay_attr_names(e).contains(attr)) {
+                    Some(Binding { value: NixExpr::Inherit { from: kept_from, .. }, .. }) if kept_from == from => {}
+                    Some(_) => conflicts.push(attr.clone()),
+                    None => fresh.push(attr.clone()),
+                }
+            }
+            if !fresh.is_empty() {
+                existing.push(Binding {
+                    path: binding.path.clone(),
+                    value: NixExpr::Inherit { from: from.clone(), attrs: fresh },
+                });
+            }
+            continue;
+        }
+
+        let name = format_attr_path(&binding.path);
+        match existing.iter().find(|e| overlay_attr_names(e).contains(&name)) {
+            Some(kept) if kept.value != binding.value => conflicts.push(name),
+            Some(_) => {}
+            None => existing.push(binding),
         }
-        // If there's a conflict, we keep the existing binding (first one wins)
     }
+    conflicts
 }
 
-fn format_attr_path(path: &AttrPath) -> String {
-    path.parts.iter()
-        .map(|part| match part {
-            AttrPathPart::Identifier(id) => id.clone(),
-            AttrPathPart::String(s) => format!("\"{s}\""),
-            AttrPathPart::Interpolation(expr) => format!("${{{}}}", expr.to_nix_string()),
+/// Attributes an overlay binding defines: each name of an `inherit`, or the binding's own path
+fn overlay_attr_names(binding: &Binding) -> Vec<String> {
+    match &binding.value {
+        NixExpr::Inherit { attrs, .. } => attrs.clone(),
+        _ => vec![format_attr_path(&binding.path)],
+    }
+}
+
+/// Whether an overlay's attributes refer to each other by name, ignoring names in `outer`, which
+/// the enclosing scope binds
+fn overlay_self_references<V>(bindings: &[Binding], outer: &HashMap<String, V>) -> bool {
+    let names: HashSet<String> = bindings
+        .iter()
+        .filter_map(|binding| match binding.path.parts.first() {
+            Some(AttrPathPart::Identifier(name)) if name != "inherit" && !outer.contains_key(name) => {
+                Some(name.clone())
+            }
+            _ => None,
         })
-        .collect::<Vec<_>>()
-        .join(".")
+        .collect();
+
+    bindings
+        .iter()
+        .any(|binding| expr_references_any(&binding.value, &names))
 }
 
+fn expr_references_any(expr: &NixExpr, names: &HashSet<String>) -> bool {
+    let any = |exprs: &[&NixExpr]| exprs.iter().any(|e| expr_references_any(e, names));
+    match expr {
+        NixExpr::Identifier(name) => names.contains(name),
+        NixExpr::InterpolatedString(parts) => parts.iter().any(|part| match part {
+            StringPart::Interpolation(e) => expr_references_any(e, names),
+            StringPart::Literal(_) => false,
+        }),
+        NixExpr::AttrSet { bindings, .. } | NixExpr::LetIn { bindings, .. } => {
+            let in_bindings = bindings
+                .iter()
+                .any(|binding| expr_references_any(&binding.value, names));
+            let in_body = match expr {
+                NixExpr::LetIn { body, .. } => expr_references_any(body, names),
+                _ => false,
+            };
+            in_bindings || in_body
+        }
+        NixExpr::List(items) => items.iter().any(|item| expr_references_any(item, names)),
+        NixExpr::Lambda { param, body } => {
+            let in_defaults = match param {
+                LambdaParam::Pattern { params, .. } => params
+                    .iter()
+                    .filter_map(|p| p.default.as_deref())
+                    .any(|d| expr_references_any(d, names)),
+                LambdaParam::Identifier(_) => false,
+            };
+            in_defaults || expr_references_any(body, names)
+        }
+        NixExpr::FunctionCall { function, argument } => any(&[function, argument]),
+        NixExpr::With { env, body } => any(&[env, body]),
+        NixExpr::If { condition, then_expr, else_expr } => any(&[condition, then_expr, else_expr]),
+        NixExpr::Assert { condition, body } => any(&[condition, body]),
+        NixExpr::BinaryOp { left, right, .. } => any(&[left, right]),
+        NixExpr::UnaryOp { expr, .. } | NixExpr::HasAttr { expr, .. } => {
+            expr_references_any(expr, names)
+        }
+        NixExpr::Select { expr, default, .. } => {
+            expr_references_any(expr, names)
+                || default.as_deref().is_some_and(|d| expr_references_any(d, names))
+        }
+        NixExpr::Inherit { from, attrs } => match from {
+            Some(from) => expr_references_any(from, names),
+            // A plain `inherit x;` reads `x` from the enclosing scope
+            None => attrs.iter().any(|attr| names.contains(attr)),
+        },
+        _ => false,
+    }
+}
 
-fn generate_pkgs_config(fragments: &FlakeFragments) -> String {
-    // Generate overlay references from inputs dynamically
-    let overlay_refs: Vec<String> = fragments.inputs.keys()
-        .filter(|key| key.contains("overlay"))
-        .map(|key| format!("\n                {key}.overlays.default"))
-        .collect();
-    
-    let overlay_refs_str = overlay_refs.join("");
+fn format_attr_path(path: &AttrPath) -> String {
+    path.to_nix_string()
+}
 
+
+/// The `config` and `overlays` arguments to `import nixpkgs`, at `indent`
+fn generate_pkgs_config(fragments: &FlakeFragments, indent: &str) -> String {
+    // Apply the overlays the templates took from their inputs, then the merged overlay
+    let mut overlay_refs: String = fragments
+        .overlay_inputs()
+        .into_iter()
+        .flat_map(|input| {
+            fragments.input_overlays[input]
+                .iter()
+                .map(move |name| format!("\n{indent}  {input}.overlays.{name}"))
+        })
+        .collect();
     if !fragments.overlays.is_empty() {
-        if fragments.allow_unfree {
-            format!(
-                "\n              config.allowUnfree = true;
-              overlays = [{overlay_refs_str}
-                self.overlays.default
-              ];")
-        } else {
-            format!(
-                "\n              overlays = [{overlay_refs_str}
-                self.overlays.default
-              ];")
+        overlay_refs.push_str(&format!("\n{indent}  self.overlays.default"));
+    }
+
+    let mut config = String::new();
+    if fragments.allow_unfree {
+        config.push_str(&format!("\n{indent}config.allowUnfree = true;"));
+    }
+    if !overlay_refs.is_empty() {
+        config.push_str(&format!("\n{indent}overlays = [{overlay_refs}\n{indent}];"));
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(name: &str, flake_content: &str) -> Template {
+        Template {
+            name: name.to_string(),
+            description: format!("{name} environment"),
+            flake_content: flake_content.to_string(),
+            additional_files: HashMap::new(),
+            placeholders: Vec::new(),
         }
-    } else if fragments.allow_unfree {
-        "\n              config.allowUnfree = true;".to_string()
-    } else {
-        String::new()
+    }
+
+    fn embedded(name: &str) -> Template {
+        template(name, crate::embedded_templates::EMBEDDED_TEMPLATES[name].content)
+    }
+
+    #[test]
+    fn test_parse_package_pin() {
+        let binding = |spec: &str| {
+            let pin = parse_package_pin(spec).unwrap();
+            format!("{} = {};", format_attr_path(&pin.to_binding().path), pin.to_binding().value.to_nix_string())
+        };
+        assert_eq!(binding("go=1.22"), "go = final.go_1_22;");
+        assert_eq!(binding("nodejs=22"), "nodejs = final.nodejs_22;");
+        assert_eq!(binding("python3=attr:python312"), "python3 = final.python312;");
+
+        // A bare name is neither a version nor an attribute
+        let err = parse_package_pin("python3=python312").unwrap_err();
+        assert!(err.to_string().contains("attr:<attribute>"), "{err}");
+        assert!(parse_package_pin("python3=attr:").is_err());
+        assert!(parse_package_pin("python3=attr:3.12").is_err());
+        assert!(parse_package_pin("nodejs").is_err());
+    }
+    #[test]
+    fn test_merge_adds_missing_nixpkgs_input() {
+        let no_inputs = template(
+            "empty-inputs",
+            r#"{
+  description = "No inputs";
+  inputs = { };
+  outputs = { self, nixpkgs }: {
+    devShells = { default = pkgs.mkShell { packages = with pkgs; [ hello ]; }; };
+  };
+}"#,
+        );
+        let no_inputs_either = template(
+            "no-inputs",
+            r#"{
+  description = "No inputs at all";
+  outputs = { self, nixpkgs }: {
+    devShells = { default = pkgs.mkShell { packages = with pkgs; [ jq ]; }; };
+  };
+}"#,
+        );
+
+        let merged = generate_flake(&[no_inputs, no_inputs_either], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains(&format!("nixpkgs.url = \"{DEFAULT_NIXPKGS_URL}\";")));
+        assert!(merged.contains("hello"));
+        assert!(merged.contains("jq"));
+    }
+
+    const APP_TEMPLATE: &str = r#"{
+  description = "Template with an app";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      supportedSystems = [ "x86_64-linux" ];
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs supportedSystems (system: f { pkgs = import nixpkgs { inherit system; }; });
+    in
+    {
+      apps = forEachSupportedSystem ({ pkgs }: {
+        default = {
+          type = "app";
+          program = "${pkgs.hello}/bin/hello";
+        };
+      });
+
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell { packages = with pkgs; [ hello ]; };
+      });
+    };
+}"#;
+
+    #[test]
+    fn test_merge_keeps_apps() {
+        let go = embedded("go");
+        let merged = generate_flake(&[template("hello", APP_TEMPLATE), go], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains("apps = forEachSupportedSystem ("));
+        assert!(merged.contains("default = {"));
+        assert!(merged.contains(r#"program = "${pkgs.hello}/bin/hello";"#));
+        nix_parser::parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_merge_namespaces_conflicting_apps() {
+        let other = APP_TEMPLATE.replace("pkgs.hello}/bin/hello", "pkgs.cowsay}/bin/cowsay");
+        let merged = generate_flake(
+            &[template("hello", APP_TEMPLATE), template("cowsay", &other)],
+            &GenerateOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        assert!(merged.contains("          default = {"));
+        assert!(merged.contains("          cowsay-default = {"));
+        assert!(merged.contains("/bin/cowsay"));
+    }
+
+    #[test]
+    fn test_merge_flattens_concat_lists_packages() {
+        let concat = template(
+            "concat",
+            r#"{
+  description = "concatLists packages";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: {
+    devShells = forEachSupportedSystem ({ pkgs }: {
+      default = pkgs.mkShell {
+        packages = with pkgs; builtins.concatLists [ [ ripgrep fd ] [ jq ] ];
+      };
+    });
+  };
+}"#,
+        );
+        let merged = generate_flake(&[concat, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+
+        for package in ["ripgrep", "fd", "jq", "hello"] {
+            assert!(merged.contains(&format!("              {package}\n")), "missing {package}");
+        }
+        assert!(!merged.contains("concatLists"));
+    }
+
+    #[test]
+    fn test_merge_preserves_custom_top_level_attributes() {
+        let custom = APP_TEMPLATE.replace(
+            "  inputs.nixpkgs.url",
+            "  meta = { license = \"MIT\"; maintainers = [ \"alice\" ]; };\n  inputs.nixpkgs.url",
+        );
+        let go = embedded("go");
+        let merged = generate_flake(&[template("custom", &custom), go], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains("meta = {"));
+        assert!(merged.contains(r#"license = "MIT";"#));
+        assert!(merged.contains(r#""alice""#));
+        nix_parser::parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_merge_keeps_negative_and_interpolated_let_bindings() {
+        let custom = APP_TEMPLATE.replace(
+            "      supportedSystems =",
+            "      threshold = -1;\n      label = \"pre-${threshold}\";\n      supportedSystems =",
+        );
+        let go = embedded("go");
+        let merged = generate_flake(&[template("custom", &custom), go], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains("threshold = -1;"));
+        assert!(merged.contains(r#"label = "pre-${threshold}";"#));
+        nix_parser::parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_merge_honors_restricted_supported_systems() {
+        let restricted = template("linux-only", APP_TEMPLATE);
+        let go = embedded("go");
+
+        for templates in [[restricted.clone(), go.clone()], [go, restricted]] {
+            let merged = generate_flake(&templates, &GenerateOptions::default(), None).unwrap();
+            assert!(merged.contains(r#"supportedSystems = [ "x86_64-linux" ];"#));
+            assert!(!merged.contains("aarch64-darwin"));
+        }
+    }
+
+    #[test]
+    fn test_merge_defines_default_supported_systems() {
+        let without_systems = APP_TEMPLATE.replace("      supportedSystems = [ \"x86_64-linux\" ];\n", "");
+        let merged = generate_flake(
+            &[template("a", &without_systems), template("b", &without_systems.replace("hello", "cowsay"))],
+            &GenerateOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        assert!(merged.contains("supportedSystems = [ \"x86_64-linux\" \"aarch64-linux\""));
+    }
+
+    const PACKAGE_TEMPLATE: &str = r#"{
+  description = "Template with a package build";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      supportedSystems = [ "x86_64-linux" ];
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs supportedSystems (system: f { pkgs = import nixpkgs { inherit system; }; });
+    in
+    {
+      packages = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.writeShellScriptBin "hello-tool" "echo hello";
+      });
+
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell { packages = with pkgs; [ hello ]; };
+      });
+    };
+}"#;
+
+    #[test]
+    fn test_merge_keeps_package_outputs_without_clobbering() {
+        let other = PACKAGE_TEMPLATE.replace("hello-tool", "other-tool");
+        let merged = generate_flake(
+            &[template("hello", PACKAGE_TEMPLATE), template("other", &other)],
+            &GenerateOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        assert!(merged.contains("packages = forEachSupportedSystem ("));
+        assert!(merged.contains(r#"default = pkgs.writeShellScriptBin "hello-tool" "echo hello";"#));
+        assert!(merged.contains(r#"other-default = pkgs.writeShellScriptBin "other-tool" "echo hello";"#));
+        nix_parser::parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_generate_flake_minimal_drops_extra_outputs() {
+        let templates = [template("hello", APP_TEMPLATE), template("tool", PACKAGE_TEMPLATE)];
+
+        let full = generate_flake(&templates, &GenerateOptions::default(), None).unwrap();
+        assert!(full.contains("apps = forEachSupportedSystem ("));
+        assert!(full.contains("packages = forEachSupportedSystem ("));
+
+        let options = GenerateOptions { minimal: true, ..GenerateOptions::default() };
+        let minimal = generate_flake(&templates, &options, None).unwrap();
+        assert!(!minimal.contains("apps = forEachSupportedSystem ("));
+        assert!(!minimal.contains("packages = forEachSupportedSystem ("));
+        assert!(!minimal.contains("forEachSupportedSystem"), "{minimal}");
+        assert!(minimal.contains("devShells.x86_64-linux.default = pkgs.mkShell {"), "{minimal}");
+        nix_parser::parse_nix_expr(&minimal).expect("minimal flake should parse");
+    }
+
+    #[test]
+    fn test_generate_flake_overrides_systems_and_unfree() {
+        let go = embedded("go");
+        let options = GenerateOptions {
+            systems: vec!["aarch64-darwin".to_string()],
+            allow_unfree: Some(true),
+            ..GenerateOptions::default()
+        };
+        let flake = generate_flake(std::slice::from_ref(&go), &options, None).unwrap();
+
+        assert!(flake.contains(r#"supportedSystems = [ "aarch64-darwin" ];"#));
+        assert!(!flake.contains("x86_64-linux"));
+        assert!(flake.contains("allowUnfree = true"));
+
+        // Without overrides a single template is passed through untouched
+        let verbatim = generate_flake(&[go], &GenerateOptions::default(), None).unwrap();
+        assert!(verbatim.contains("x86_64-linux"));
+        assert!(!verbatim.contains("allowUnfree"));
+    }
+
+    #[test]
+    fn test_self_check_rejects_unrendered_nodes() {
+        let unhandled = NixExpr::Assert {
+            condition: Box::new(NixExpr::Bool(true)),
+            body: Box::new(NixExpr::Null),
+        };
+        let flake = format!("{{\n  description = \"broken\";\n  value = {};\n}}\n", unhandled.to_nix_string());
+
+        assert!(self_check(&flake).is_err());
+        assert!(self_check("{\n  description = \"ok\";\n}\n").is_ok());
+        assert!(self_check("{\n  description = \"ok\";\n}\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_outputs_params_match_referenced_inputs() {
+        let overlay_user = template(
+            "overlay-user",
+            r#"{
+  description = "References an input from a let binding";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  inputs.rust-overlay.url = "github:oxalica/rust-overlay";
+  inputs.unused-tool.url = "github:example/unused-tool";
+  outputs = { self, nixpkgs, rust-overlay, unused-tool }:
+    let
+      overlaySource = rust-overlay;
+    in
+    {
+      devShells = { default = pkgs.mkShell { packages = with pkgs; [ cargo ]; }; };
+    };
+}"#,
+        );
+        let templates = [overlay_user, template("hello", APP_TEMPLATE)];
+        let merged = generate_flake(&templates, &GenerateOptions::default(), None).unwrap();
+
+        let expr = parse_nix_expr(&merged).unwrap();
+        let (params, body) = outputs_function(&expr).expect("outputs should be a pattern lambda");
+        let free = free_identifiers(body);
+        let referenced: HashSet<&str> = params
+            .iter()
+            .map(|param| param.name.as_str())
+            .filter(|name| free.contains(*name))
+            .collect();
+
+        assert_eq!(referenced, HashSet::from(["nixpkgs", "rust-overlay"]));
+        assert_eq!(unused_outputs_params(&merged), vec!["unused-tool".to_string()]);
+    }
+
+    #[test]
+    fn test_crlf_template_extracts_like_lf() {
+        let lf = r#"{
+  description = "Hook spanning lines";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: {
+    devShells = {
+      default = pkgs.mkShell {
+        packages = with pkgs; [ go ];
+        env = {
+          GREETING = "hello
+world";
+        };
+        shellHook = ''
+          echo ready
+        '';
+      };
+    };
+  };
+}"#;
+        let crlf = lf.replace('\n', "\r\n");
+
+        let lf_fragments = extract_fragments(lf, None).unwrap();
+        let crlf_fragments = extract_fragments(&crlf, None).unwrap();
+        assert_eq!(lf_fragments, crlf_fragments);
+        assert_eq!(crlf_fragments.env_vars["GREETING"], "hello\nworld");
+
+        let lf_merged = generate_flake(&[template("hook", lf), template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+        let crlf_merged = generate_flake(&[template("hook", &crlf), template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+        assert_eq!(lf_merged, crlf_merged);
+        assert!(!crlf_merged.contains('\r'));
+    }
+
+    #[test]
+    fn test_merge_preserves_input_follows() {
+        let follows = template("follows", r#"{
+  description = "Nested follows";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    flake-utils.url = "github:numtide/flake-utils";
+    crane = {
+      url = "github:ipetkov/crane";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+    fenix.url = "github:nix-community/fenix";
+    fenix.inputs.rust-analyzer-src.inputs.flake-utils.follows = "flake-utils";
+  };
+  inputs.fenix.inputs.nixpkgs.follows = "nixpkgs";
+  outputs = { self, nixpkgs, flake-utils, crane, fenix }: {
+    devShells = { default = pkgs.mkShell { packages = with pkgs; [ cargo ]; }; };
+  };
+}"#);
+        let merged = generate_flake(&[follows, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains(
+            "    fenix = {\n      url = \"github:nix-community/fenix\";\n      inputs.nixpkgs.follows = \"nixpkgs\";\n      inputs.rust-analyzer-src.inputs.flake-utils.follows = \"flake-utils\";\n    };"
+        ), "{merged}");
+        assert!(merged.contains("      inputs.nixpkgs.follows = \"nixpkgs\";\n    };"));
+        assert!(merged.contains("    flake-utils.url = \"github:numtide/flake-utils\";"));
+        parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_overlay_sibling_references_make_the_overlay_rec() {
+        let overlay = |recursive: &str, outer: &str| {
+            template(
+                "overlay",
+                &format!(
+                    r#"{{
+  description = "Overlay attrs referring to each other";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = {{ self, nixpkgs }}:
+    let
+      {outer}
+    in
+    {{
+      overlays.default = final: prev: {recursive}{{
+        tool = helper.override {{ withDocs = false; }};
+        helper = prev.hello;
+      }};
+      devShells = {{ default = pkgs.mkShell {{ packages = with pkgs; [ tool ]; }}; }};
+    }};
+}}"#
+                ),
+            )
+        };
+        let merge = |overlay| generate_flake(&[overlay, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+
+        // Nothing but the sibling can bind `helper`, whether or not the overlay says `rec`
+        for recursive in ["rec ", ""] {
+            let merged = merge(overlay(recursive, "unused = 1;"));
+            assert!(merged.contains("overlays.default = final: prev: rec {"), "{merged}");
+            parse_nix_expr(&merged).expect("merged flake should parse");
+        }
+
+        // Without `rec`, the template's own `let` binds `helper`
+        let merged = merge(overlay("", "helper = 1;"));
+        assert!(merged.contains("overlays.default = final: prev: {"), "{merged}");
+    }
+
+    #[test]
+    fn test_conflicting_overlay_attributes_are_reported() {
+        let overlay_template = |name: &str, nodejs: &str| {
+            template(
+                name,
+                &format!(
+                    r#"{{
+  description = "{name}";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = {{ self, nixpkgs }}: {{
+    overlays.default = final: prev: {{ nodejs = prev.{nodejs}; yarn = prev.yarn; }};
+    devShells = {{ default = pkgs.mkShell {{ packages = with pkgs; [ nodejs ]; }}; }};
+  }};
+}}"#
+                ),
+            )
+        };
+
+        let mut fragments = FlakeFragments::default();
+        for template in [overlay_template("node", "nodejs_22"), overlay_template("bun", "nodejs_20")] {
+            parse_template_with_nix_parser(&template, &mut fragments, None, false).unwrap();
+        }
+
+        assert_eq!(
+            fragments.warnings,
+            vec!["overlay attribute 'nodejs' is defined differently by node and bun; keeping node's definition"]
+        );
+        let nodejs = fragments.overlays["default"].iter().find(|b| format_attr_path(&b.path) == "nodejs").unwrap();
+        assert_eq!(nodejs.value.to_nix_string(), "prev.nodejs_22");
+    }
+
+    #[test]
+    fn test_merge_keeps_conditional_let_binding() {
+        let conditional = template(
+            "conditional",
+            r#"{
+  description = "Conditional let binding";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      stable = true;
+      channel = if stable then "stable" else "beta";
+      edition = if channel == "stable" && !stable then 2021 else 2024;
+    in
+    {
+      devShells = { default = pkgs.mkShell { packages = with pkgs; [ cargo ]; }; };
+    };
+}"#,
+        );
+        let merged = generate_flake(&[conditional, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains(r#"      channel = if stable then "stable" else "beta";"#), "{merged}");
+        assert!(merged.contains(r#"      edition = if channel == "stable" && !stable then 2021 else 2024;"#), "{merged}");
+
+        let channel = parse_nix_expr(r#"if stable then "stable" else "beta""#).unwrap();
+        assert_eq!(parse_nix_expr(&channel.to_nix_string()).unwrap(), channel);
+    }
+
+    #[test]
+    fn test_no_overlay_inlines_go_package() {
+        let go = embedded("go");
+        let options = GenerateOptions { no_overlay: true, ..GenerateOptions::default() };
+        let flake = generate_flake(&[template("hello", APP_TEMPLATE), go], &options, None).unwrap();
+        assert!(!flake.contains("overlays.default"), "{flake}");
+        assert!(flake.contains(r#"pkgs."go_1_${toString goVersion}""#), "{flake}");
+        assert!(flake.contains("goVersion = 24;"), "{flake}");
+        parse_nix_expr(&flake).expect("flake without overlay should parse");
+
+        // An input is only an overlay when a template applies it, whatever its name
+        let docs = APP_TEMPLATE.replace(
+            "inputs.nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";",
+            "inputs.nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";\n  inputs.overlay-docs.url = \"github:example/overlay-docs\";",
+        );
+        let flake = generate_flake(&[template("docs", &docs), embedded("go")], &options, None).unwrap();
+        assert!(flake.contains("overlay-docs.url"), "{flake}");
+        assert!(!flake.contains("overlays"), "{flake}");
+    }
+
+    #[test]
+    fn test_merge_unions_inputs_from() {
+        let with_inputs_from = |name: &str, entries: &str| {
+            APP_TEMPLATE.replace(
+                "pkgs.mkShell { packages = with pkgs; [ hello ]; }",
+                &format!("pkgs.mkShell {{ inputsFrom = {entries}; packages = with pkgs; [ {name} ]; }}"),
+            )
+        };
+        let first = template("first", &with_inputs_from("jq", "[ pkgs.hello ]"));
+        let second = template("second", &with_inputs_from("yq", "with pkgs; [ hello ] ++ [ pkgs.hello self.packages.${pkgs.system}.default ]"));
+
+        let merged = generate_flake(&[first, second], &GenerateOptions::default(), None).unwrap();
+        assert!(
+            merged.contains(
+                "inputsFrom = with pkgs; [\n              hello\n              pkgs.hello\n              self.packages.${pkgs.system}.default\n            ];"
+            ),
+            "{merged}"
+        );
+        parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_dedupe_inputs_makes_overlays_follow_one_nixpkgs() {
+        let overlay_template = |name: &str, nixpkgs: &str, overlay: &str, overlay_input: &str| {
+            template(
+                name,
+                &format!(
+                    r#"{{
+  description = "{name}";
+  inputs = {{
+    nixpkgs.url = "{nixpkgs}";
+    {overlay_input}
+  }};
+  outputs = {{ self, nixpkgs, ... }}@inputs:
+    let
+      pkgs = import nixpkgs {{ overlays = [ inputs.{overlay}.overlays.default self.overlays.default ]; }};
+    in
+    {{
+      overlays.default = final: prev: {{ {name}-tool = prev.hello; }};
+      devShells = {{ default = pkgs.mkShell {{ packages = with pkgs; [ {name}-tool ]; }}; }};
+    }};
+}}"#
+                ),
+            )
+        };
+        let templates = [
+            overlay_template(
+                "rust",
+                "github:NixOS/nixpkgs/nixos-unstable",
+                "rust-overlay",
+                r#"rust-overlay = { url = "github:oxalica/rust-overlay"; inputs.nixpkgs.follows = "nixpkgs"; };"#,
+            ),
+            overlay_template("zig", "github:NixOS/nixpkgs/nixos-24.05", "zig-overlay", r#"zig-overlay.url = "github:mitchellh/zig-overlay";"#),
+        ];
+
+        let mut fragments = FlakeFragments::default();
+        for template in &templates {
+            parse_template_with_nix_parser(template, &mut fragments, None, false).unwrap();
+        }
+        dedupe_nixpkgs(&mut fragments);
+        assert_eq!(
+            fragments.warnings,
+            vec!["rust pins nixpkgs to github:NixOS/nixpkgs/nixos-unstable; using github:NixOS/nixpkgs/nixos-24.05 for every input instead"]
+        );
+
+        let options = GenerateOptions { dedupe_inputs: true, quiet: true, ..GenerateOptions::default() };
+        let merged = generate_flake(&templates, &options, None).unwrap();
+        assert_eq!(merged.matches("nixpkgs.url").count(), 1, "{merged}");
+        for input in ["rust-overlay", "zig-overlay"] {
+            assert!(
+                merged.contains(&format!("    {input} = {{\n      url = ")),
+                "{merged}"
+            );
+        }
+        assert_eq!(merged.matches("inputs.nixpkgs.follows = \"nixpkgs\";").count(), 2, "{merged}");
+        assert!(merged.contains("rust-overlay.overlays.default"));
+        assert!(merged.contains("zig-overlay.overlays.default"));
+    }
+
+    #[test]
+    fn test_dedupe_inputs_recognizes_nixpkgs_by_url() {
+        let aliased = template(
+            "aliased",
+            r#"{
+  description = "nixpkgs under other names";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    nixpkgs-unstable.url = "github:NixOS/nixpkgs/nixos-unstable";
+    pkgs.url = "github:nixos/nixpkgs/nixos-24.05";
+    nixpkgs-wayland.url = "github:nix-community/nixpkgs-wayland";
+  };
+  outputs = { self, nixpkgs, nixpkgs-unstable, pkgs, nixpkgs-wayland }: {
+    devShells = { default = pkgs.mkShell { packages = with pkgs; [ hello ]; }; };
+  };
+}"#,
+        );
+        let mut fragments = FlakeFragments::default();
+        parse_template_with_nix_parser(&aliased, &mut fragments, None, false).unwrap();
+        dedupe_nixpkgs(&mut fragments);
+        assert_eq!(
+            fragments.warnings,
+            vec!["input 'pkgs' pins nixpkgs to github:nixos/nixpkgs/nixos-24.05; it now follows the top-level nixpkgs"]
+        );
+
+        let options = GenerateOptions { dedupe_inputs: true, quiet: true, ..GenerateOptions::default() };
+        let merged = generate_flake(&[aliased, template("hello", APP_TEMPLATE)], &options, None).unwrap();
+        assert!(merged.contains("    nixpkgs-unstable.follows = \"nixpkgs\";\n"), "{merged}");
+        assert!(merged.contains("    pkgs.follows = \"nixpkgs\";\n"), "{merged}");
+        assert!(merged.contains("    nixpkgs-wayland.url = \"github:nix-community/nixpkgs-wayland\";\n"), "{merged}");
+        assert_eq!(merged.matches("nixpkgs.url").count(), 1, "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_merge_to_ast_returns_flake_attrset() {
+        let merged = merge_to_ast(&[template("hello", APP_TEMPLATE), template("package", PACKAGE_TEMPLATE)]).unwrap();
+
+        let NixExpr::AttrSet { bindings, .. } = &merged else {
+            panic!("expected an attribute set, got {merged:?}");
+        };
+        let names: Vec<String> = bindings.iter().map(|binding| format_attr_path(&binding.path)).collect();
+        for name in ["description", "inputs", "outputs"] {
+            assert!(names.iter().any(|n| n == name), "missing {name} in {names:?}");
+        }
+        let outputs = bindings.iter().find(|b| format_attr_path(&b.path) == "outputs").unwrap();
+        assert!(matches!(outputs.value, NixExpr::Lambda { .. }));
+        parse_nix_expr(&merged.to_nix_string()).expect("rendered AST should parse");
+    }
+
+    #[test]
+    fn test_merge_keeps_extra_mkshell_attributes() {
+        let shell = |name: &str, attrs: &str| {
+            template(
+                name,
+                &format!(
+                    r#"{{
+  description = "{name}";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = {{ self, nixpkgs }}: {{
+    devShells = {{
+      default = pkgs.mkShell.override {{ }} {{
+        packages = with pkgs; [ {name} ];
+        {attrs}
+      }};
+    }};
+  }};
+}}"#
+                ),
+            )
+        };
+        let merged = generate_flake(
+            &[
+                shell("clang", r#"hardeningDisable = [ "fortify" ]; NIX_CFLAGS_COMPILE = "-O2 -g";"#),
+                shell("gcc", r#"hardeningDisable = [ "all" "fortify" ]; NIX_CFLAGS_COMPILE = "-g -Wall";"#),
+            ],
+            &GenerateOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        assert!(merged.contains(r#"            hardeningDisable = [ "fortify" "all" ];"#), "{merged}");
+        assert!(merged.contains(r#"            NIX_CFLAGS_COMPILE = "-O2 -g -Wall";"#), "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_merge_keeps_follows_only_inputs() {
+        let aliased = template(
+            "aliased",
+            r#"{
+  description = "Follows-only input";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    nixpkgs-lib.follows = "nixpkgs";
+  };
+  inputs.systems.follows = "nixpkgs";
+  outputs = { self, nixpkgs, nixpkgs-lib, systems }: {
+    devShells = { default = pkgs.mkShell { packages = with pkgs; [ hello ]; }; };
+  };
+}"#,
+        );
+        let merged = generate_flake(&[aliased, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains("    nixpkgs-lib.follows = \"nixpkgs\";\n"), "{merged}");
+        assert!(merged.contains("    systems.follows = \"nixpkgs\";\n"), "{merged}");
+        assert!(merged.contains("      nixpkgs-lib,\n"), "{merged}");
+        assert!(!merged.contains("inputs..follows"), "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_merge_takes_build_inputs_from_flat_devshell_paths() {
+        let build_inputs = template(
+            "build-inputs",
+            r#"{
+  description = "buildInputs under a flat devShells path";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      pkgs = nixpkgs.legacyPackages.x86_64-linux;
+    in
+    {
+      devShells.x86_64-linux.default = pkgs.mkShell {
+        buildInputs = with pkgs; [ openssl zlib ] ++ [ pkgs.pkg-config ];
+      };
+    };
+}"#,
+        );
+        let extracted = nix_parser::extract_flake_fragments(&build_inputs.flake_content).unwrap();
+        assert_eq!(extracted.packages, vec!["openssl", "zlib", "pkg-config"]);
+
+        let merged = generate_flake(&[build_inputs, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+        for package in ["openssl", "zlib", "pkg-config"] {
+            assert!(merged.contains(&format!("              {package}\n")), "{merged}");
+        }
+    }
+
+    #[test]
+    fn test_minimal_targets_a_single_system() {
+        let templates = [template("hello", APP_TEMPLATE), template("tool", PACKAGE_TEMPLATE)];
+
+        let options = GenerateOptions {
+            minimal: true,
+            systems: vec!["x86_64-linux".to_string()],
+            ..GenerateOptions::default()
+        };
+        let minimal = generate_flake(&templates, &options, None).unwrap();
+        assert!(!minimal.contains("forEachSupportedSystem"), "{minimal}");
+        assert!(!minimal.contains("supportedSystems"), "{minimal}");
+        assert!(minimal.contains("      pkgs = import nixpkgs {\n        system = \"x86_64-linux\";"), "{minimal}");
+        assert!(minimal.contains("devShells.x86_64-linux.default = pkgs.mkShell {"), "{minimal}");
+        nix_parser::parse_nix_expr(&minimal).expect("minimal flake should parse");
+
+        let options = GenerateOptions {
+            minimal: true,
+            systems: vec!["aarch64-darwin".to_string()],
+            ..GenerateOptions::default()
+        };
+        let minimal = generate_flake(&[embedded("go")], &options, None).unwrap();
+        assert!(minimal.contains("devShells.aarch64-darwin.default = pkgs.mkShell {"), "{minimal}");
+        assert!(!minimal.contains("forEachSupportedSystem"), "{minimal}");
+
+        let options = GenerateOptions {
+            minimal: true,
+            systems: vec!["x86_64-linux".to_string(), "aarch64-darwin".to_string()],
+            ..GenerateOptions::default()
+        };
+        let err = generate_flake(&templates, &options, None).unwrap_err();
+        assert!(err.to_string().contains("--minimal supports a single system"), "{err}");
+    }
+
+    #[test]
+    fn test_merge_references_named_input_overlay() {
+        let named = template(
+            "named-overlay",
+            r#"{
+  description = "Overlay input without overlays.default";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    zig-overlay.url = "github:mitchellh/zig-overlay";
+  };
+  outputs = { self, nixpkgs, zig-overlay }:
+    let
+      pkgs = import nixpkgs {
+        system = "x86_64-linux";
+        overlays = [ zig-overlay.overlays.zig self.overlays.default ];
+      };
+    in
+    {
+      overlays.default = final: prev: { zig = prev.zigpkgs.master; };
+      devShells.x86_64-linux.default = pkgs.mkShell { packages = with pkgs; [ zig ]; };
+    };
+}"#,
+        );
+        let extracted = nix_parser::extract_flake_fragments(&named.flake_content).unwrap();
+        assert_eq!(extracted.input_overlays.get("zig-overlay"), Some(&vec!["zig".to_string()]));
+        assert!(!extracted.input_overlays.contains_key("self"));
+
+        let merged = generate_flake(&[named, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+        assert!(merged.contains("zig-overlay.overlays.zig\n"), "{merged}");
+        assert!(!merged.contains("zig-overlay.overlays.default"), "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_merge_applies_overlays_of_any_input() {
+        let fenix = template(
+            "fenix",
+            r#"{
+  description = "Overlay input without overlay in its name and no overlay of its own";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    fenix.url = "github:nix-community/fenix";
+  };
+  outputs = { self, nixpkgs, fenix }:
+    let
+      pkgs = import nixpkgs {
+        system = "x86_64-linux";
+        overlays = [ fenix.overlays.default ];
+      };
+    in
+    {
+      devShells.x86_64-linux.default = pkgs.mkShell { packages = with pkgs; [ fenix.stable.toolchain ]; };
+    };
+}"#,
+        );
+        let merged = generate_flake(&[fenix, template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains("              overlays = [\n                fenix.overlays.default\n              ];"), "{merged}");
+        assert!(!merged.contains("self.overlays.default"), "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
+    }
+
+    #[test]
+    fn test_sort_orders_inputs() {
+        let overlay = template(
+            "overlay",
+            r#"{
+  description = "Overlay input declared before nixpkgs";
+  inputs = {
+    rust-overlay.url = "github:oxalica/rust-overlay";
+    flake-utils.url = "github:numtide/flake-utils";
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  };
+  outputs = { self, nixpkgs, rust-overlay, flake-utils }:
+    let
+      pkgs = import nixpkgs { overlays = [ rust-overlay.overlays.default ]; };
+    in
+    {
+      devShells = { default = pkgs.mkShell { packages = with pkgs; [ rustc ]; }; };
+    };
+}"#,
+        );
+        let templates = [overlay, template("hello", APP_TEMPLATE)];
+        let input_order = |sort: SortOrder| {
+            let options = GenerateOptions { sort, ..GenerateOptions::default() };
+            let merged = generate_flake(&templates, &options, None).unwrap();
+            let mut inputs = vec!["flake-utils.url", "nixpkgs.url", "rust-overlay.url"];
+            inputs.sort_by_key(|input| merged.find(input).unwrap());
+            inputs
+        };
+
+        assert_eq!(input_order(SortOrder::Alpha), ["flake-utils.url", "nixpkgs.url", "rust-overlay.url"]);
+        assert_eq!(input_order(SortOrder::NixpkgsFirst), ["nixpkgs.url", "rust-overlay.url", "flake-utils.url"]);
+        assert_eq!(input_order(SortOrder::Source), ["rust-overlay.url", "flake-utils.url", "nixpkgs.url"]);
+    }
+
+    #[test]
+    fn test_merged_flake_is_reproducible() {
+        let env_template = template(
+            "env",
+            r#"{
+  description = "Several inputs and env vars";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    alpha.url = "github:example/alpha";
+    beta.url = "github:example/beta";
+    gamma.url = "github:example/gamma";
+    delta.url = "github:example/delta";
+  };
+  outputs = { self, nixpkgs, alpha, beta, gamma, delta }: {
+    devShells = {
+      default = pkgs.mkShell {
+        packages = with pkgs; [ hello ];
+        env = { ZETA = "z"; ALPHA = "a"; MU = "m"; BETA = "b"; OMEGA = "o"; };
+      };
+    };
+  };
+}"#,
+        );
+        let templates = [env_template, embedded("rust"), embedded("go")];
+        let options = GenerateOptions { quiet: true, ..GenerateOptions::default() };
+
+        let first = generate_flake(&templates, &options, None).unwrap();
+        for _ in 0..5 {
+            assert_eq!(generate_flake(&templates, &options, None).unwrap(), first);
+        }
+        let env_order: Vec<_> = ["ALPHA", "BETA", "MU", "OMEGA", "ZETA"].map(|name| first.find(&format!("{name} =")).unwrap()).into();
+        assert!(env_order.is_sorted(), "{first}");
+        assert!(
+            first.contains("      nixpkgs,\n      alpha,\n      beta,\n      delta,\n      gamma,\n      rust-overlay,\n"),
+            "{first}"
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_nix_systems_input() {
+        let nix_systems = |name: &str, package: &str| {
+            template(
+                name,
+                &format!(
+                    r#"{{
+  description = "nix-systems style";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  inputs.systems.url = "github:nix-systems/default";
+  outputs = {{ self, nixpkgs, systems }}:
+    let
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs (import systems) (system: f {{ pkgs = import nixpkgs {{ inherit system; }}; }});
+    in
+    {{
+      devShells = forEachSupportedSystem ({{ pkgs }}: {{
+        default = pkgs.mkShell {{ packages = with pkgs; [ {package} ]; }};
+      }});
+    }};
+}}"#
+                ),
+            )
+        };
+
+        // With no template listing its systems, the input supplies them
+        let merged = generate_flake(&[nix_systems("a", "jq"), nix_systems("b", "yq")], &GenerateOptions::default(), None).unwrap();
+        assert!(merged.contains("    systems.url = \"github:nix-systems/default\";\n"), "{merged}");
+        assert!(merged.contains("      systems,\n"), "{merged}");
+        assert!(merged.contains("supportedSystems = import systems;"), "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
+
+        // A template's own list still wins, but the input is not dropped
+        let merged = generate_flake(&[nix_systems("a", "jq"), template("hello", APP_TEMPLATE)], &GenerateOptions::default(), None).unwrap();
+        assert!(merged.contains("    systems.url = \"github:nix-systems/default\";\n"), "{merged}");
+        assert!(merged.contains("supportedSystems = [ \"x86_64-linux\" ];"), "{merged}");
+    }
+
+    #[test]
+    fn test_merge_keeps_distinct_overlay_inherits() {
+        let overlay = |name: &str, inherits: &str| {
+            template(
+                name,
+                &format!(
+                    r#"{{
+  description = "Overlay with inherits";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = {{ self, nixpkgs }}: {{
+    overlays.default = final: prev: {{ {inherits} }};
+    devShells = {{ default = pkgs.mkShell {{ packages = with pkgs; [ cmake ]; }}; }};
+  }};
+}}"#
+                ),
+            )
+        };
+        let templates = [
+            overlay("cmake", "inherit (prev) cmake ninja;"),
+            overlay("python", "inherit (prev.python3Packages) pytest; inherit (prev) ninja meson;"),
+        ];
+        let merged = generate_flake(&templates, &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains("        inherit (prev) cmake ninja;\n"), "{merged}");
+        assert!(merged.contains("        inherit (prev.python3Packages) pytest;\n"), "{merged}");
+        // `ninja` is already inherited from `prev`, so only `meson` is added
+        assert!(merged.contains("        inherit (prev) meson;\n"), "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
+
+        // The same name inherited from somewhere else keeps the first and warns
+        let mut fragments = FlakeFragments::default();
+        parse_template_with_nix_parser(&overlay("a", "inherit (prev) ninja;"), &mut fragments, None, false).unwrap();
+        parse_template_with_nix_parser(&overlay("b", "inherit (prev.llvmPackages) ninja;"), &mut fragments, None, false).unwrap();
+        assert_eq!(fragments.overlays["default"].len(), 1);
+        assert!(fragments.warnings.iter().any(|w| w.contains("'ninja'")), "{:?}", fragments.warnings);
+    }
+
+    #[test]
+    fn test_profile_selects_dev_shell_packages() {
+        let profiles = template(
+            "profiles",
+            r#"{
+  description = "Template with a minimal profile";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      supportedSystems = [ "x86_64-linux" ];
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs supportedSystems (system: f { pkgs = import nixpkgs { inherit system; }; });
+    in
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell { packages = with pkgs; [ go gopls golangci-lint ]; };
+        minimal = pkgs.mkShell { packages = with pkgs; [ go ]; };
+      });
+    };
+}"#,
+        );
+        let extracted = nix_parser::extract_flake_fragments(&profiles.flake_content).unwrap();
+        assert_eq!(extracted.shell_packages["minimal"], vec!["go"]);
+        assert_eq!(extracted.shell_packages["default"], vec!["go", "gopls", "golangci-lint"]);
+
+        let templates = [profiles, template("hello", APP_TEMPLATE)];
+        let full = generate_flake(&templates, &GenerateOptions::default(), None).unwrap();
+        assert!(full.contains("              gopls\n"), "{full}");
+
+        let options = GenerateOptions { profile: "minimal".to_string(), ..GenerateOptions::default() };
+        let minimal = generate_flake(&templates, &options, None).unwrap();
+        assert!(minimal.contains("              go\n"), "{minimal}");
+        assert!(!minimal.contains("gopls"), "{minimal}");
+        assert!(!minimal.contains("golangci-lint"), "{minimal}");
+        // Templates without the profile keep all of their packages
+        assert!(minimal.contains("              hello\n"), "{minimal}");
+    }
+
+    #[test]
+    fn test_no_overlay_substitutes_sibling_definitions() {
+        let options = GenerateOptions { no_overlay: true, ..GenerateOptions::default() };
+        let flake = generate_flake(&[embedded("java")], &options, None).unwrap();
+        assert!(flake.contains(r#"java = pkgs."jdk${toString javaVersion}";"#), "{flake}");
+        parse_nix_expr(&flake).expect("flake without overlay should parse");
+
+        // `inherit` statements, `inherit` in arguments and `final.<sibling>` all reach the sibling's definition
+        let overlay = APP_TEMPLATE
+            .replace("packages = with pkgs; [ hello ];", "packages = with pkgs; [ jdk boot tool ];")
+            .replace(
+                "      apps =",
+                "      overlays.default = final: prev: {\n        inherit (prev) jdk;\n        boot = prev.boot.override { inherit (final) jdk; };\n        tool = final.boot.override { jre = final.jdk; };\n      };\n\n      apps =",
+            );
+        let flake = generate_flake(&[template("custom", &overlay)], &options, None).unwrap();
+        assert!(flake.contains("(pkgs.boot.override {\n  jdk = pkgs.jdk;\n})"), "{flake}");
+        assert!(flake.contains("((pkgs.boot.override {\n  jdk = pkgs.jdk;\n}).override {\n  jre = pkgs.jdk;\n})"), "{flake}");
+        assert!(!flake.contains("final"), "{flake}");
+
+        // Overlays taken from inputs stay applied, so the inlined toolchain can still use `rust-bin`
+        let flake = generate_flake(&[embedded("rust")], &options, None).unwrap();
+        assert!(!flake.contains("overlays.default ="), "{flake}");
+        assert!(flake.contains("overlays = [\n                rust-overlay.overlays.default\n              ];"), "{flake}");
+        assert!(flake.contains("pkgs.rust-bin"), "{flake}");
+        parse_nix_expr(&flake).expect("flake without overlay should parse");
+    }
+
+    #[test]
+    fn test_merge_emits_template_shell_hooks() {
+        let hooked = APP_TEMPLATE.replace(
+            "pkgs.mkShell { packages = with pkgs; [ hello ]; }",
+            r#"pkgs.mkShell { packages = with pkgs; [ hello ]; shellHook = ''
+          echo ${pkgs.hello} ''${HOME}
+        '' + "echo done"; }"#,
+        );
+        let merged = generate_flake(&[template("hooked", &hooked), embedded("go")], &GenerateOptions::default(), None).unwrap();
+
+        assert!(merged.contains("              echo ${pkgs.hello} ''${HOME}\n              echo done\n            '';"), "{merged}");
+        parse_nix_expr(&merged).expect("merged flake should parse");
     }
 }
@@ -0,0 +1,136 @@
+use crate::error::GeneratorError;
+use anyhow::{Result, anyhow};
+use nix_parser::NixExpr;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A template parameter declared in the template's `.toml` metadata. It names a binding whose
+/// value in the template is the default, e.g. `goVersion = 24;`, and `--set NAME=value` replaces.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Placeholder {
+    pub name: String,
+    pub binding: String,
+}
+
+/// Parse a `--set NAME=value` option
+pub fn parse_placeholder_value(spec: &str) -> Result<(String, String)> {
+    let (name, value) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid placeholder value '{}': expected NAME=value", spec))?;
+    let name = name.trim();
+    if !is_placeholder_name(name) {
+        return Err(anyhow!(
+            "Invalid placeholder name '{}': expected uppercase letters, digits and underscores",
+            name
+        ));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Replace the value of each placeholder's binding in `template`'s content with its value from
+/// `values`; placeholders without a value keep the template's own. A binding whose default is a
+/// string gets the value as a string, any other binding takes it as a Nix expression.
+pub fn substitute(
+    template: &str,
+    content: &str,
+    placeholders: &[Placeholder],
+    values: &HashMap<String, String>,
+) -> Result<String> {
+    let mut output = content.to_string();
+    for placeholder in placeholders {
+        let Some(value) = values.get(&placeholder.name) else {
+            continue;
+        };
+        let invalid = |reason: String| GeneratorError::InvalidPlaceholder {
+            template: template.to_string(),
+            name: placeholder.name.clone(),
+            reason,
+        };
+
+        let (start, end) = binding_value(&output, &placeholder.binding)
+            .ok_or_else(|| invalid(format!("it has no single '{} = ...;' binding", placeholder.binding)))?;
+        let replacement = if output[start..end].starts_with('"') {
+            NixExpr::String(value.clone()).to_nix_string()
+        } else {
+            nix_parser::parse_nix_expr(value)
+                .map_err(|_| invalid(format!("'{value}' is not a Nix expression")))?;
+            value.clone()
+        };
+        output.replace_range(start..end, &replacement);
+    }
+    Ok(output)
+}
+
+/// Byte range of the value in the only `binding = value;` line of `content`
+fn binding_value(content: &str, binding: &str) -> Option<(usize, usize)> {
+    let mut found = None;
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        let indent = line.len() - line.trim_start().len();
+        let value = line[indent..]
+            .strip_prefix(binding)
+            .map(str::trim_start)
+            .and_then(|rest| rest.strip_prefix('='))
+            .filter(|rest| !rest.starts_with('='));
+        if let Some(value) = value {
+            let value_start = line.len() - value.trim_start().len();
+            let length = value.trim_start().find(';')?;
+            if found.is_some() {
+                return None;
+            }
+            let start = line_start + value_start;
+            found = Some((start, start + value.trim_start()[..length].trim_end().len()));
+        }
+        line_start += line.len();
+    }
+    found
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_placeholders() {
+        let content = "let\n  goVersion = 24; # stack\n  name = \"demo\";\n  goVersionSuffix = 1;\nin { }\n";
+        let placeholders = [
+            Placeholder { name: "GO_VERSION".to_string(), binding: "goVersion".to_string() },
+            Placeholder { name: "NAME".to_string(), binding: "name".to_string() },
+        ];
+
+        assert_eq!(substitute("go", content, &placeholders, &HashMap::new()).unwrap(), content);
+
+        let values = HashMap::from([
+            ("GO_VERSION".to_string(), "22".to_string()),
+            ("NAME".to_string(), "my \"app\"".to_string()),
+        ]);
+        assert_eq!(
+            substitute("go", content, &placeholders, &values).unwrap(),
+            "let\n  goVersion = 22; # stack\n  name = \"my \\\"app\\\"\";\n  goVersionSuffix = 1;\nin { }\n"
+        );
+
+        let values = HashMap::from([("GO_VERSION".to_string(), "22 +".to_string())]);
+        let err = substitute("go", content, &placeholders, &values).unwrap_err();
+        assert!(err.to_string().contains("not a Nix expression"), "{err}");
+
+        let missing = [Placeholder { name: "GO_VERSION".to_string(), binding: "rustVersion".to_string() }];
+        let values = HashMap::from([("GO_VERSION".to_string(), "22".to_string())]);
+        let err = substitute("go", content, &missing, &values).unwrap_err();
+        assert!(err.to_string().contains("rustVersion"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_placeholder_value() {
+        assert_eq!(
+            parse_placeholder_value("GO_VERSION=22").unwrap(),
+            ("GO_VERSION".to_string(), "22".to_string())
+        );
+        assert!(parse_placeholder_value("GO_VERSION").is_err());
+        assert!(parse_placeholder_value("go=22").is_err());
+    }
+}
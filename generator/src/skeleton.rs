@@ -0,0 +1,77 @@
+use anyhow::{anyhow, Result};
+
+/// Starting point for a custom template: the same forEachSupportedSystem scaffolding as the
+/// embedded templates, with an empty overlay and package list to fill in
+pub fn template_skeleton(name: &str) -> Result<String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!(
+            "Invalid template name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+
+    Ok(format!(
+        r#"{{
+  description = "A Nix-flake-based {name} development environment";
+
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+
+  outputs =
+    {{ self, nixpkgs }}:
+    let
+      supportedSystems = [
+        "x86_64-linux"
+        "aarch64-linux"
+        "x86_64-darwin"
+        "aarch64-darwin"
+      ];
+      forEachSupportedSystem =
+        f:
+        nixpkgs.lib.genAttrs supportedSystems (
+          system:
+          f {{
+            pkgs = import nixpkgs {{
+              inherit system;
+              overlays = [ self.overlays.default ];
+            }};
+          }}
+        );
+    in
+    {{
+      # Override or add packages here, e.g. `foo = prev.foo_2;`
+      overlays.default = final: prev: {{ }};
+
+      devShells = forEachSupportedSystem (
+        {{ pkgs }}:
+        {{
+          default = pkgs.mkShell {{
+            # Tools for the {name} development environment
+            packages = with pkgs; [ ];
+          }};
+        }}
+      );
+    }};
+}}
+"#
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skeleton_is_an_empty_template() {
+        let skeleton = template_skeleton("mylang").unwrap();
+        let fragments = nix_parser::extract_flake_fragments(&skeleton).unwrap();
+
+        assert_eq!(fragments.header, "A Nix-flake-based mylang development environment");
+        assert_eq!(fragments.inputs.keys().collect::<Vec<_>>(), ["nixpkgs"]);
+        assert!(fragments.packages.is_empty());
+        assert_eq!(fragments.overlays.get("default"), Some(&Vec::new()));
+        assert!(fragments.let_bindings.contains_key("supportedSystems"));
+
+        assert!(template_skeleton("my lang").is_err());
+        assert!(template_skeleton("").is_err());
+    }
+}
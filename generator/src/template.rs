@@ -1,10 +1,28 @@
 use crate::embedded_templates::EMBEDDED_TEMPLATES;
-use anyhow::{Result, anyhow};
+use crate::error::GeneratorError;
+use crate::filesystem::{FileSystem, RealFileSystem};
+use crate::formatter::Formatter;
+use crate::fragment_cache::FragmentCache;
+use crate::markers;
+use crate::merger::GenerateOptions;
+use crate::placeholders::{self, Placeholder};
+use anyhow::Result;
+use nix_parser::NixExpr;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// `shell.nix` written by `--compat`, exposing the flake's default dev shell to `nix-shell`
+const COMPAT_SHELL_NIX: &str = r#"# Lets `nix-shell` enter the dev shell defined in flake.nix
+let
+  flake-compat = fetchTarball "https://github.com/edolstra/flake-compat/archive/master.tar.gz";
+  flake = (import flake-compat { src = ./.; }).defaultNix;
+in
+flake.devShells.${builtins.currentSystem}.default
+"#;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Template {
@@ -12,24 +30,85 @@ pub struct Template {
     pub description: String,
     pub flake_content: String,
     pub additional_files: HashMap<String, String>,
+    /// Placeholders `--set` can fill, declared in the template's metadata
+    pub placeholders: Vec<Placeholder>,
 }
 
 pub struct TemplateManager {
     templates: HashMap<String, Template>,
+    parse_cache: Option<FragmentCache>,
+    quiet: bool,
+    markers: bool,
+    compat: bool,
+    placeholder_values: HashMap<String, String>,
+    formatter_notice_shown: AtomicBool,
+    filesystem: Box<dyn FileSystem>,
 }
 
 impl TemplateManager {
     pub async fn new() -> Result<Self> {
         let mut manager = Self {
             templates: HashMap::new(),
+            parse_cache: None,
+            quiet: false,
+            markers: false,
+            compat: false,
+            placeholder_values: HashMap::new(),
+            formatter_notice_shown: AtomicBool::new(false),
+            filesystem: Box::new(RealFileSystem),
         };
 
         manager.load_embedded_templates().await?;
         Ok(manager)
     }
 
+    /// Suppress informational messages; warnings are still printed
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Wrap generated sections in BEGIN/END marker comments so later runs only rewrite those
+    pub fn with_markers(mut self, markers: bool) -> Self {
+        self.markers = markers;
+        self
+    }
+
+    /// Also write a `shell.nix` shim so the environment works with non-flake `nix-shell`
+    pub fn with_compat(mut self, compat: bool) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Values for template placeholders, used instead of the templates' defaults
+    pub fn with_placeholder_values(mut self, values: HashMap<String, String>) -> Self {
+        self.placeholder_values = values;
+        self
+    }
+
+    /// Add templates from outside the binary, replacing embedded templates of the same name
+    pub fn with_templates(mut self, templates: Vec<Template>) -> Self {
+        for template in templates {
+            self.templates.insert(template.name.clone(), template);
+        }
+        self
+    }
+
+    /// Write generated files through `filesystem` instead of the real one
+    #[cfg(test)]
+    pub fn with_filesystem(mut self, filesystem: impl FileSystem + 'static) -> Self {
+        self.filesystem = Box::new(filesystem);
+        self
+    }
+
+    /// Opt in to caching extraction results by template content hash
+    pub fn with_parse_cache(mut self) -> Self {
+        self.parse_cache = Some(FragmentCache::new());
+        self
+    }
+
     async fn load_embedded_templates(&mut self) -> Result<()> {
-        for (template_name, (description, flake_content)) in EMBEDDED_TEMPLATES.iter() {
+        for (template_name, embedded) in EMBEDDED_TEMPLATES.iter() {
             let mut additional_files = HashMap::new();
 
             // Special case for rust-toolchain template - add sample rust-toolchain.toml
@@ -46,9 +125,10 @@ components = ["rustfmt", "rust-analyzer"]
 
             let template = Template {
                 name: template_name.to_string(),
-                description: description.to_string(),
-                flake_content: flake_content.to_string(),
+                description: embedded.description.to_string(),
+                flake_content: embedded.content.to_string(),
                 additional_files,
+                placeholders: embedded.placeholders.clone(),
             };
 
             self.templates.insert(template_name.to_string(), template);
@@ -57,87 +137,540 @@ components = ["rustfmt", "rust-analyzer"]
         Ok(())
     }
 
-    pub async fn init_single(&self, template_name: &str, target_path: &Path) -> Result<()> {
-        let template = self
-            .templates
-            .get(template_name)
-            .ok_or_else(|| anyhow!("Template '{}' not found", template_name))?;
+    /// Render a single template's flake without writing it, formatted with `options.formatter`
+    pub fn render_single(&self, template_name: &str, options: &GenerateOptions) -> Result<String> {
+        let templates = self.resolve_templates(&[template_name])?;
+
+        let flake_content = crate::merger::generate_flake(
+            &templates,
+            options,
+            self.parse_cache.as_ref(),
+        )?;
+        self.format_content(&flake_content, template_name, options.formatter)
+    }
 
-        fs::create_dir_all(target_path)?;
+    pub async fn init_single(
+        &self,
+        template_name: &str,
+        target_path: &Path,
+        options: &GenerateOptions,
+    ) -> Result<()> {
+        let flake_content = self.render_single(template_name, options)?;
+
+        self.filesystem.create_dir_all(target_path)?;
 
         let flake_path = target_path.join("flake.nix");
-        fs::write(&flake_path, &template.flake_content)?;
+        self.write_flake(&flake_path, &flake_content)?;
 
-        self.format_with_nixfmt(&flake_path)?;
+        self.write_additional_files(std::slice::from_ref(&self.templates[template_name]), target_path)?;
 
-        for (filename, content) in &template.additional_files {
-            fs::write(target_path.join(filename), content)?;
-        }
+        Ok(())
+    }
+
+    pub async fn init_multi(
+        &self,
+        template_names: &[&str],
+        target_path: &Path,
+        options: &GenerateOptions,
+    ) -> Result<()> {
+        let templates = self.resolve_templates(template_names)?;
+
+        let merged = crate::merger::generate_flake(
+            &templates,
+            options,
+            self.parse_cache.as_ref(),
+        )?;
+        let merged = self.format_content(&merged, &template_names.join("+"), options.formatter)?;
+
+        self.filesystem.create_dir_all(target_path)?;
+        let flake_path = target_path.join("flake.nix");
+        self.write_flake(&flake_path, &merged)?;
+
+        self.write_additional_files(&templates, target_path)?;
 
         Ok(())
     }
 
-    pub async fn init_multi(&self, template_names: &[&str], target_path: &Path) -> Result<()> {
-        let mut templates = Vec::new();
+    /// Write each template's own flake into `<target>/<name>`, plus a root flake exposing them all
+    pub async fn init_split(
+        &self,
+        template_names: &[&str],
+        target_path: &Path,
+        options: &GenerateOptions,
+    ) -> Result<()> {
+        let mut names: Vec<&str> = Vec::new();
         for name in template_names {
-            let template = self
-                .templates
-                .get(*name)
-                .ok_or_else(|| anyhow!("Template '{}' not found", name))?;
-            templates.push(template.clone());
+            if !names.contains(name) {
+                names.push(name);
+            }
         }
+        // Fail on unknown templates or placeholders before writing anything
+        self.resolve_templates(&names)?;
 
-        let merged = crate::merger::merge_templates(&templates)?;
+        for name in &names {
+            self.init_single(name, &target_path.join(name), options).await?;
+        }
 
-        fs::create_dir_all(target_path)?;
+        let root = crate::merger::generate_split_flake(&names, options)?;
+        let root = self.format_content(&root, "root", options.formatter)?;
+        self.filesystem.create_dir_all(target_path)?;
         let flake_path = target_path.join("flake.nix");
-        fs::write(&flake_path, merged)?;
+        self.write_flake(&flake_path, &root)?;
 
-        self.format_with_nixfmt(&flake_path)?;
+        self.write_additional_files(&[], target_path)?;
 
-        for template in &templates {
-            for (filename, content) in &template.additional_files {
-                let target_file = target_path.join(filename);
-                if !target_file.exists() {
-                    fs::write(target_file, content)?;
-                }
+        Ok(())
+    }
+
+    /// Write each template's additional files, plus the `shell.nix` shim when requested,
+    /// never overwriting existing ones
+    fn write_additional_files(&self, templates: &[Template], target_path: &Path) -> Result<()> {
+        for template in templates {
+            let mut filenames: Vec<_> = template.additional_files.keys().collect();
+            filenames.sort();
+
+            for filename in filenames {
+                self.write_if_absent(&target_path.join(filename), &template.additional_files[filename])?;
             }
         }
-
+        if self.compat {
+            self.write_if_absent(&target_path.join("shell.nix"), COMPAT_SHELL_NIX)?;
+        }
         Ok(())
     }
 
-    fn format_with_nixfmt(&self, file_path: &Path) -> Result<()> {
-        if Command::new("nixfmt").arg("--version").output().is_ok() {
-            let output = Command::new("nixfmt")
-                .arg(file_path)
-                .output();
-            
-            match output {
-                Ok(result) if result.status.success() => {
-                    println!("Formatted {} with nixfmt", file_path.display());
-                }
-                Ok(result) => {
-                    eprintln!("Warning: nixfmt failed to format {}: {}", 
-                        file_path.display(), 
-                        String::from_utf8_lossy(&result.stderr));
-                }
-                Err(_) => {
-                    eprintln!("Warning: Failed to run nixfmt on {}", file_path.display());
-                }
+    /// Write `content` unless the file already exists, reporting which happened
+    fn write_if_absent(&self, target_file: &Path, content: &str) -> Result<()> {
+        if self.filesystem.exists(target_file) {
+            if !self.quiet {
+                println!("Skipped {} (already exists)", target_file.display());
+            }
+        } else {
+            self.filesystem.write(target_file, content)?;
+            if !self.quiet {
+                println!("Wrote {}", target_file.display());
             }
         }
         Ok(())
     }
 
-    pub fn list_templates(&self) {
+    /// Write the generated flake, only replacing the marked region if the existing file has markers
+    fn write_flake(&self, flake_path: &Path, content: &str) -> Result<()> {
+        let existing = self
+            .filesystem
+            .read_to_string(flake_path)
+            .ok()
+            .filter(|existing| markers::has_markers(existing));
+
+        let output = match existing {
+            Some(existing) => {
+                let generated = markers::wrap_in_markers(content);
+                markers::splice_generated(&existing, &generated).unwrap_or(generated)
+            }
+            None if self.markers => markers::wrap_in_markers(content),
+            None => content.to_string(),
+        };
+
+        self.filesystem.write(flake_path, &output)?;
+        Ok(())
+    }
+
+    /// Format flake text that isn't written anywhere yet, going through a scratch file the formatter
+    /// can open; the text comes back as it is when nothing formatted it
+    fn format_content(&self, content: &str, template_name: &str, formatter: Formatter) -> Result<String> {
+        if !self.filesystem.is_on_disk() || formatter == Formatter::None {
+            return Ok(content.to_string());
+        }
+        let scratch_dir = scratch_dir()?;
+        let scratch_file = scratch_dir.join("flake.nix");
+        std::fs::write(&scratch_file, content)?;
+
+        let formatted = if self.run_formatter(&scratch_file, formatter) {
+            if !self.quiet {
+                println!("Formatted the {template_name} flake with {}", formatter.name());
+            }
+            std::fs::read_to_string(&scratch_file)
+        } else {
+            Ok(content.to_string())
+        };
+        std::fs::remove_dir_all(&scratch_dir)?;
+        Ok(formatted?)
+    }
+
+    /// Format `file_path` in place, returning whether the formatter ran successfully
+    fn run_formatter(&self, file_path: &Path, formatter: Formatter) -> bool {
+        let (Some(mut version), Some(mut format)) = (
+            formatter.version_command(),
+            formatter.format_command(file_path),
+        ) else {
+            return false;
+        };
+        let name = formatter.name();
+
+        if version.output().is_err() {
+            // Only mention the missing formatter once per run
+            if !self.quiet && !self.formatter_notice_shown.swap(true, Ordering::Relaxed) {
+                eprintln!("{name} not found; skipping formatting — install {name} for prettier output");
+            }
+            return false;
+        }
+        match format.output() {
+            Ok(result) if result.status.success() => true,
+            Ok(result) => {
+                eprintln!("Warning: {name} failed to format {}: {}", 
+                    file_path.display(), 
+                    String::from_utf8_lossy(&result.stderr));
+                false
+            }
+            Err(_) => {
+                eprintln!("Warning: Failed to run {name} on {}", file_path.display());
+                false
+            }
+        }
+    }
+
+    pub fn list_templates(&self, unfree_only: bool) -> Result<()> {
         println!("Available templates:");
         let mut sorted: Vec<_> = self.templates.values().collect();
         sorted.sort_by(|a, b| a.name.cmp(&b.name));
 
+        if unfree_only {
+            let mut unfree = Vec::new();
+            for template in sorted {
+                if self.requires_unfree(template)? {
+                    unfree.push(template);
+                }
+            }
+            sorted = unfree;
+        }
+
         for template in sorted {
             println!("  {} - {}", template.name, template.description);
         }
+        Ok(())
+    }
+
+    /// Deduplicated, sorted packages the dev shells of `template_names` would include together
+    pub fn merged_packages(&self, template_names: &[&str]) -> Result<Vec<String>> {
+        let mut packages = Vec::new();
+        for name in template_names {
+            let template = self
+                .templates
+                .get(*name)
+                .ok_or_else(|| GeneratorError::TemplateNotFound(name.to_string()))?;
+            packages.extend(self.fragments(template)?.packages);
+        }
+        packages.sort();
+        packages.dedup();
+        Ok(packages)
     }
 
+    /// Parse and extract every embedded template, printing a table of the results.
+    /// Templates loaded from an archive aren't checked.
+    ///
+    /// Fails if any template doesn't parse or yields no inputs or packages.
+    pub fn doctor(&self) -> Result<()> {
+        let mut sorted: Vec<_> = EMBEDDED_TEMPLATES.iter().collect();
+        sorted.sort_by_key(|(name, _)| **name);
+        let width = sorted.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("TEMPLATE".len());
+
+        println!("{:width$}  {:>6}  {:>8}  STATUS", "TEMPLATE", "INPUTS", "PACKAGES");
+        let mut failures = Vec::new();
+        for (name, embedded) in sorted {
+            let (inputs, packages, problem) = match self.health(embedded.content) {
+                Ok(fragments) => {
+                    let problem = if fragments.inputs.is_empty() {
+                        Some("no inputs found".to_string())
+                    } else if fragments.packages.is_empty() {
+                        Some("no packages found".to_string())
+                    } else {
+                        None
+                    };
+                    (fragments.inputs.len().to_string(), fragments.packages.len().to_string(), problem)
+                }
+                Err(e) => ("-".to_string(), "-".to_string(), Some(e.to_string())),
+            };
+            let status = problem.as_deref().unwrap_or("ok");
+            println!("{name:width$}  {inputs:>6}  {packages:>8}  {status}");
+            if problem.is_some() {
+                failures.push(*name);
+            }
+        }
+
+        if failures.is_empty() {
+            if !self.quiet {
+                println!("All {} templates are healthy", EMBEDDED_TEMPLATES.len());
+            }
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Unhealthy templates: {}", failures.join(", ")))
+        }
+    }
+
+    fn health(&self, content: &str) -> Result<nix_parser::FlakeFragments> {
+        nix_parser::parse_nix_expr(content).map_err(|e| GeneratorError::Parse(e.to_string()))?;
+        nix_parser::extract_flake_fragments(content).map_err(|e| GeneratorError::Parse(e.to_string()).into())
+    }
+
+    /// Print the files `init` would write for these templates, without writing anything
+    pub fn list_files(&self, template_names: &[&str], target_path: &Path) -> Result<()> {
+        let mut files = vec![("flake.nix".to_string(), true)];
+        for name in template_names {
+            let template = self
+                .templates
+                .get(*name)
+                .ok_or_else(|| GeneratorError::TemplateNotFound(name.to_string()))?;
+            let mut filenames: Vec<_> = template.additional_files.keys().cloned().collect();
+            filenames.sort();
+            files.extend(filenames.into_iter().map(|filename| (filename, false)));
+        }
+        if self.compat {
+            files.push(("shell.nix".to_string(), false));
+        }
+
+        for (filename, overwrites) in files {
+            let path = target_path.join(&filename);
+            let status = match (self.filesystem.exists(&path), overwrites) {
+                (false, _) => "new",
+                (true, true) => "overwrites existing file",
+                (true, false) => "exists, would be skipped",
+            };
+            println!("{} ({status})", path.display());
+        }
+        Ok(())
+    }
+
+    /// Describe a template and its packages, optionally with the versions they resolve to in the
+    /// template's package set: its nixpkgs with the overlays it applies
+    pub fn template_info(&self, template_name: &str, versions: bool) -> Result<()> {
+        let template = self
+            .templates
+            .get(template_name)
+            .ok_or_else(|| GeneratorError::TemplateNotFound(template_name.to_string()))?;
+        let fragments = self.fragments(template)?;
+
+        println!("{} - {}", template.name, template.description);
+        for line in crate::merger::fragment_summary(&fragments) {
+            println!("  {line}");
+        }
+        if !versions {
+            return Ok(());
+        }
+
+        let nixpkgs = fragments
+            .inputs
+            .get("nixpkgs")
+            .map(String::as_str)
+            .unwrap_or(crate::merger::DEFAULT_NIXPKGS_URL);
+        println!("Versions ({nixpkgs} with the template's overlays):");
+
+        // The overlays come from the template's own flake, so nix evaluates a copy of it
+        let flake_dir = scratch_dir()?;
+        std::fs::write(flake_dir.join("flake.nix"), &template.flake_content)?;
+        for (filename, content) in &template.additional_files {
+            std::fs::write(flake_dir.join(filename), content)?;
+        }
+        let mut result = Ok(());
+        for package in &fragments.packages {
+            match package_version(&version_expr(&flake_dir, &fragments, package)) {
+                Ok(version) => println!("  {package}: {}", version.as_deref().unwrap_or("unknown")),
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    if !self.quiet {
+                        eprintln!("nix not found; skipping version lookup — install nix to see resolved versions");
+                    }
+                    break;
+                }
+                Err(err) => {
+                    result = Err(anyhow::Error::new(err).context("Failed to run nix eval"));
+                    break;
+                }
+            }
+        }
+        std::fs::remove_dir_all(&flake_dir)?;
+        result
+    }
+
+    /// Whether the template sets `config.allowUnfree = true` when importing nixpkgs
+    fn requires_unfree(&self, template: &Template) -> Result<bool> {
+        Ok(self.fragments(template)?.allow_unfree)
+    }
+
+    fn fragments(&self, template: &Template) -> Result<nix_parser::FlakeFragments> {
+        let content = &template.flake_content;
+        Ok(match &self.parse_cache {
+            Some(cache) => cache.extract(content)?,
+            None => nix_parser::extract_flake_fragments_lenient(content)
+                .map_err(|e| GeneratorError::Parse(e.to_string()))?,
+        })
+    }
+
+    /// Look up templates and fill in their placeholders, rejecting values no template uses
+    pub fn resolve_templates(&self, template_names: &[&str]) -> Result<Vec<Template>> {
+        let mut templates = Vec::new();
+        for name in template_names {
+            let template = self
+                .templates
+                .get(*name)
+                .ok_or_else(|| GeneratorError::TemplateNotFound(name.to_string()))?;
+            templates.push(template);
+        }
+
+        let mut names: Vec<&String> = self.placeholder_values.keys().collect();
+        names.sort();
+        for name in names {
+            let used = templates
+                .iter()
+                .any(|template| template.placeholders.iter().any(|p| &p.name == name));
+            if !used {
+                return Err(GeneratorError::UnknownPlaceholder(name.clone()).into());
+            }
+        }
+
+        templates
+            .into_iter()
+            .map(|template| {
+                Ok(Template {
+                    flake_content: placeholders::substitute(
+                        &template.name,
+                        &template.flake_content,
+                        &template.placeholders,
+                        &self.placeholder_values,
+                    )?,
+                    ..template.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+/// Version of `package` in the given nixpkgs flake, via `nix eval`.
+///
+/// Errors only when nix itself can't be run; a package that fails to evaluate yields `None`.
+/// A fresh directory under the system temp dir, for files an external tool has to open
+fn scratch_dir() -> std::io::Result<PathBuf> {
+    static SCRATCH_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!(
+        "nix-flake-generator-{}-{}",
+        std::process::id(),
+        SCRATCH_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Nix expression for `package`'s version in the package set of the template flake in `flake_dir`,
+/// or `""` if it has none
+fn version_expr(flake_dir: &Path, fragments: &nix_parser::FlakeFragments, package: &str) -> String {
+    let nixpkgs = if fragments.inputs.contains_key("nixpkgs") {
+        "flake.inputs.nixpkgs".to_string()
+    } else {
+        format!("builtins.getFlake \"{}\"", crate::merger::DEFAULT_NIXPKGS_URL)
+    };
+
+    // Overlays taken from inputs go first, then the template's own, as the templates apply them
+    let mut input_overlays: Vec<_> = fragments.input_overlays.iter().collect();
+    input_overlays.sort();
+    let mut own_overlays: Vec<_> = fragments.overlays.keys().collect();
+    own_overlays.sort();
+    let overlays: String = input_overlays
+        .into_iter()
+        .flat_map(|(input, names)| names.iter().map(move |name| format!(" flake.inputs.{input}.overlays.{name}")))
+        .chain(own_overlays.into_iter().map(|name| format!(" flake.overlays.{name}")))
+        .collect();
+
+    format!(
+        r#"let
+  flake = builtins.getFlake {};
+  pkgs = import ({nixpkgs}) {{
+    system = builtins.currentSystem;
+    config.allowUnfree = {};
+    overlays = [{overlays} ];
+  }};
+in
+(with pkgs; {package}).version or """#,
+        NixExpr::String(format!("path:{}", flake_dir.display())).to_nix_string(),
+        fragments.allow_unfree,
+    )
+}
+
+fn package_version(expr: &str) -> std::io::Result<Option<String>> {
+    let output = Command::new("nix")
+        .args(["--extra-experimental-features", "nix-command flakes", "eval", "--impure", "--raw", "--expr"])
+        .arg(expr)
+        .output()?;
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|version| !version.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFileSystem;
+
+    #[tokio::test]
+    async fn test_render_single_returns_flake_content() {
+        let manager = TemplateManager::new().await.unwrap();
+        let flake = manager.render_single("rust", &GenerateOptions::default()).unwrap();
+
+        assert!(flake.contains("rustToolchain"));
+        assert!(manager.render_single("missing", &GenerateOptions::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_version_expr_applies_template_overlays() {
+        let manager = TemplateManager::new().await.unwrap();
+        let flake_dir = Path::new("/tmp/go template");
+
+        let go = manager.fragments(&manager.templates["go"]).unwrap();
+        let expr = version_expr(flake_dir, &go, "go");
+        assert!(expr.contains(r#"builtins.getFlake "path:/tmp/go template";"#), "{expr}");
+        assert!(expr.contains("import (flake.inputs.nixpkgs)"), "{expr}");
+        assert!(expr.contains("overlays = [ flake.overlays.default ];"), "{expr}");
+        assert!(expr.ends_with(r#"(with pkgs; go).version or """#), "{expr}");
+        nix_parser::parse_nix_expr(&expr).expect("version expression should parse");
+
+        let rust = manager.fragments(&manager.templates["rust"]).unwrap();
+        let expr = version_expr(flake_dir, &rust, "rustToolchain");
+        assert!(
+            expr.contains("overlays = [ flake.inputs.rust-overlay.overlays.default flake.overlays.default ];"),
+            "{expr}"
+        );
+        nix_parser::parse_nix_expr(&expr).expect("version expression should parse");
+    }
+
+    #[tokio::test]
+    async fn test_render_fills_placeholders() {
+        let manager = TemplateManager::new().await.unwrap();
+        let default = manager.render_single("go", &GenerateOptions::default()).unwrap();
+        assert!(default.contains("goVersion = 24;"), "{default}");
+
+        let manager = manager.with_placeholder_values(HashMap::from([("GO_VERSION".to_string(), "22".to_string())]));
+        let flake = manager.render_single("go", &GenerateOptions::default()).unwrap();
+        assert!(flake.contains("goVersion = 22;"), "{flake}");
+
+        let unknown = manager.render_single("rust", &GenerateOptions::default()).unwrap_err();
+        assert!(unknown.to_string().contains("GO_VERSION"), "{unknown}");
+    }
+
+    #[tokio::test]
+    async fn test_init_keeps_existing_additional_files() {
+        let target = Path::new("/project");
+        // The default formatter must not look for these files on the real disk
+        let options = GenerateOptions::default();
+
+        let existing = MemoryFileSystem::default().with_file("/project/rust-toolchain.toml", "# mine\n");
+        let manager = TemplateManager::new().await.unwrap().with_quiet(true).with_filesystem(existing.clone());
+        manager.init_single("rust-toolchain", target, &options).await.unwrap();
+        assert_eq!(existing.file("/project/rust-toolchain.toml").as_deref(), Some("# mine\n"));
+        assert!(existing.file("/project/flake.nix").unwrap().contains("rustToolchain"));
+
+        let empty = MemoryFileSystem::default();
+        let manager = TemplateManager::new().await.unwrap().with_quiet(true).with_filesystem(empty.clone());
+        manager.init_single("rust-toolchain", target, &options).await.unwrap();
+        assert!(empty.file("/project/rust-toolchain.toml").unwrap().contains("channel = \"stable\""));
+    }
 }
@@ -0,0 +1,210 @@
+use crate::error::GeneratorError;
+use anyhow::{Result, anyhow};
+use nix_parser::extract_flake_fragments;
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// Point every nixpkgs input of `flake`, plus the inputs named by `patterns`, at `new_ref`,
+/// leaving the rest of the file untouched. In a pattern, `*` matches any run of characters.
+///
+/// Returns the rewritten flake and the `(old, new)` URL pairs that changed.
+pub fn upgrade_nixpkgs(flake: &str, new_ref: &str, patterns: &[String]) -> Result<(String, Vec<(String, String)>)> {
+    let fragments = extract_flake_fragments(flake).map_err(|e| GeneratorError::Parse(e.to_string()))?;
+
+    let mut selected = HashSet::new();
+    for (name, url) in &fragments.inputs {
+        if is_nixpkgs_url(url) {
+            selected.insert(url.as_str());
+        } else if patterns.iter().any(|pattern| matches_pattern(pattern, name)) {
+            if !has_ref_path(url) {
+                return Err(anyhow!("Can't set a ref on input '{name}': '{url}' is not a github:owner/repo URL"));
+            }
+            selected.insert(url.as_str());
+        }
+    }
+    if selected.is_empty() {
+        return Err(anyhow!("No nixpkgs input found to upgrade"));
+    }
+
+    // Rewrite back to front so earlier ranges stay valid
+    let mut upgraded = flake.to_string();
+    let mut changes = Vec::new();
+    for range in url_bindings(flake).into_iter().rev() {
+        let url = &flake[range.clone()];
+        if !selected.contains(url) {
+            continue;
+        }
+        let new_url = with_ref(url, new_ref);
+        if new_url != url {
+            upgraded.replace_range(range, &new_url);
+            changes.push((url.to_string(), new_url));
+        }
+    }
+    changes.sort();
+    changes.dedup();
+    Ok((upgraded, changes))
+}
+
+/// `github:NixOS/nixpkgs[/ref]`, in any letter case
+pub fn is_nixpkgs_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    let url = url.split('?').next().unwrap_or_default();
+    url == "github:nixos/nixpkgs" || url.starts_with("github:nixos/nixpkgs/")
+}
+
+/// URL schemes of the form `scheme:owner/repo[/ref][?query]`
+fn has_ref_path(url: &str) -> bool {
+    ["github:", "gitlab:", "sourcehut:"]
+        .iter()
+        .any(|scheme| url.to_ascii_lowercase().starts_with(scheme))
+}
+
+/// Replace the ref of a `github:owner/repo[/ref][?query]` URL, keeping its query
+fn with_ref(url: &str, new_ref: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some((base, query)) => (base, format!("?{query}")),
+        None => (url, String::new()),
+    };
+    let repo: Vec<&str> = base.splitn(3, '/').take(2).collect();
+    format!("{}/{new_ref}{query}", repo.join("/"))
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(remaining) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=remaining.len())
+                .filter(|&i| remaining.is_char_boundary(i))
+                .any(|i| matches_pattern(rest, &remaining[i..]))
+        }
+    }
+}
+
+/// Byte ranges of the string values of `url = "...";` bindings, skipping comments and other strings
+fn url_bindings(flake: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while offset < flake.len() {
+        let rest = &flake[offset..];
+        if rest.starts_with('#') {
+            offset += rest.find('\n').unwrap_or(rest.len());
+        } else if rest.starts_with("/*") {
+            offset += rest.find("*/").map_or(rest.len(), |end| end + 2);
+        } else if rest.starts_with("''") {
+            offset += indented_string_len(rest);
+        } else if rest.starts_with('"') {
+            offset += string_len(rest);
+        } else if rest.starts_with("url") && !flake[..offset].ends_with(is_identifier_char) {
+            let after = &rest[3..];
+            let value = after
+                .trim_start()
+                .strip_prefix('=')
+                .filter(|value| !value.starts_with('='))
+                .map(str::trim_start)
+                .filter(|value| value.starts_with('"') && !after.starts_with(is_identifier_char));
+            match value {
+                Some(value) => {
+                    let start = flake.len() - value.len();
+                    let length = string_len(value);
+                    ranges.push(start + 1..start + length - 1);
+                    offset = start + length;
+                }
+                None => offset += 3,
+            }
+        } else {
+            offset += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    ranges
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'')
+}
+
+/// Length of the `"..."` string at the start of `input`, including its quotes
+fn string_len(input: &str) -> usize {
+    let mut escaped = false;
+    for (index, c) in input.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return index + 1,
+            _ => {}
+        }
+    }
+    input.len()
+}
+
+/// Length of the `''...''` string at the start of `input`, including its delimiters
+fn indented_string_len(input: &str) -> usize {
+    let mut offset = 2;
+    while let Some(found) = input[offset..].find("''") {
+        let end = offset + found + 2;
+        // `'''`, `''$` and `''\` are escapes, not the end of the string
+        match input[end..].chars().next() {
+            Some('\'' | '$') => offset = end + 1,
+            Some('\\') => offset = end + 1 + input[end + 1..].chars().next().map_or(0, char::len_utf8),
+            _ => return end,
+        }
+    }
+    input.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_rewrites_flat_and_structured_nixpkgs_inputs() {
+        let flake = r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    stable = { url = "github:nixos/nixpkgs/nixos-23.11"; };
+    rust-overlay.url = "github:oxalica/rust-overlay";
+  };
+  outputs = { self, nixpkgs, stable, rust-overlay }: { };
+}"#;
+
+        let (upgraded, changes) = upgrade_nixpkgs(flake, "nixos-24.05", &[]).unwrap();
+
+        assert!(upgraded.contains(r#"nixpkgs.url = "github:NixOS/nixpkgs/nixos-24.05";"#));
+        assert!(upgraded.contains(r#"stable = { url = "github:nixos/nixpkgs/nixos-24.05"; };"#));
+        assert!(upgraded.contains(r#"rust-overlay.url = "github:oxalica/rust-overlay";"#));
+        assert_eq!(changes.len(), 2);
+        assert!(upgrade_nixpkgs("{ inputs = { }; outputs = _: { }; }", "nixos-24.05", &[]).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_touches_only_url_bindings_and_keeps_queries() {
+        let flake = r#"{
+  # was "github:NixOS/nixpkgs/nixos-unstable" before
+  description = "Pinned to github:NixOS/nixpkgs/nixos-unstable";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable?shallow=1";
+  inputs.nixpkgs-old.url = "github:someone/nixpkgs-fork/old";
+  inputs.flake-utils.url = "github:numtide/flake-utils";
+  outputs = { self, nixpkgs, nixpkgs-old, flake-utils }: {
+    note = "github:NixOS/nixpkgs/nixos-unstable?shallow=1";
+  };
+}"#;
+
+        let patterns = ["nixpkgs-*".to_string()];
+        let (upgraded, changes) = upgrade_nixpkgs(flake, "nixos-24.05", &patterns).unwrap();
+
+        assert!(upgraded.contains(r#"inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-24.05?shallow=1";"#));
+        assert!(upgraded.contains(r#"inputs.nixpkgs-old.url = "github:someone/nixpkgs-fork/nixos-24.05";"#));
+        assert!(upgraded.contains(r#"inputs.flake-utils.url = "github:numtide/flake-utils";"#));
+        assert!(upgraded.contains(r#"# was "github:NixOS/nixpkgs/nixos-unstable" before"#));
+        assert!(upgraded.contains(r#"description = "Pinned to github:NixOS/nixpkgs/nixos-unstable";"#));
+        assert!(upgraded.contains(r#"note = "github:NixOS/nixpkgs/nixos-unstable?shallow=1";"#));
+        assert_eq!(changes.len(), 2);
+
+        let patterns = ["*".to_string()];
+        let local = flake.replace("github:numtide/flake-utils", "path:./utils");
+        assert!(upgrade_nixpkgs(&local, "nixos-24.05", &patterns).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use predicates::prelude::*;
-use crate::integration::common::create_cargo_command;
+use std::fs;
+use crate::integration::common::{create_cargo_command, create_temp_dir_with_path, write_template_archive};
 
 #[test]
 fn test_help_command() {
@@ -28,6 +29,16 @@ fn test_list_command() {
         .stdout(predicate::str::contains("node - "));
 }
 
+#[test]
+fn test_list_unfree_command() {
+    let mut cmd = create_cargo_command();
+    cmd.args(["list", "--unfree"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hashi - "))
+        .stdout(predicate::str::contains("rust - ").not());
+}
+
 #[test]
 fn test_missing_template_argument() {
     let mut cmd = create_cargo_command();
@@ -35,4 +46,61 @@ fn test_missing_template_argument() {
         .assert()
         .failure()
         .stderr(predicate::str::contains("required"));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_diff_templates_shows_changed_lines() {
+    let (temp_dir, _) = create_temp_dir_with_path();
+    let newer = include_str!("../../../nix-parser/src/templates/go.nix").replace("goVersion = 24;", "goVersion = 25;");
+    let archive = write_template_archive(temp_dir.path(), &[("go.nix", &newer)]);
+
+    create_cargo_command()
+        .args(["diff-templates", "go", "--template-archive", &archive])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("--- embedded/go.nix"))
+        .stdout(predicate::str::contains("-      goVersion = 24; # Change this to update the whole stack"))
+        .stdout(predicate::str::contains("+      goVersion = 25; # Change this to update the whole stack"));
+
+    create_cargo_command()
+        .args(["diff-templates", "go"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No other version of 'go' to compare"));
+
+    create_cargo_command()
+        .args(["diff-templates", "rust", "--template-archive", &archive])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("has no 'rust' template to compare"));
+}
+
+#[test]
+fn test_new_template_writes_checkable_skeleton() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["new-template", "mylang", "--path", &temp_path])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("mylang.nix"));
+
+    let skeleton = temp_dir.path().join("mylang.nix");
+    let content = fs::read_to_string(&skeleton).unwrap();
+    assert!(content.contains("A Nix-flake-based mylang development environment"));
+    assert!(content.contains("packages = with pkgs; [ ];"));
+
+    create_cargo_command()
+        .args(["check", &skeleton.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("mylang.nix: ok"))
+        .stdout(predicate::str::contains("nixpkgs"));
+
+    // An existing template is never overwritten
+    create_cargo_command()
+        .args(["new-template", "mylang", "--path", &temp_path])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+}
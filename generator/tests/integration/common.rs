@@ -1,5 +1,6 @@
 use assert_cmd::Command;
 use std::fs;
+use std::path::Path;
 use std::process::Command as StdCommand;
 use tempfile::TempDir;
 
@@ -91,3 +92,40 @@ fn run_nix_validation(temp_path: &std::path::Path, test_name: &str) {
         }
     }
 }
+
+/// Write `templates` as `(relative path, content)` files and pack them into `templates.tar.gz`
+/// under `dir`, returning the archive path for `--template-archive`
+pub fn write_template_archive(dir: &Path, templates: &[(&str, &str)]) -> String {
+    let bundle = dir.join("bundle");
+    for (name, content) in templates {
+        let file = bundle.join(name);
+        fs::create_dir_all(file.parent().unwrap()).expect("Should create template directory");
+        fs::write(&file, content).expect("Should write template");
+    }
+    let archive = dir.join("templates.tar.gz");
+    let status = StdCommand::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&bundle)
+        .arg(".")
+        .status()
+        .expect("Should run tar");
+    assert!(status.success(), "tar should create the template archive");
+    archive.to_string_lossy().to_string()
+}
+
+/// Put a stand-in `alejandra` in `dir` that marks each file it is asked to format
+#[cfg(unix)]
+pub fn write_fake_alejandra(dir: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let fake_alejandra = dir.join("alejandra");
+    fs::write(
+        &fake_alejandra,
+        "#!/bin/sh\n[ \"$1\" = \"--version\" ] && exit 0\necho '# formatted by alejandra' >> \"$1\"\n",
+    )
+    .expect("Should write fake alejandra");
+    fs::set_permissions(&fake_alejandra, fs::Permissions::from_mode(0o755))
+        .expect("Should make fake alejandra executable");
+}
@@ -21,4 +21,46 @@ fn test_nonexistent_multi_template() {
         .assert()
         .failure()
         .stderr(predicate::str::contains("Template 'nonexistent' not found"));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_nonexistent_template_exit_code() {
+    let mut cmd = create_cargo_command();
+    cmd.arg("init")
+        .arg("nonexistent")
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn test_io_error_exit_code() {
+    let mut cmd = create_cargo_command();
+    cmd.arg("init")
+        .arg("go")
+        .arg("--shell-hook-file")
+        .arg("/nonexistent/setup.sh")
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn test_json_errors_reports_kind_and_location() {
+    let output = create_cargo_command()
+        .args(["init", "nonexistent", "--json-errors"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["kind"], "template_not_found");
+    assert!(error["message"].as_str().unwrap().contains("nonexistent"));
+
+    let output = create_cargo_command()
+        .args(["check", "--stdin", "--json-errors"])
+        .write_stdin("{\n  a = 1;\n  b = 2;\n} }")
+        .output()
+        .unwrap();
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["kind"], "parse_error");
+    assert_eq!(error["line"], 4);
+    assert_eq!(error["column"], 3);
+}
@@ -6,4 +6,5 @@ pub mod multi_template_tests;
 pub mod comprehensive_tests;
 pub mod combination_tests;
 pub mod stress_tests;
-pub mod coverage_tests;
\ No newline at end of file
+pub mod coverage_tests;
+pub mod option_tests;
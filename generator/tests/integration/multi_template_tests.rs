@@ -57,4 +57,47 @@ fn test_jvm_languages_combination() {
     );
     
     validate_flake_content_with_nix_check(&flake_content, "test-jvm-combination");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_rec_overlay_keeps_self_references() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("java,go")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    // gradle refers to the overlay's own jdk attribute, so the merged overlay must stay `rec`
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["overlays.default = final: prev: rec {", "jdk = prev.", "java = jdk;"],
+    );
+
+    validate_flake_content_with_nix_check(&flake_content, "test-rec-overlay-java-go");
+}
+
+#[test]
+fn test_non_rec_overlays_merge_without_rec() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("go,elm")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["overlays.default = final: prev: {"],
+    );
+    assert!(!flake_content.contains("rec {"), "Merged overlay should not be rec");
+
+    validate_flake_content_with_nix_check(&flake_content, "test-non-rec-overlay-go-elm");
+}
+
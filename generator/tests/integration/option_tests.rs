@@ -0,0 +1,957 @@
+use predicates::prelude::*;
+use std::fs;
+use crate::integration::common::{
+    create_cargo_command, create_temp_dir_with_path, assert_flake_exists_and_contains,
+    validate_flake_content_with_nix_check, write_template_archive
+};
+#[cfg(unix)]
+use crate::integration::common::write_fake_alejandra;
+
+#[test]
+fn test_pin_packages_single_template() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("node")
+        .arg("--pin-packages")
+        .arg("nodejs=22")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["nodejs = final.nodejs_22;", "self.overlays.default"],
+    );
+
+    validate_flake_content_with_nix_check(&flake_content, "test-pin-packages-node");
+}
+
+#[test]
+fn test_pin_packages_multi_template() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("python,go")
+        .arg("--pin-packages")
+        .arg("python3=attr:python312,go=1.22")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["python3 = final.python312;", "go = final.go_1_22;"],
+    );
+    assert_eq!(flake_content.matches("go = ").count(), 1, "Pin should replace the template's go binding");
+
+    validate_flake_content_with_nix_check(&flake_content, "test-pin-packages-python-go");
+}
+
+#[test]
+fn test_pin_packages_rejects_invalid_syntax() {
+    let mut cmd = create_cargo_command();
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("node")
+        .arg("--pin-packages")
+        .arg("nodejs")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected name=version"));
+}
+
+#[test]
+fn test_shell_hook_file_is_injected() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let hook_path = temp_dir.path().join("setup.sh");
+    fs::write(&hook_path, "echo \"project setup\"\nexport DATA_DIR=${PWD}/data\n")
+        .expect("Should write hook script");
+
+    cmd.arg("init")
+        .arg("go")
+        .arg("--shell-hook-file")
+        .arg(&hook_path)
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &[
+            "shellHook = ''\n              echo \"project setup\"\n              export DATA_DIR=''${PWD}/data\n            '';",
+        ],
+    );
+
+    validate_flake_content_with_nix_check(&flake_content, "test-shell-hook-file-go");
+}
+
+#[test]
+fn test_shell_hook_file_appends_after_template_hooks() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let hook_path = temp_dir.path().join("setup.sh");
+    fs::write(&hook_path, "echo \"after venv\"\n").expect("Should write hook script");
+
+    cmd.arg("init")
+        .arg("python,go")
+        .arg("--shell-hook-file")
+        .arg(&hook_path)
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &["source .venv/bin/activate"]);
+    let venv_pos = flake_content.find("pip install --upgrade pip").unwrap();
+    let hook_pos = flake_content.find("echo \"after venv\"").unwrap();
+    assert!(hook_pos > venv_pos, "User hook should run after the template hook");
+    assert_eq!(flake_content.matches("shellHook").count(), 1);
+}
+
+#[test]
+fn test_missing_shell_hook_file() {
+    let mut cmd = create_cargo_command();
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("go")
+        .arg("--shell-hook-file")
+        .arg("/nonexistent/setup.sh")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to read shell hook file"));
+}
+
+#[test]
+fn test_missing_nixfmt_notice() {
+    let mut cmd = create_cargo_command();
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+    let (_empty_bin, empty_bin_path) = create_temp_dir_with_path();
+
+    cmd.env("PATH", &empty_bin_path)
+        .arg("init")
+        .arg("go")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("nixfmt not found; skipping formatting").count(1));
+}
+
+#[test]
+fn test_quiet_suppresses_missing_nixfmt_notice() {
+    let mut cmd = create_cargo_command();
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+    let (_empty_bin, empty_bin_path) = create_temp_dir_with_path();
+
+    cmd.env("PATH", &empty_bin_path)
+        .arg("--quiet")
+        .arg("init")
+        .arg("go")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("nixfmt not found").not());
+}
+
+#[test]
+fn test_explain_annotates_flake() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("rust")
+        .arg("--explain")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["# inputs:", "# overlays.default:", "# devShells.default:", "rustToolchain"],
+    );
+    nix_parser::parse_nix_expr(&flake_content).expect("Annotated flake should still parse");
+
+    validate_flake_content_with_nix_check(&flake_content, "test-explain-rust");
+}
+
+#[test]
+fn test_no_comments_without_explain() {
+    let mut cmd = create_cargo_command();
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("rust,go")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &[]);
+    assert!(!flake_content.contains("# overlays.default:"));
+}
+
+
+#[test]
+fn test_markers_preserve_edits_outside_generated_region() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "go", "--markers", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["# BEGIN dev-template-generator", "# END dev-template-generator"],
+    );
+
+    // Add a user binding after the generated region, inside the top-level attribute set
+    let edited = flake_content.replacen(
+        "# END dev-template-generator\n",
+        "# END dev-template-generator\n  nixConfig.bash-prompt = \"[dev] \";\n",
+        1,
+    );
+    fs::write(temp_dir.path().join("flake.nix"), edited).unwrap();
+
+    create_cargo_command()
+        .args(["init", "go,node", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["nixConfig.bash-prompt = \"[dev] \";", "nodejs", "# END dev-template-generator"],
+    );
+    nix_parser::parse_nix_expr(&flake_content).expect("Spliced flake should still parse");
+}
+
+#[test]
+fn test_verbose_traces_extracted_sections() {
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust", "-v", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("[rust] inputs:"))
+        .stderr(predicate::str::contains("rust-overlay"))
+        .stderr(predicate::str::contains("rustToolchain"));
+}
+
+#[test]
+fn test_existing_additional_file_is_reported_as_skipped() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let toolchain_file = temp_dir.path().join("rust-toolchain.toml");
+    fs::write(&toolchain_file, "[toolchain]\nchannel = \"1.70.0\"\n").unwrap();
+
+    create_cargo_command()
+        .args(["init", "rust-toolchain", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped").and(predicate::str::contains("rust-toolchain.toml (already exists)")));
+
+    assert_eq!(
+        fs::read_to_string(&toolchain_file).unwrap(),
+        "[toolchain]\nchannel = \"1.70.0\"\n"
+    );
+}
+
+#[test]
+fn test_written_additional_file_is_reported() {
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust-toolchain", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Wrote").and(predicate::str::contains("rust-toolchain.toml")));
+}
+
+#[test]
+fn test_formatter_none_skips_formatting() {
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+    let (_empty_bin, empty_bin_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .env("PATH", &empty_bin_path)
+        .args(["init", "go", "--formatter", "none", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Formatted").not())
+        .stderr(predicate::str::contains("not found").not());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_formatter_alejandra_is_invoked() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let (bin_dir, bin_path) = create_temp_dir_with_path();
+    write_fake_alejandra(bin_dir.path());
+
+    create_cargo_command()
+        .env("PATH", &bin_path)
+        .args(["init", "go", "--formatter", "alejandra", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("with alejandra"));
+
+    assert_flake_exists_and_contains(&temp_dir, &["# formatted by alejandra"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_single_template_with_options_is_formatted_once() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let (bin_dir, bin_path) = create_temp_dir_with_path();
+    write_fake_alejandra(bin_dir.path());
+
+    create_cargo_command()
+        .env("PATH", &bin_path)
+        .args(["init", "go", "--explain", "--systems", "x86_64-linux", "--formatter", "alejandra", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Formatted the go flake with alejandra"));
+
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &["# formatted by alejandra"]);
+    assert_eq!(flake_content.matches("# formatted by alejandra").count(), 1, "{flake_content}");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_multi_template_formatting_leaves_edits_outside_markers_alone() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let (bin_dir, bin_path) = create_temp_dir_with_path();
+    write_fake_alejandra(bin_dir.path());
+
+    create_cargo_command()
+        .args(["init", "go", "--markers", "--formatter", "none", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_path = temp_dir.path().join("flake.nix");
+    let edited = fs::read_to_string(&flake_path).unwrap().replacen(
+        "# END dev-template-generator\n",
+        "# END dev-template-generator\n  nixConfig.bash-prompt = \"[dev] \";\n",
+        1,
+    );
+    fs::write(&flake_path, edited).unwrap();
+
+    create_cargo_command()
+        .env("PATH", &bin_path)
+        .args(["init", "go,node", "--formatter", "alejandra", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Formatted the go+node flake with alejandra"));
+
+    // Only the generated flake went through the formatter, not the user's bindings after the markers
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &["nodejs"]);
+    let (_, user) = flake_content.split_once("# END dev-template-generator").unwrap();
+    assert_eq!(user, "\n  nixConfig.bash-prompt = \"[dev] \";\n}\n", "{flake_content}");
+}
+
+#[test]
+fn test_banner_defaults_to_config_file() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let (config_dir, config_path) = create_temp_dir_with_path();
+    fs::create_dir_all(config_dir.path().join("nix-flake-generator")).unwrap();
+    fs::write(
+        config_dir.path().join("nix-flake-generator/config.toml"),
+        "banner = \"From the config file\"\n",
+    )
+    .unwrap();
+
+    create_cargo_command()
+        .env("XDG_CONFIG_HOME", &config_path)
+        .args(["init", "rust,go", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &[]);
+    assert_eq!(flake_content.lines().next(), Some("# From the config file"));
+
+    create_cargo_command()
+        .env("XDG_CONFIG_HOME", &config_path)
+        .args(["init", "rust,go", "--banner", "From the flag", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &[]);
+    assert_eq!(flake_content.lines().next(), Some("# From the flag"));
+    assert!(!flake_content.contains("From the config file"), "{flake_content}");
+}
+
+#[test]
+fn test_banner_is_first_line() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust,go", "--banner", "Generated by dev-template-generator {edit with care}", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &[]);
+    assert_eq!(
+        flake_content.lines().next(),
+        Some("# Generated by dev-template-generator {edit with care}")
+    );
+    nix_parser::parse_nix_expr(&flake_content).expect("Flake with banner should still parse");
+    validate_flake_content_with_nix_check(&flake_content, "test-banner");
+}
+
+#[test]
+fn test_banner_sits_above_markers() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "go", "--markers", "--banner", "Generated {file}", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let flake_content = assert_flake_exists_and_contains(&temp_dir, &["# BEGIN dev-template-generator"]);
+    let banner = flake_content.find("# Generated {file}").expect("banner should be present");
+    let begin = flake_content.find("# BEGIN dev-template-generator").unwrap();
+    assert!(banner < begin);
+    assert!(flake_content.starts_with("# Generated {file}\n{\n  # BEGIN dev-template-generator"));
+    nix_parser::parse_nix_expr(&flake_content).expect("Flake with banner and markers should still parse");
+}
+
+#[test]
+fn test_compat_writes_shell_nix_shim() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust", "--compat", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("shell.nix"));
+
+    assert_flake_exists_and_contains(&temp_dir, &["devShells"]);
+    let shim = fs::read_to_string(temp_dir.path().join("shell.nix")).unwrap();
+    assert!(shim.contains("flake-compat"));
+    assert!(shim.contains("devShells.${builtins.currentSystem}.default"));
+
+    // An existing shell.nix is left alone
+    fs::write(temp_dir.path().join("shell.nix"), "{ }").unwrap();
+    create_cargo_command()
+        .args(["init", "rust", "--compat", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped"));
+    assert_eq!(fs::read_to_string(temp_dir.path().join("shell.nix")).unwrap(), "{ }");
+}
+
+#[test]
+fn test_upgrade_rewrites_nixpkgs_ref() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    create_cargo_command()
+        .args(["upgrade", "--nixpkgs-ref", "nixos-24.05", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("github:NixOS/nixpkgs/nixos-24.05"));
+
+    let flake_content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["github:NixOS/nixpkgs/nixos-24.05", "github:oxalica/rust-overlay"],
+    );
+    assert!(!flake_content.contains("nixos-unstable"));
+}
+
+#[test]
+fn test_check_reads_flake_from_stdin() {
+    create_cargo_command()
+        .args(["check", "--stdin"])
+        .write_stdin(include_str!("../../../nix-parser/src/templates/go.nix"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<stdin>: ok"))
+        .stdout(predicate::str::contains("inputs: nixpkgs"))
+        .stdout(predicate::str::contains("packages: go, gotools, golangci-lint"));
+
+    create_cargo_command()
+        .args(["check", "--stdin"])
+        .write_stdin("{ description = ")
+        .assert()
+        .failure()
+        .code(4);
+}
+
+#[test]
+fn test_info_versions_reports_go_version() {
+    if std::process::Command::new("nix").arg("--version").output().is_err() {
+        println!("⚠️  Nix not available, skipping version lookup test");
+        return;
+    }
+
+    create_cargo_command()
+        .args(["info", "go", "--versions"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^  go: \d+\.\d+").unwrap());
+}
+
+#[test]
+fn test_info_versions_quiet_hides_missing_nix_notice() {
+    if std::process::Command::new("nix").arg("--version").output().is_ok() {
+        println!("⚠️  Nix available, skipping missing-nix notice test");
+        return;
+    }
+
+    create_cargo_command()
+        .args(["info", "go", "--versions"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("nix not found"));
+    create_cargo_command()
+        .args(["info", "go", "--versions", "--quiet"])
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn test_info_lists_template_packages() {
+    create_cargo_command()
+        .args(["info", "go"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("packages: go, gotools, golangci-lint"))
+        .stdout(predicate::str::contains("Versions").not());
+}
+
+#[test]
+fn test_packages_lists_merged_packages() {
+    create_cargo_command()
+        .args(["packages", "rust,go"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("rustToolchain\n"))
+        .stdout(predicate::str::contains("cargo-watch\n"))
+        .stdout(predicate::str::contains("gotools\n"));
+
+    let output = create_cargo_command()
+        .args(["packages", "go,go", "--json"])
+        .output()
+        .unwrap();
+    let packages: Vec<String> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(packages, vec!["go", "golangci-lint", "gotools"]);
+}
+
+#[test]
+fn test_init_list_files_writes_nothing() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust-toolchain", "--list-files", "--path", &temp_path])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("flake.nix (new)"))
+        .stdout(predicate::str::contains("rust-toolchain.toml (new)"));
+
+    assert!(!temp_dir.path().join("flake.nix").exists());
+    assert!(!temp_dir.path().join("rust-toolchain.toml").exists());
+
+    fs::write(temp_dir.path().join("flake.nix"), "{ }").unwrap();
+    create_cargo_command()
+        .args(["init", "rust-toolchain", "--list-files", "--path", &temp_path])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("flake.nix (overwrites existing file)"));
+}
+
+#[test]
+fn test_with_checks_adds_formatter_checks() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust,go", "--with-checks", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+
+    let content = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["checks = forEachSupportedSystem (", "rust-fmt = ", "go-fmt = ", "rustfmt --check", "gofmt -l"],
+    );
+    validate_flake_content_with_nix_check(&content, "rust,go --with-checks");
+}
+
+#[test]
+fn test_set_fills_template_placeholders() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "go", "--set", "NODE_VERSION=20", "--path", &temp_path])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No selected template has a placeholder named 'NODE_VERSION'"));
+    assert!(!temp_dir.path().join("flake.nix").exists());
+
+    create_cargo_command()
+        .args(["init", "go", "--set", "GO_VERSION=22", "--path", &temp_path])
+        .assert()
+        .success();
+    assert_flake_exists_and_contains(&temp_dir, &["goVersion = 22;"]);
+
+    create_cargo_command()
+        .args(["init", "go", "--set", "go-version=22", "--path", &temp_path])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid placeholder name"));
+}
+
+#[test]
+fn test_doctor_reports_embedded_templates_healthy() {
+    create_cargo_command()
+        .arg("doctor")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^go\s+1\s+3\s+ok$").unwrap())
+        .stdout(predicate::str::is_match(r"(?m)^rust\s+2\s+\d+\s+ok$").unwrap())
+        .stdout(predicate::str::contains("templates are healthy"));
+
+    // Hidden from help, since it is a diagnostic rather than a day-to-day command
+    create_cargo_command()
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("doctor").not());
+}
+
+#[test]
+fn test_doctor_skips_archived_templates() {
+    let (temp_dir, _) = create_temp_dir_with_path();
+    // Parses, but yields no packages, which doctor would report for an embedded template
+    let archive = write_template_archive(
+        temp_dir.path(),
+        &[(
+            "team/empty.nix",
+            r#"{
+  description = "Team environment without packages";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: { };
+}
+"#,
+        )],
+    );
+
+    create_cargo_command()
+        .args(["doctor", "--template-archive", &archive])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^go\s+1\s+3\s+ok$").unwrap())
+        .stdout(predicate::str::contains("empty").not());
+}
+
+#[test]
+fn test_split_writes_flake_per_template() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust,go", "--split", "--path", &temp_path])
+        .assert()
+        .success();
+
+    let rust = fs::read_to_string(temp_dir.path().join("rust/flake.nix")).unwrap();
+    assert!(rust.contains("rustToolchain"));
+    let go = fs::read_to_string(temp_dir.path().join("go/flake.nix")).unwrap();
+    assert!(go.contains("gotools"));
+
+    let root = assert_flake_exists_and_contains(
+        &temp_dir,
+        &[
+            "rust.url = \"path:./rust\";",
+            "go.url = \"path:./go\";",
+            "rust = rust.devShells.${system}.default;",
+            "go = go.devShells.${system}.default;",
+        ],
+    );
+    validate_flake_content_with_nix_check(&root, "rust,go --split");
+}
+
+#[test]
+fn test_split_compat_shim_uses_a_root_shell() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust,go", "--split", "--compat", "--path", &temp_path])
+        .assert()
+        .success();
+
+    // The root shim enters the root flake's default shell, which must exist for every system
+    let shim = fs::read_to_string(temp_dir.path().join("shell.nix")).unwrap();
+    assert!(shim.contains("flake.devShells.${builtins.currentSystem}.default"), "{shim}");
+    let root = assert_flake_exists_and_contains(
+        &temp_dir,
+        &["default = rust.inputs.nixpkgs.legacyPackages.${system}.mkShell {"],
+    );
+    let default_shell = root.split("default = rust.inputs").nth(1).unwrap();
+    assert!(default_shell.contains("rust.devShells.${system}.default"), "{root}");
+    assert!(default_shell.contains("go.devShells.${system}.default"), "{root}");
+    validate_flake_content_with_nix_check(&root, "rust,go --split --compat");
+}
+
+#[test]
+fn test_template_archive_loads_templates() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let archive = write_template_archive(
+        temp_dir.path(),
+        &[(
+            "team/hello.nix",
+            r#"{
+  description = "Team hello environment";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      supportedSystems = [ "x86_64-linux" ];
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs supportedSystems (system: f { pkgs = import nixpkgs { inherit system; }; });
+    in
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell { packages = with pkgs; [ hello ]; };
+      });
+    };
+}
+"#,
+        )],
+    );
+
+    create_cargo_command()
+        .args(["list", "--template-archive", &archive])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hello - Team hello environment"));
+
+    let out_path = format!("{temp_path}/out");
+    create_cargo_command()
+        .args(["init", "hello,go", "--template-archive", &archive, "--path", &out_path])
+        .assert()
+        .success();
+    let flake = fs::read_to_string(temp_dir.path().join("out/flake.nix")).unwrap();
+    assert!(flake.contains("hello"), "{flake}");
+    assert!(flake.contains("gotools"), "{flake}");
+
+    let broken = temp_dir.path().join("broken.tar.gz");
+    fs::write(&broken, "not an archive").unwrap();
+    create_cargo_command()
+        .args(["list", "--template-archive", &broken.to_string_lossy()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid template archive"));
+}
+
+#[test]
+fn test_upgrade_dry_run_leaves_flake_untouched() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "rust", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success();
+    let before = fs::read_to_string(temp_dir.path().join("flake.nix")).unwrap();
+
+    create_cargo_command()
+        .args(["upgrade", "--nixpkgs-ref", "nixos-24.05", "--dry-run", "--path"])
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("nixpkgs.url = \"github:NixOS/nixpkgs/nixos-24.05\""))
+        .stderr(predicate::str::contains("Would upgrade github:NixOS/nixpkgs/nixos-unstable"));
+
+    assert_eq!(fs::read_to_string(temp_dir.path().join("flake.nix")).unwrap(), before);
+}
+
+#[test]
+fn test_init_defaults_to_target_env_var() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let work_dir = temp_dir.path().join("work");
+    fs::create_dir_all(&work_dir).unwrap();
+
+    create_cargo_command()
+        .args(["init", "go"])
+        .env("DEV_TEMPLATE_TARGET", &temp_path)
+        .current_dir(&work_dir)
+        .assert()
+        .success();
+    assert_flake_exists_and_contains(&temp_dir, &["gotools"]);
+    assert!(!work_dir.join("flake.nix").exists());
+
+    // --path still wins over the environment
+    create_cargo_command()
+        .args(["init", "go", "--path", "."])
+        .env("DEV_TEMPLATE_TARGET", &temp_path)
+        .current_dir(&work_dir)
+        .assert()
+        .success();
+    assert!(work_dir.join("flake.nix").exists());
+}
+
+#[test]
+fn test_minimal_rejects_several_systems() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+
+    create_cargo_command()
+        .args(["init", "go", "--minimal", "--systems", "x86_64-linux", "--path", &temp_path])
+        .assert()
+        .success();
+    assert_flake_exists_and_contains(&temp_dir, &["devShells.x86_64-linux.default = pkgs.mkShell {"]);
+    let flake = fs::read_to_string(temp_dir.path().join("flake.nix")).unwrap();
+    assert!(!flake.contains("forEachSupportedSystem"), "{flake}");
+
+    create_cargo_command()
+        .args(["init", "go", "--minimal", "--systems", "x86_64-linux,aarch64-darwin", "--path", &temp_path])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--minimal supports a single system"));
+}
+
+#[test]
+fn test_sort_orders_inputs_and_packages() {
+    let packages_in = |sort: &str| {
+        let (temp_dir, temp_path) = create_temp_dir_with_path();
+        create_cargo_command()
+            .args(["init", "rust,go", "--path", &temp_path, "--sort", sort])
+            .assert()
+            .success();
+        let content = fs::read_to_string(temp_dir.path().join("flake.nix")).unwrap();
+        assert!(
+            content.find("nixpkgs.url").unwrap() < content.find("rust-overlay = {").unwrap(),
+            "{sort}: {content}"
+        );
+        let packages = content.split("packages = with pkgs; [").nth(1).unwrap().split(']').next().unwrap();
+        packages.split_whitespace().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    let alpha = packages_in("alpha");
+    let mut sorted = alpha.clone();
+    sorted.sort();
+    assert_eq!(alpha, sorted);
+    assert_eq!(packages_in("nixpkgs-first"), alpha);
+
+    // rust's packages as the rust template lists them, then go's
+    let source = packages_in("source");
+    assert_eq!(source.first().map(String::as_str), Some("rustToolchain"));
+    assert_eq!(&source[source.len() - 3..], ["go", "gotools", "golangci-lint"]);
+}
+
+#[test]
+fn test_merged_flake_binds_supported_systems() {
+    // flake-utils templates never define `supportedSystems`, but the merged flake's
+    // `forEachSupportedSystem` still refers to it
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let templates = [("jq-tools", "jq"), ("yq-tools", "yq")].map(|(name, package)| {
+        (
+            format!("{name}.nix"),
+            format!(
+                r#"{{
+  description = "{name} environment";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  inputs.flake-utils.url = "github:numtide/flake-utils";
+  outputs = {{ self, nixpkgs, flake-utils }}:
+    flake-utils.lib.eachDefaultSystem (system:
+      let pkgs = import nixpkgs {{ inherit system; }};
+      in {{
+        devShells.default = pkgs.mkShell {{ packages = with pkgs; [ {package} ]; }};
+      }});
+}}
+"#
+            ),
+        )
+    });
+    let templates: Vec<(&str, &str)> = templates.iter().map(|(name, content)| (name.as_str(), content.as_str())).collect();
+    let archive = write_template_archive(temp_dir.path(), &templates);
+
+    create_cargo_command()
+        .args(["init", "jq-tools,yq-tools", "--template-archive", &archive, "--path", &temp_path])
+        .assert()
+        .success();
+    let flake = fs::read_to_string(temp_dir.path().join("flake.nix")).unwrap();
+    let expr = nix_parser::parse_nix_expr(&flake).expect("Merged flake should parse");
+    let free = nix_parser::free_identifiers(&expr);
+    assert!(!free.contains("supportedSystems"), "supportedSystems is unbound in {flake}");
+    assert!(!free.contains("forEachSupportedSystem"), "forEachSupportedSystem is unbound in {flake}");
+}
+
+#[test]
+fn test_profile_minimal_yields_fewer_packages() {
+    let (temp_dir, temp_path) = create_temp_dir_with_path();
+    let archive = write_template_archive(
+        temp_dir.path(),
+        &[(
+            "golang.nix",
+            r#"{
+  description = "Go with a minimal profile";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      supportedSystems = [ "x86_64-linux" ];
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs supportedSystems (system: f { pkgs = import nixpkgs { inherit system; }; });
+    in
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell { packages = with pkgs; [ go gopls golangci-lint gotools ]; };
+        minimal = pkgs.mkShell { packages = with pkgs; [ go ]; };
+      });
+    };
+}
+"#,
+        )],
+    );
+
+    let packages_with = |profile: Option<&str>| {
+        let out = format!("{temp_path}/{}", profile.unwrap_or("default"));
+        let mut args = vec!["--template-archive", &archive, "init", "golang,node", "--path", &out];
+        if let Some(profile) = profile {
+            args.extend(["--profile", profile]);
+        }
+        create_cargo_command().args(&args).assert().success();
+        let content = fs::read_to_string(format!("{out}/flake.nix")).unwrap();
+        let packages = content.split("packages = with pkgs; [").nth(1).unwrap().split(']').next().unwrap();
+        packages.split_whitespace().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    let full = packages_with(None);
+    let minimal = packages_with(Some("minimal"));
+    assert!(minimal.len() < full.len(), "minimal: {minimal:?}, full: {full:?}");
+    assert!(minimal.contains(&"go".to_string()));
+    assert!(!minimal.contains(&"gopls".to_string()));
+    assert_eq!(packages_with(Some("full")), full);
+}
@@ -1,3 +1,4 @@
+use predicates::prelude::*;
 use std::fs;
 use crate::integration::common::{
     create_cargo_command, create_temp_dir_with_path, validate_flake_content_with_nix_check
@@ -50,4 +51,55 @@ fn test_large_combination(combo: &str) {
             );
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_large_merge_warns_about_package_count() {
+    let mut cmd = create_cargo_command();
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("rust,go,python,node,java,haskell")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("consider splitting it into multiple dev shells"));
+}
+
+#[test]
+fn test_small_merge_does_not_warn_about_package_count() {
+    let mut cmd = create_cargo_command();
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("rust,go")
+        .arg("--path")
+        .arg(&temp_path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("consider splitting").not());
+}
+
+#[test]
+fn test_package_warning_threshold_is_configurable() {
+    let mut cmd = create_cargo_command();
+    let (_temp_dir, temp_path) = create_temp_dir_with_path();
+
+    cmd.arg("init")
+        .arg("rust,go")
+        .arg("--path")
+        .arg(&temp_path)
+        .arg("--max-packages")
+        .arg("3")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("(threshold 3)"));
+
+    let (_quiet_dir, quiet_path) = create_temp_dir_with_path();
+    create_cargo_command()
+        .args(["init", "rust,go", "--quiet", "--max-packages", "3", "--path", &quiet_path])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("threshold").not());
+}
@@ -14,7 +14,7 @@ pub enum ParseError {
 pub enum NixExpr {
     // Literals
     String(String),
-    Path(String),
+    Path(String), // As written, including search paths such as `<nixpkgs>`
     Uri(String),
     Integer(i64),
     Float(f64),
@@ -92,12 +92,114 @@ pub enum NixExpr {
         from: Option<Box<NixExpr>>,
         attrs: Vec<String>,
     },
+
+    // Bracketed source the parser doesn't understand yet, kept verbatim
+    Raw(String),
 }
 
 impl NixExpr {
+    /// Equality that treats numerically equal integers and floats as equal, e.g. `1.0` and `1`,
+    /// and compares floats with a small relative tolerance, so a render/parse round trip that
+    /// reformats numbers still compares equal
+    pub fn structurally_eq(&self, other: &Self) -> bool {
+        use NixExpr::*;
+        match (self, other) {
+            (Integer(a), Integer(b)) => a == b,
+            (Float(a), Float(b)) => floats_eq(*a, *b),
+            (Integer(a), Float(b)) | (Float(b), Integer(a)) => floats_eq(*a as f64, *b),
+            (AttrSet { recursive: r1, bindings: b1 }, AttrSet { recursive: r2, bindings: b2 }) => {
+                r1 == r2 && bindings_eq(b1, b2)
+            }
+            (List(a), List(b)) => exprs_eq(a, b),
+            (InterpolatedString(a), InterpolatedString(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|pair| match pair {
+                        (StringPart::Literal(x), StringPart::Literal(y)) => x == y,
+                        (StringPart::Interpolation(x), StringPart::Interpolation(y)) => x.structurally_eq(y),
+                        _ => false,
+                    })
+            }
+            (Lambda { param: p1, body: b1 }, Lambda { param: p2, body: b2 }) => {
+                lambda_params_eq(p1, p2) && b1.structurally_eq(b2)
+            }
+            (FunctionCall { function: f1, argument: a1 }, FunctionCall { function: f2, argument: a2 }) => {
+                f1.structurally_eq(f2) && a1.structurally_eq(a2)
+            }
+            (LetIn { bindings: b1, body: e1 }, LetIn { bindings: b2, body: e2 }) => {
+                bindings_eq(b1, b2) && e1.structurally_eq(e2)
+            }
+            (With { env: v1, body: b1 }, With { env: v2, body: b2 })
+            | (Assert { condition: v1, body: b1 }, Assert { condition: v2, body: b2 }) => {
+                v1.structurally_eq(v2) && b1.structurally_eq(b2)
+            }
+            (
+                If { condition: c1, then_expr: t1, else_expr: e1 },
+                If { condition: c2, then_expr: t2, else_expr: e2 },
+            ) => c1.structurally_eq(c2) && t1.structurally_eq(t2) && e1.structurally_eq(e2),
+            (BinaryOp { left: l1, op: o1, right: r1 }, BinaryOp { left: l2, op: o2, right: r2 }) => {
+                o1 == o2 && l1.structurally_eq(l2) && r1.structurally_eq(r2)
+            }
+            (UnaryOp { op: o1, expr: e1 }, UnaryOp { op: o2, expr: e2 }) => o1 == o2 && e1.structurally_eq(e2),
+            (
+                Select { expr: e1, path: p1, default: d1 },
+                Select { expr: e2, path: p2, default: d2 },
+            ) => e1.structurally_eq(e2) && p1.structurally_eq(p2) && options_eq(d1, d2),
+            (HasAttr { expr: e1, path: p1 }, HasAttr { expr: e2, path: p2 }) => {
+                e1.structurally_eq(e2) && p1.structurally_eq(p2)
+            }
+            (Inherit { from: f1, attrs: a1 }, Inherit { from: f2, attrs: a2 }) => a1 == a2 && options_eq(f1, f2),
+            // Leaves without numbers or nested expressions
+            _ => self == other,
+        }
+    }
+
+    /// Render as a function argument, list item or select base, parenthesized unless it is a
+    /// single term that can't be split up by the surrounding expression
+    fn to_nix_operand(&self) -> String {
+        match self {
+            NixExpr::Integer(i) if *i < 0 => format!("({i})"),
+            NixExpr::Float(f) if *f < 0.0 => format!("({f})"),
+            NixExpr::String(_)
+            | NixExpr::Path(_)
+            | NixExpr::Uri(_)
+            | NixExpr::Integer(_)
+            | NixExpr::Float(_)
+            | NixExpr::Bool(_)
+            | NixExpr::Null
+            | NixExpr::Identifier(_)
+            | NixExpr::AttrSet { .. }
+            | NixExpr::List(_)
+            | NixExpr::InterpolatedString(_)
+            | NixExpr::Select { default: None, .. }
+            | NixExpr::Raw(_) => self.to_nix_string(),
+            _ => format!("({})", self.to_nix_string()),
+        }
+    }
+
+    /// How tightly the rendered expression binds, following Nix's operator precedence;
+    /// lambdas, `if`, `let`, `with` and `assert` extend as far right as possible
+    fn precedence(&self) -> u8 {
+        match self {
+            NixExpr::BinaryOp { op, .. } => op.precedence(),
+            NixExpr::UnaryOp { op: UnaryOperator::Not, .. } => 8,
+            NixExpr::UnaryOp { op: UnaryOperator::Negate, .. } => 13,
+            NixExpr::Integer(i) if *i < 0 => 13,
+            NixExpr::Float(f) if *f < 0.0 => 13,
+            NixExpr::HasAttr { .. } => 12,
+            NixExpr::FunctionCall { .. } | NixExpr::Select { default: Some(_), .. } => 14,
+            NixExpr::Lambda { .. }
+            | NixExpr::If { .. }
+            | NixExpr::LetIn { .. }
+            | NixExpr::With { .. }
+            | NixExpr::Assert { .. }
+            | NixExpr::Inherit { .. } => 0,
+            _ => 15,
+        }
+    }
+
     pub fn to_nix_string(&self) -> String {
         match self {
-            NixExpr::String(s) => format!("\"{}\"", s.replace("\"", "\\\"")),
+            NixExpr::String(s) => quote(s),
             NixExpr::Path(p) => p.clone(),
             NixExpr::Uri(u) => u.clone(),
             NixExpr::Integer(i) => i.to_string(),
@@ -105,6 +207,9 @@ impl NixExpr {
             NixExpr::Bool(b) => b.to_string(),
             NixExpr::Null => "null".to_string(),
             NixExpr::Identifier(name) => name.clone(),
+            NixExpr::AttrSet { recursive, bindings } if bindings.is_empty() => {
+                if *recursive { "rec { }" } else { "{ }" }.to_string()
+            }
             NixExpr::AttrSet { recursive, bindings } => {
                 let mut result = if *recursive { "rec {\n" } else { "{\n" }.to_string();
                 for binding in bindings {
@@ -120,7 +225,7 @@ impl NixExpr {
                         let path_str = binding.path.parts.iter()
                             .map(|part| match part {
                                 AttrPathPart::Identifier(id) => id.clone(),
-                                AttrPathPart::String(s) => format!("\"{s}\""),
+                                AttrPathPart::String(s) => quote_key(s),
                                 AttrPathPart::Interpolation(expr) => format!("${{{}}}", expr.to_nix_string()),
                             })
                             .collect::<Vec<_>>()
@@ -131,9 +236,10 @@ impl NixExpr {
                 result.push('}');
                 result
             }
+            NixExpr::List(items) if items.is_empty() => "[ ]".to_string(),
             NixExpr::List(items) => {
                 let items_str = items.iter()
-                    .map(|item| item.to_nix_string())
+                    .map(NixExpr::to_nix_operand)
                     .collect::<Vec<_>>()
                     .join(" ");
                 format!("[ {items_str} ]")
@@ -141,7 +247,7 @@ impl NixExpr {
             NixExpr::Lambda { param, body } => {
                 let param_str = match param {
                     LambdaParam::Identifier(name) => name.clone(),
-                    LambdaParam::Pattern { params, ellipsis } => {
+                    LambdaParam::Pattern { params, ellipsis, bind } => {
                         let param_list = params.iter()
                             .map(|p| if let Some(ref default) = p.default {
                                 format!("{} ? {}", p.name, default.to_nix_string())
@@ -150,28 +256,36 @@ impl NixExpr {
                             })
                             .collect::<Vec<_>>()
                             .join(", ");
-                        if *ellipsis {
+                        let pattern = if *ellipsis {
                             format!("{{ {param_list}, ... }}")
                         } else {
                             format!("{{ {param_list} }}")
+                        };
+                        match bind {
+                            Some(name) => format!("{name}@{pattern}"),
+                            None => pattern,
                         }
                     }
                 };
                 format!("{}: {}", param_str, body.to_nix_string())
             }
             NixExpr::FunctionCall { function, argument } => {
-                format!("{} {}", function.to_nix_string(), argument.to_nix_string())
+                let function = match **function {
+                    NixExpr::FunctionCall { .. } => function.to_nix_string(),
+                    _ => function.to_nix_operand(),
+                };
+                format!("{} {}", function, argument.to_nix_operand())
             }
             NixExpr::Select { expr, path, default } => {
                 let path_str = path.parts.iter()
                     .map(|part| match part {
                         AttrPathPart::Identifier(id) => id.clone(),
-                        AttrPathPart::String(s) => format!("\"{s}\""),
+                        AttrPathPart::String(s) => quote_key(s),
                         AttrPathPart::Interpolation(expr) => format!("${{{}}}", expr.to_nix_string()),
                     })
                     .collect::<Vec<_>>()
                     .join(".");
-                let base = format!("{}.{}", expr.to_nix_string(), path_str);
+                let base = format!("{}.{}", expr.to_nix_operand(), path_str);
                 if let Some(def) = default {
                     format!("{} or {}", base, def.to_nix_string())
                 } else {
@@ -195,8 +309,32 @@ impl NixExpr {
                     BinaryOperator::Or => "||",
                     BinaryOperator::Implication => "->",
                     BinaryOperator::Update => "//",
+                    BinaryOperator::PipeForward => "|>",
+                    BinaryOperator::PipeBackward => "<|",
                 };
-                format!("{} {} {}", left.to_nix_string(), op_str, right.to_nix_string())
+                // Parenthesize operands that bind looser, or equally tight on the non-associative side.
+                // `|>` and `<|` share a level but group in opposite directions, so a mix is always wrapped.
+                let parent = op.precedence();
+                let right_assoc = op.is_right_associative();
+                let operand = |expr: &NixExpr, grouped: bool| {
+                    let child = expr.precedence();
+                    let mixed = matches!(expr, NixExpr::BinaryOp { op: child_op, .. } if child_op.is_right_associative() != right_assoc);
+                    if child < parent || (child == parent && (!grouped || mixed)) {
+                        format!("({})", expr.to_nix_string())
+                    } else {
+                        expr.to_nix_string()
+                    }
+                };
+                let non_assoc = matches!(
+                    op,
+                    BinaryOperator::Eq | BinaryOperator::Ne | BinaryOperator::Lt | BinaryOperator::Le | BinaryOperator::Gt | BinaryOperator::Ge
+                );
+                format!(
+                    "{} {} {}",
+                    operand(left, !right_assoc && !non_assoc),
+                    op_str,
+                    operand(right, right_assoc)
+                )
             }
             NixExpr::If { condition, then_expr, else_expr } => {
                 format!("if {} then {} else {}", 
@@ -207,7 +345,7 @@ impl NixExpr {
             NixExpr::InterpolatedString(parts) => {
                 let content = parts.iter()
                     .map(|part| match part {
-                        StringPart::Literal(s) => s.clone(),
+                        StringPart::Literal(s) => escape_string(s),
                         StringPart::Interpolation(expr) => format!("${{{}}}", expr.to_nix_string()),
                     })
                     .collect::<String>();
@@ -219,7 +357,7 @@ impl NixExpr {
                     let path_str = binding.path.parts.iter()
                         .map(|part| match part {
                             AttrPathPart::Identifier(id) => id.clone(),
-                            AttrPathPart::String(s) => format!("\"{s}\""),
+                            AttrPathPart::String(s) => quote_key(s),
                             AttrPathPart::Interpolation(expr) => format!("${{{}}}", expr.to_nix_string()),
                         })
                         .collect::<Vec<_>>()
@@ -240,12 +378,102 @@ impl NixExpr {
                     format!("inherit {attr_list}")
                 }
             }
+            NixExpr::UnaryOp { op, expr } => {
+                let op_str = match op {
+                    UnaryOperator::Not => "!",
+                    UnaryOperator::Negate => "-",
+                };
+                match **expr {
+                    NixExpr::Integer(_) | NixExpr::Float(_) | NixExpr::Bool(_)
+                    | NixExpr::Identifier(_) | NixExpr::Select { .. } => {
+                        format!("{}{}", op_str, expr.to_nix_string())
+                    }
+                    _ => format!("{}({})", op_str, expr.to_nix_string()),
+                }
+            }
+            NixExpr::Raw(text) => text.clone(),
             // Add other cases as needed - for now, fall back to debug for unhandled cases
             _ => format!("(* unhandled: {self:?} *)"),
         }
     }
 }
 
+/// Render decoded string content as a double-quoted Nix string
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape_string(s))
+}
+
+/// Escape decoded text so it reads back unchanged inside a double-quoted string
+fn escape_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${")
+}
+
+/// Escape text for use inside an indented `''...''` string. A lone `'` right before an escaped
+/// `${` would otherwise fuse with it into `'''`, so it is written as `''\'`.
+pub fn escape_indented_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("''") {
+            escaped.push_str("'''");
+            rest = &rest[2..];
+        } else if rest.starts_with("'${") {
+            escaped.push_str("''\\'");
+            rest = &rest[1..];
+        } else if rest.starts_with("${") {
+            escaped.push_str("''${");
+            rest = &rest[2..];
+        } else {
+            escaped.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    escaped
+}
+
+/// Floats equal up to a relative tolerance covering formatting that drops trailing digits
+fn floats_eq(a: f64, b: f64) -> bool {
+    a == b || (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+}
+
+fn exprs_eq(a: &[NixExpr], b: &[NixExpr]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.structurally_eq(y))
+}
+
+fn options_eq(a: &Option<Box<NixExpr>>, b: &Option<Box<NixExpr>>) -> bool {
+    match (a, b) {
+        (Some(x), Some(y)) => x.structurally_eq(y),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn bindings_eq(a: &[Binding], b: &[Binding]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(x, y)| x.path.structurally_eq(&y.path) && x.value.structurally_eq(&y.value))
+}
+
+fn lambda_params_eq(a: &LambdaParam, b: &LambdaParam) -> bool {
+    match (a, b) {
+        (
+            LambdaParam::Pattern { params: p1, ellipsis: e1, bind: b1 },
+            LambdaParam::Pattern { params: p2, ellipsis: e2, bind: b2 },
+        ) => {
+            e1 == e2
+                && b1 == b2
+                && p1.len() == p2.len()
+                && p1.iter().zip(p2).all(|(x, y)| x.name == y.name && options_eq(&x.default, &y.default))
+        }
+        _ => a == b,
+    }
+}
+
+/// Quoted attribute names keep escapes other than `\"`, and any interpolations, as written,
+/// so only quotes need escaping again
+fn quote_key(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\\\""))
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StringPart {
     Literal(String),
@@ -258,6 +486,8 @@ pub enum LambdaParam {
     Pattern {
         params: Vec<PatternParam>,
         ellipsis: bool,
+        /// Name bound to the whole argument by an `@`-pattern, e.g. `inputs@{ ... }`
+        bind: Option<String>,
     },
 }
 
@@ -278,6 +508,28 @@ pub struct AttrPath {
     pub parts: Vec<AttrPathPart>,
 }
 
+impl AttrPath {
+    /// [`NixExpr::structurally_eq`] for attribute paths with interpolated parts
+    pub fn structurally_eq(&self, other: &Self) -> bool {
+        self.parts.len() == other.parts.len()
+            && self.parts.iter().zip(&other.parts).all(|pair| match pair {
+                (AttrPathPart::Interpolation(x), AttrPathPart::Interpolation(y)) => x.structurally_eq(y),
+                (x, y) => x == y,
+            })
+    }
+
+    pub fn to_nix_string(&self) -> String {
+        self.parts.iter()
+            .map(|part| match part {
+                AttrPathPart::Identifier(id) => id.clone(),
+                AttrPathPart::String(s) => quote_key(s),
+                AttrPathPart::Interpolation(expr) => format!("${{{}}}", expr.to_nix_string()),
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AttrPathPart {
     Identifier(String),
@@ -295,6 +547,34 @@ pub enum BinaryOperator {
     And, Or, Implication,
     // List/String
     Concat, Update,
+    // Function application (experimental pipe-operators feature)
+    PipeForward, PipeBackward,
+}
+
+impl BinaryOperator {
+    /// Binding strength, higher binds tighter
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::PipeForward | BinaryOperator::PipeBackward => 1,
+            BinaryOperator::Implication => 2,
+            BinaryOperator::Or => 3,
+            BinaryOperator::And => 4,
+            BinaryOperator::Eq | BinaryOperator::Ne => 5,
+            BinaryOperator::Lt | BinaryOperator::Le | BinaryOperator::Gt | BinaryOperator::Ge => 6,
+            BinaryOperator::Update => 7,
+            BinaryOperator::Add | BinaryOperator::Sub => 9,
+            BinaryOperator::Mul | BinaryOperator::Div => 10,
+            BinaryOperator::Concat => 11,
+        }
+    }
+
+    /// Whether `a op b op c` groups as `a op (b op c)`
+    pub(crate) fn is_right_associative(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Concat | BinaryOperator::Update | BinaryOperator::Implication | BinaryOperator::PipeBackward
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -315,10 +595,28 @@ pub struct FlakeData {
 pub struct FlakeFragments {
     pub header: String,
     pub inputs: HashMap<String, String>,
+    pub input_order: Vec<String>, // Input names in the order they are first declared
     pub overlays: HashMap<String, Vec<Binding>>, // Store AST bindings instead of strings
+    pub recursive_overlays: Vec<String>, // Names of overlays whose body was a `rec` attrset
     pub packages: Vec<String>,
     pub env_vars: HashMap<String, String>,
-    pub shell_hooks: Vec<String>,
+    pub shell_hooks: Vec<String>, // Hook source for a `''...''` string, with the template's indentation stripped
     pub allow_unfree: bool,
     pub let_bindings: HashMap<String, String>,
+    pub apps: HashMap<String, String>, // App name -> rendered `{ type = "app"; ... }` definition
+    pub package_outputs: HashMap<String, String>, // `packages.<system>.<name>` build outputs, rendered
+    pub passthrough: HashMap<String, NixExpr>, // Unrecognized top-level attributes, re-emitted verbatim
+    pub input_follows: HashMap<String, Vec<InputFollows>>, // Input name -> its `follows` overrides
+    pub inputs_from: Vec<String>, // Dev shell `inputsFrom` entries, rendered
+    pub shell_attrs: HashMap<String, NixExpr>, // Other recognized `mkShell` attributes, e.g. `hardeningDisable`
+    pub shell_packages: HashMap<String, Vec<String>>, // Dev shell name -> its own packages, e.g. `minimal` and `default`
+    pub input_overlays: HashMap<String, Vec<String>>, // Input name -> overlays applied from it, e.g. `rust-overlay.overlays.<name>`
+}
+
+/// `inputs.<input>.inputs.<path>.follows = "<target>"`, where `path` names the nested inputs.
+/// An empty `path` is a follows-only input, `inputs.<input>.follows = "<target>"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputFollows {
+    pub path: Vec<String>,
+    pub target: String,
 }
\ No newline at end of file
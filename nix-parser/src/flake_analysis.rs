@@ -3,6 +3,8 @@ use std::collections::HashMap;
 
 pub fn extract_flake_data(expr: &NixExpr) -> Result<FlakeData, ParseError> {
     match expr {
+        // `let ... in { ... }` around the whole flake
+        NixExpr::LetIn { body, .. } => extract_flake_data(body),
         NixExpr::AttrSet { bindings, .. } => {
             let mut flake = FlakeData {
                 description: None,
@@ -43,39 +45,70 @@ pub fn extract_fragments_from_expr(expr: &NixExpr) -> Result<FlakeFragments, Par
     let mut fragments = FlakeFragments {
         header: String::new(),
         inputs: HashMap::new(),
+        input_order: Vec::new(),
         overlays: HashMap::new(),
+        recursive_overlays: Vec::new(),
         packages: Vec::new(),
         env_vars: HashMap::new(),
         shell_hooks: Vec::new(),
         allow_unfree: false,
         let_bindings: HashMap::new(),
+        apps: HashMap::new(),
+        package_outputs: HashMap::new(),
+        passthrough: HashMap::new(),
+        input_follows: HashMap::new(),
+        inputs_from: Vec::new(),
+        shell_attrs: HashMap::new(),
+        shell_packages: HashMap::new(),
+        input_overlays: HashMap::new(),
     };
+
+    // A flake wrapped in `let ... in { ... }`: its bindings stay in scope for the outputs
+    let mut expr = expr;
+    let mut scope = LetScope::new();
+    while let NixExpr::LetIn { bindings, body } = expr {
+        extract_let_bindings(bindings, &mut fragments.let_bindings);
+        scope = extend_scope(&scope, bindings);
+        expr = body;
+    }
     
     if let NixExpr::AttrSet { bindings, .. } = expr {
         for binding in bindings {
+            // `inherit` can't be resolved statically; its synthesized `inherit` key is not a real attribute
+            if let NixExpr::Inherit { .. } = binding.value {
+                continue;
+            }
             match &binding.path.parts[..] {
                 [AttrPathPart::Identifier(name)] => match name.as_str() {
                     "description" => {
-                        if let NixExpr::String(desc) = &binding.value {
-                            fragments.header = desc.clone();
+                        if let Some(desc) = description_text(&binding.value, &scope) {
+                            fragments.header = desc;
                         }
                     }
                     "inputs" => {
-                        extract_inputs_from_expr(&binding.value, &mut fragments.inputs);
+                        extract_inputs_from_expr(&binding.value, &mut fragments);
+                        collect_input_follows(Vec::new(), &binding.value, &mut fragments.input_follows);
                     }
                     "outputs" => {
-                        extract_outputs_from_expr(&binding.value, &mut fragments);
+                        extract_outputs_from_expr(&binding.value, &mut fragments, &scope);
+                    }
+                    _ => {
+                        fragments.passthrough.insert(name.clone(), binding.value.clone());
                     }
-                    _ => {}
                 },
                 // Handle multi-part paths like "inputs.nixpkgs.url"
-                [AttrPathPart::Identifier(first), AttrPathPart::Identifier(second), AttrPathPart::Identifier(third)] => {
-                    if first == "inputs" && third == "url" {
+                [AttrPathPart::Identifier(first), AttrPathPart::Identifier(second), AttrPathPart::Identifier(third)]
+                    if first == "inputs" && third == "url" => {
                         if let NixExpr::String(url) = &binding.value {
-                            fragments.inputs.insert(second.clone(), url.clone());
+                            insert_input(&mut fragments, second, url);
                         }
+                    },
+                // Flat follows such as "inputs.crane.inputs.nixpkgs.follows"
+                [AttrPathPart::Identifier(first), rest @ ..] if first == "inputs" => {
+                    if let Some(path) = identifier_path(rest) {
+                        collect_input_follows(path, &binding.value, &mut fragments.input_follows);
                     }
-                },
+                }
                 _ => {}
             }
         }
@@ -84,31 +117,83 @@ pub fn extract_fragments_from_expr(expr: &NixExpr) -> Result<FlakeFragments, Par
     Ok(fragments)
 }
 
-fn extract_inputs_from_expr(expr: &NixExpr, inputs: &mut HashMap<String, String>) {
+// Collects `<input>.inputs.<name>[.inputs.<name>...].follows = "<target>"` below the
+// top-level `inputs`, whether written as nested attribute sets or flat paths, and
+// follows-only inputs `<input>.follows = "<target>"` as an entry with an empty path
+fn collect_input_follows(path: Vec<String>, expr: &NixExpr, follows: &mut HashMap<String, Vec<InputFollows>>) {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                if let Some(parts) = identifier_path(&binding.path.parts) {
+                    let mut child = path.clone();
+                    child.extend(parts);
+                    collect_input_follows(child, &binding.value, follows);
+                }
+            }
+        }
+        NixExpr::String(target) => {
+            let [input, nested @ .., last] = &path[..] else {
+                return;
+            };
+            if last != "follows" || nested.len() % 2 != 0 {
+                return;
+            }
+            let names: Option<Vec<String>> = nested
+                .chunks(2)
+                .map(|pair| (pair[0] == "inputs").then(|| pair[1].clone()))
+                .collect();
+            if let Some(names) = names {
+                follows.entry(input.clone()).or_default().push(InputFollows {
+                    path: names,
+                    target: target.clone(),
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+// Attribute path as plain names, or `None` if it contains an interpolation
+fn identifier_path(parts: &[AttrPathPart]) -> Option<Vec<String>> {
+    parts
+        .iter()
+        .map(|part| match part {
+            AttrPathPart::Identifier(name) | AttrPathPart::String(name) => Some(name.clone()),
+            AttrPathPart::Interpolation(_) => None,
+        })
+        .collect()
+}
+
+// Records the input's URL, remembering the order inputs are first declared in
+fn insert_input(fragments: &mut FlakeFragments, name: &str, url: &str) {
+    if !fragments.input_order.iter().any(|seen| seen == name) {
+        fragments.input_order.push(name.to_string());
+    }
+    fragments.inputs.insert(name.to_string(), url.to_string());
+}
+
+fn extract_inputs_from_expr(expr: &NixExpr, fragments: &mut FlakeFragments) {
     if let NixExpr::AttrSet { bindings, .. } = expr {
         for binding in bindings {
             if let [AttrPathPart::Identifier(input_name)] = &binding.path.parts[..] {
-                match &binding.value {
-                    // Simple format: nixpkgs.url = "...";
-                    NixExpr::AttrSet { bindings, .. } => {
-                        for url_binding in bindings {
-                            if let [AttrPathPart::Identifier(attr)] = &url_binding.path.parts[..] {
-                                if attr == "url" {
-                                    if let NixExpr::String(url) = &url_binding.value {
-                                        inputs.insert(input_name.clone(), url.clone());
-                                    }
+                // Simple format: nixpkgs.url = "...";
+                if let NixExpr::AttrSet { bindings, .. } = &binding.value {
+                    for url_binding in bindings {
+                        if let [AttrPathPart::Identifier(attr)] = &url_binding.path.parts[..] {
+                            if attr == "url" {
+                                if let NixExpr::String(url) = &url_binding.value {
+                                    insert_input(fragments, input_name, url);
                                 }
                             }
                         }
                     }
-                    _ => {}
                 }
             } else if binding.path.parts.len() == 2 {
                 // Handle nixpkgs.url format
                 if let [AttrPathPart::Identifier(input_name), AttrPathPart::Identifier(attr)] = &binding.path.parts[..] {
                     if attr == "url" {
                         if let NixExpr::String(url) = &binding.value {
-                            inputs.insert(input_name.clone(), url.clone());
+                            insert_input(fragments, input_name, url);
                         }
                     }
                 }
@@ -117,16 +202,95 @@ fn extract_inputs_from_expr(expr: &NixExpr, inputs: &mut HashMap<String, String>
     }
 }
 
-fn extract_outputs_from_expr(expr: &NixExpr, fragments: &mut FlakeFragments) {
+fn extract_outputs_from_expr(expr: &NixExpr, fragments: &mut FlakeFragments, scope: &LetScope) {
     // Outputs is typically a lambda function
-    if let NixExpr::Lambda { body, .. } = expr {
-        extract_outputs_body(body, fragments);
+    if let NixExpr::Lambda { param, body } = expr {
+        match param {
+            // `inputs@{ self, nixpkgs, ... }`: read `inputs.nixpkgs` as the destructured `nixpkgs`
+            LambdaParam::Pattern { bind: Some(bind), .. } => {
+                extract_outputs_body(&resolve_through_bind(body, bind), fragments, scope);
+            }
+            _ => extract_outputs_body(body, fragments, scope),
+        }
     }
     
     // Also search for config.allowUnfree patterns anywhere in the expression
     if detect_allow_unfree(expr) {
         fragments.allow_unfree = true;
     }
+
+    // Which overlay each input contributes to `import nixpkgs { overlays = [ ... ]; }`
+    find_input_overlays_in_expr(expr, &mut fragments.input_overlays);
+}
+
+// Rewrites `<bind>.<name>...` selects to `<name>...`, leaving scopes that shadow `bind` untouched
+fn resolve_through_bind(expr: &NixExpr, bind: &str) -> NixExpr {
+    let resolve = |expr: &NixExpr| Box::new(resolve_through_bind(expr, bind));
+    let resolve_bindings = |bindings: &[Binding]| -> Vec<Binding> {
+        bindings
+            .iter()
+            .map(|binding| Binding { path: binding.path.clone(), value: resolve_through_bind(&binding.value, bind) })
+            .collect()
+    };
+    match expr {
+        NixExpr::Select { expr: base, path, default } => {
+            let default = default.as_deref().map(resolve);
+            match (base.as_ref(), &path.parts[..]) {
+                (NixExpr::Identifier(name), [AttrPathPart::Identifier(input), rest @ ..]) if name == bind => {
+                    let input = NixExpr::Identifier(input.clone());
+                    if rest.is_empty() && default.is_none() {
+                        input
+                    } else if rest.is_empty() {
+                        // `inputs.foo or x` keeps the fallback for a missing input
+                        NixExpr::Select { expr: base.clone(), path: path.clone(), default }
+                    } else {
+                        NixExpr::Select { expr: Box::new(input), path: AttrPath { parts: rest.to_vec() }, default }
+                    }
+                }
+                _ => NixExpr::Select { expr: resolve(base), path: path.clone(), default },
+            }
+        }
+        NixExpr::AttrSet { recursive, bindings } => NixExpr::AttrSet { recursive: *recursive, bindings: resolve_bindings(bindings) },
+        NixExpr::List(items) => NixExpr::List(items.iter().map(|item| resolve_through_bind(item, bind)).collect()),
+        NixExpr::InterpolatedString(parts) => NixExpr::InterpolatedString(
+            parts
+                .iter()
+                .map(|part| match part {
+                    StringPart::Interpolation(expr) => StringPart::Interpolation(resolve(expr)),
+                    StringPart::Literal(text) => StringPart::Literal(text.clone()),
+                })
+                .collect(),
+        ),
+        NixExpr::Lambda { param, .. } if lambda_binds(param, bind) => expr.clone(),
+        NixExpr::Lambda { param, body } => NixExpr::Lambda { param: param.clone(), body: resolve(body) },
+        NixExpr::FunctionCall { function, argument } => NixExpr::FunctionCall { function: resolve(function), argument: resolve(argument) },
+        NixExpr::LetIn { bindings, .. }
+            if bindings.iter().any(|b| matches!(&b.path.parts[..], [AttrPathPart::Identifier(name)] if name == bind)) =>
+        {
+            expr.clone()
+        }
+        NixExpr::LetIn { bindings, body } => NixExpr::LetIn { bindings: resolve_bindings(bindings), body: resolve(body) },
+        NixExpr::With { env, body } => NixExpr::With { env: resolve(env), body: resolve(body) },
+        NixExpr::If { condition, then_expr, else_expr } => NixExpr::If {
+            condition: resolve(condition),
+            then_expr: resolve(then_expr),
+            else_expr: resolve(else_expr),
+        },
+        NixExpr::Assert { condition, body } => NixExpr::Assert { condition: resolve(condition), body: resolve(body) },
+        NixExpr::BinaryOp { left, op, right } => NixExpr::BinaryOp { left: resolve(left), op: op.clone(), right: resolve(right) },
+        NixExpr::UnaryOp { op, expr } => NixExpr::UnaryOp { op: op.clone(), expr: resolve(expr) },
+        NixExpr::HasAttr { expr, path } => NixExpr::HasAttr { expr: resolve(expr), path: path.clone() },
+        _ => expr.clone(),
+    }
+}
+
+fn lambda_binds(param: &LambdaParam, name: &str) -> bool {
+    match param {
+        LambdaParam::Identifier(param) => param == name,
+        LambdaParam::Pattern { params, bind, .. } => {
+            bind.as_deref() == Some(name) || params.iter().any(|param| param.name == name)
+        }
+    }
 }
 
 fn detect_allow_unfree(expr: &NixExpr) -> bool {
@@ -173,66 +337,172 @@ fn detect_allow_unfree(expr: &NixExpr) -> bool {
     }
 }
 
-fn extract_outputs_body(expr: &NixExpr, fragments: &mut FlakeFragments) {
+fn find_input_overlays_in_expr(expr: &NixExpr, input_overlays: &mut HashMap<String, Vec<String>>) {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                if let ([AttrPathPart::Identifier(name)], NixExpr::List(items)) = (&binding.path.parts[..], &binding.value) {
+                    if name == "overlays" {
+                        for (input, overlay) in items.iter().filter_map(input_overlay_reference) {
+                            let overlays = input_overlays.entry(input).or_default();
+                            if !overlays.contains(&overlay) {
+                                overlays.push(overlay);
+                            }
+                        }
+                        continue;
+                    }
+                }
+                find_input_overlays_in_expr(&binding.value, input_overlays);
+            }
+        }
+        NixExpr::Lambda { body, .. } | NixExpr::With { body, .. } => find_input_overlays_in_expr(body, input_overlays),
+        NixExpr::LetIn { bindings, body } => {
+            for binding in bindings {
+                find_input_overlays_in_expr(&binding.value, input_overlays);
+            }
+            find_input_overlays_in_expr(body, input_overlays);
+        }
+        NixExpr::FunctionCall { function, argument } => {
+            find_input_overlays_in_expr(function, input_overlays);
+            find_input_overlays_in_expr(argument, input_overlays);
+        }
+        NixExpr::List(items) => {
+            for item in items {
+                find_input_overlays_in_expr(item, input_overlays);
+            }
+        }
+        NixExpr::If { then_expr, else_expr, .. } => {
+            find_input_overlays_in_expr(then_expr, input_overlays);
+            find_input_overlays_in_expr(else_expr, input_overlays);
+        }
+        _ => {}
+    }
+}
+
+// `<input>.overlays.<name>` or `inputs.<input>.overlays.<name>` as `(input, name)`; the flake's own
+// `self.overlays.*` is not an input
+fn input_overlay_reference(expr: &NixExpr) -> Option<(String, String)> {
+    // The parser nests one `Select` per `.`, so walk down to the base collecting the path
+    let mut parts = Vec::new();
+    let mut expr = expr;
+    while let NixExpr::Select { expr: base, path, default: None } = expr {
+        parts.splice(0..0, identifier_path(&path.parts)?);
+        expr = base;
+    }
+    let NixExpr::Identifier(base) = expr else {
+        return None;
+    };
+    let (input, overlay) = match &parts[..] {
+        [input, overlays, overlay] if base == "inputs" && overlays == "overlays" => (input, overlay),
+        [overlays, overlay] if overlays == "overlays" => (base, overlay),
+        _ => return None,
+    };
+    (input != "self").then(|| (input.clone(), overlay.clone()))
+}
+
+fn extract_outputs_body(expr: &NixExpr, fragments: &mut FlakeFragments, scope: &LetScope) {
     match expr {
         NixExpr::LetIn { bindings, body } => {
             // Extract let bindings first
             extract_let_bindings(bindings, &mut fragments.let_bindings);
-            // Then process the body
-            extract_outputs_body(body, fragments);
+            // Then process the body, which may be another stacked let block
+            extract_outputs_body(body, fragments, &extend_scope(scope, bindings));
         }
         NixExpr::AttrSet { bindings, .. } => {
             for binding in bindings {
                 match &binding.path.parts[..] {
                     [AttrPathPart::Identifier(name)] => match name.as_str() {
                         "inputs" => {
-                            extract_inputs_from_expr(&binding.value, &mut fragments.inputs);
+                            extract_inputs_from_expr(&binding.value, fragments);
                         }
                         "overlays" => {
                             extract_overlays_from_expr(&binding.value, fragments);
                         }
-                        "devShells" => {
-                            extract_devshells_from_expr(&binding.value, fragments);
+                        // `devShell` is the older singular form, common in flake-utils flakes
+                        "devShells" | "devShell" => {
+                            extract_devshells_from_expr(&binding.value, fragments, scope, None);
+                        }
+                        "apps" => {
+                            find_apps_in_expr(&binding.value, &mut fragments.apps);
+                        }
+                        "packages" => {
+                            find_package_outputs_in_expr(&binding.value, &mut fragments.package_outputs);
                         }
                         _ => {}
                     },
                     // Handle nested paths like "overlays.default" and "inputs.nixpkgs.url"
-                    [AttrPathPart::Identifier(first), AttrPathPart::Identifier(second)] => {
-                        if first == "overlays" {
-                            // Extract the overlay body bindings (inside the lambda)
-                            let overlay_bindings = extract_overlay_bindings(&binding.value);
-                            fragments.overlays.insert(second.clone(), overlay_bindings);
-                        }
-                    },
+                    [AttrPathPart::Identifier(first), AttrPathPart::Identifier(second)]
+                        if first == "overlays" => {
+                            insert_overlay(second, &binding.value, fragments);
+                        },
+                    [AttrPathPart::Identifier(first), rest @ ..] if first == "packages" => {
+                        find_package_outputs_in_binding(rest, &binding.value, &mut fragments.package_outputs);
+                    }
+                    [AttrPathPart::Identifier(first), .., AttrPathPart::Identifier(last)] if first == "devShells" => {
+                        extract_devshells_from_expr(&binding.value, fragments, scope, Some(last));
+                    }
                     // Handle inputs.nixpkgs.url format
-                    [AttrPathPart::Identifier(first), AttrPathPart::Identifier(second), AttrPathPart::Identifier(third)] => {
-                        if first == "inputs" && third == "url" {
+                    [AttrPathPart::Identifier(first), AttrPathPart::Identifier(second), AttrPathPart::Identifier(third)]
+                        if first == "inputs" && third == "url" => {
                             if let NixExpr::String(url) = &binding.value {
-                                fragments.inputs.insert(second.clone(), url.clone());
+                                insert_input(fragments, second, url);
                             }
-                        }
-                    },
+                        },
                     _ => {}
                 }
             }
         }
+        // flake-utils: `flake-utils.lib.eachDefaultSystem (system: { ... })`
+        NixExpr::FunctionCall { function, argument } if is_flake_utils_wrapper(function) => {
+            if let NixExpr::Lambda { body, .. } = argument.as_ref() {
+                extract_outputs_body(body, fragments, scope);
+            }
+        }
+        // Per-system outputs combined with system-independent ones, e.g. `eachDefaultSystem (...) // { overlays = ...; }`
+        NixExpr::BinaryOp { left, op: BinaryOperator::Update, right } => {
+            extract_outputs_body(left, fragments, scope);
+            extract_outputs_body(right, fragments, scope);
+        }
         _ => {}
     }
 }
 
+// Matches `flake-utils.lib.eachDefaultSystem` and `flake-utils.lib.eachSystem systems`,
+// including the bare `eachDefaultSystem` brought into scope with `with` or `inherit`
+fn is_flake_utils_wrapper(expr: &NixExpr) -> bool {
+    let is_each_system = |name: &str| name == "eachDefaultSystem" || name == "eachSystem";
+    match expr {
+        NixExpr::Identifier(name) => is_each_system(name),
+        NixExpr::Select { path, .. } => matches!(
+            path.parts.last(),
+            Some(AttrPathPart::Identifier(name)) if is_each_system(name)
+        ),
+        // `eachSystem [ ... ]` is applied to the system list before the lambda
+        NixExpr::FunctionCall { function, .. } => is_flake_utils_wrapper(function),
+        _ => false,
+    }
+}
+
 fn extract_overlays_from_expr(expr: &NixExpr, fragments: &mut FlakeFragments) {
     if let NixExpr::AttrSet { bindings, .. } = expr {
         for binding in bindings {
             if let [AttrPathPart::Identifier(overlay_name)] = &binding.path.parts[..] {
-                // Extract the overlay body bindings (inside the lambda)
-                let overlay_bindings = extract_overlay_bindings(&binding.value);
-                fragments.overlays.insert(overlay_name.clone(), overlay_bindings);
+                insert_overlay(overlay_name, &binding.value, fragments);
             }
         }
     }
 }
 
-fn extract_overlay_bindings(expr: &NixExpr) -> Vec<Binding> {
+fn insert_overlay(name: &str, expr: &NixExpr, fragments: &mut FlakeFragments) {
+    // Extract the overlay body bindings (inside the lambda)
+    let (overlay_bindings, recursive) = extract_overlay_bindings(expr);
+    if recursive && !fragments.recursive_overlays.iter().any(|n| n == name) {
+        fragments.recursive_overlays.push(name.to_string());
+    }
+    fragments.overlays.insert(name.to_string(), overlay_bindings);
+}
+
+fn extract_overlay_bindings(expr: &NixExpr) -> (Vec<Binding>, bool) {
     match expr {
         // Handle final: prev: { ... } or final: prev: rec { ... }
         NixExpr::Lambda { body, .. } => {
@@ -249,20 +519,57 @@ fn extract_overlay_bindings(expr: &NixExpr) -> Vec<Binding> {
     }
 }
 
-fn extract_overlay_attrset_bindings(expr: &NixExpr) -> Vec<Binding> {
+// Returns the overlay's output bindings along with whether the overlay attrset was `rec`.
+// Helpers from a `let` around the attrset are not outputs; they are kept in a `let`
+// around each output value that uses them.
+fn extract_overlay_attrset_bindings(expr: &NixExpr) -> (Vec<Binding>, bool) {
     match expr {
-        NixExpr::AttrSet { bindings, .. } => bindings.clone(),
-        NixExpr::LetIn { bindings, body } => {
-            // For let-in expressions in overlays, we need to collect both let bindings and body bindings
-            let mut result = bindings.clone();
-            result.extend(extract_overlay_attrset_bindings(body));
-            result
+        NixExpr::AttrSet { recursive, bindings } => (bindings.clone(), *recursive),
+        NixExpr::LetIn { bindings: helpers, body } => {
+            let helper_names: Vec<&String> = helpers
+                .iter()
+                .filter_map(|helper| match &helper.path.parts[..] {
+                    [AttrPathPart::Identifier(name)] => Some(name),
+                    _ => None,
+                })
+                .collect();
+            let (body_bindings, recursive) = extract_overlay_attrset_bindings(body);
+            let bindings = body_bindings
+                .into_iter()
+                .map(|binding| {
+                    let free = crate::free_identifiers(&binding.value);
+                    if !helper_names.iter().any(|name| free.contains(*name)) {
+                        return binding;
+                    }
+                    Binding {
+                        path: binding.path,
+                        value: NixExpr::LetIn {
+                            bindings: helpers.clone(),
+                            body: Box::new(binding.value),
+                        },
+                    }
+                })
+                .collect();
+            (bindings, recursive)
         }
-        _ => Vec::new(),
+        _ => (Vec::new(), false),
     }
 }
 
 
+// Let bindings visible at a point in the outputs, used to resolve indirect package lists
+type LetScope = HashMap<String, NixExpr>;
+
+fn extend_scope(scope: &LetScope, bindings: &[Binding]) -> LetScope {
+    let mut scope = scope.clone();
+    for binding in bindings {
+        if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
+            scope.insert(name.clone(), binding.value.clone());
+        }
+    }
+    scope
+}
+
 fn extract_let_bindings(bindings: &[Binding], let_bindings: &mut HashMap<String, String>) {
     for binding in bindings {
         if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
@@ -281,130 +588,508 @@ fn is_simple_binding(expr: &NixExpr) -> bool {
         NixExpr::Integer(_) | NixExpr::Float(_) | NixExpr::Bool(_) | NixExpr::String(_) => true,
         // Simple identifiers
         NixExpr::Identifier(_) => true,
+        // Negative numeric literals like `-1`
+        NixExpr::UnaryOp { op: UnaryOperator::Negate, expr } => {
+            matches!(**expr, NixExpr::Integer(_) | NixExpr::Float(_))
+        }
+        // Strings whose interpolations are themselves simple, like "pre-${version}"
+        NixExpr::InterpolatedString(parts) => parts.iter().all(|part| match part {
+            StringPart::Literal(_) => true,
+            StringPart::Interpolation(expr) => is_simple_binding(expr),
+        }),
         // Simple lists of identifiers/literals
         NixExpr::List(items) => items.iter().all(is_simple_binding),
+        // Conditionals choosing between simple values, like `if stable then "stable" else "beta"`
+        NixExpr::If { condition, then_expr, else_expr } => {
+            is_simple_condition(condition) && is_simple_binding(then_expr) && is_simple_binding(else_expr)
+        }
         // Skip complex expressions like lambdas, function calls, etc.
         _ => false,
     }
 }
 
-fn extract_devshells_from_expr(expr: &NixExpr, fragments: &mut FlakeFragments) {
+// A simple value, or a comparison or boolean combination of simple values
+fn is_simple_condition(expr: &NixExpr) -> bool {
+    match expr {
+        NixExpr::BinaryOp { left, op, right } => {
+            matches!(op, BinaryOperator::Eq | BinaryOperator::Ne | BinaryOperator::And | BinaryOperator::Or)
+                && is_simple_condition(left)
+                && is_simple_condition(right)
+        }
+        NixExpr::UnaryOp { op: UnaryOperator::Not, expr } => is_simple_condition(expr),
+        _ => is_simple_binding(expr),
+    }
+}
+
+// Collects `name = { type = "app"; ... };` definitions, looking through
+// forEachSupportedSystem calls and per-system attribute sets
+fn find_apps_in_expr(expr: &NixExpr, apps: &mut HashMap<String, String>) {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                if is_app_definition(&binding.value) {
+                    if let Some(AttrPathPart::Identifier(name)) = binding.path.parts.last() {
+                        apps.insert(name.clone(), binding.value.to_nix_string());
+                    }
+                } else {
+                    find_apps_in_expr(&binding.value, apps);
+                }
+            }
+        }
+        NixExpr::FunctionCall { argument, .. } => find_apps_in_expr(argument, apps),
+        NixExpr::Lambda { body, .. }
+        | NixExpr::LetIn { body, .. }
+        | NixExpr::With { body, .. } => find_apps_in_expr(body, apps),
+        _ => {}
+    }
+}
+
+fn is_app_definition(expr: &NixExpr) -> bool {
+    if let NixExpr::AttrSet { bindings, .. } = expr {
+        bindings.iter().any(|binding| {
+            matches!(&binding.path.parts[..], [AttrPathPart::Identifier(name)] if name == "type")
+                && binding.value == NixExpr::String("app".to_string())
+        })
+    } else {
+        false
+    }
+}
+
+// Collects `packages.<system>.<name>` build outputs, looking through forEachSupportedSystem
+// calls and per-system attribute sets so only the package name is kept
+fn find_package_outputs_in_expr(expr: &NixExpr, outputs: &mut HashMap<String, String>) {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                find_package_outputs_in_binding(&binding.path.parts, &binding.value, outputs);
+            }
+        }
+        NixExpr::FunctionCall { argument, .. } => find_package_outputs_in_expr(argument, outputs),
+        NixExpr::Lambda { body, .. }
+        | NixExpr::LetIn { body, .. }
+        | NixExpr::With { body, .. } => find_package_outputs_in_expr(body, outputs),
+        _ => {}
+    }
+}
+
+fn find_package_outputs_in_binding(parts: &[AttrPathPart], value: &NixExpr, outputs: &mut HashMap<String, String>) {
+    match parts {
+        [] => find_package_outputs_in_expr(value, outputs),
+        [system, rest @ ..] if is_system_attr(system) => find_package_outputs_in_binding(rest, value, outputs),
+        [.., AttrPathPart::Identifier(name)] | [.., AttrPathPart::String(name)] => {
+            outputs.insert(name.clone(), value.to_nix_string());
+        }
+        _ => {}
+    }
+}
+
+// `x86_64-linux`, `"aarch64-darwin"` or `${system}`
+fn is_system_attr(part: &AttrPathPart) -> bool {
+    match part {
+        AttrPathPart::Identifier(name) | AttrPathPart::String(name) => {
+            name.ends_with("-linux") || name.ends_with("-darwin")
+        }
+        AttrPathPart::Interpolation(_) => true,
+    }
+}
+
+// `shell` names the dev shell when `expr` is a single shell under a flat path, e.g. `devShells.x86_64-linux.minimal`
+fn extract_devshells_from_expr(expr: &NixExpr, fragments: &mut FlakeFragments, scope: &LetScope, shell: Option<&str>) {
     // Navigate through the devShells structure to find mkShell calls
-    find_packages_in_expr(expr, &mut fragments.packages);
+    find_packages_in_expr(expr, &mut fragments.packages, scope);
+    find_shell_packages_in_expr(shell, expr, &mut fragments.shell_packages, scope);
     find_env_in_expr(expr, &mut fragments.env_vars);
-    find_shell_hooks_in_expr(expr, &mut fragments.shell_hooks);
+    find_inputs_from_in_expr(expr, &mut fragments.inputs_from);
+    find_shell_attrs_in_expr(expr, &mut fragments.shell_attrs);
+    find_shell_hooks_in_expr(expr, &mut fragments.shell_hooks, scope);
+}
+
+// Packages of each named `mkShell` call, found under the attribute that holds it
+fn find_shell_packages_in_expr(
+    shell: Option<&str>,
+    expr: &NixExpr,
+    shell_packages: &mut HashMap<String, Vec<String>>,
+    scope: &LetScope,
+) {
+    match expr {
+        NixExpr::FunctionCall { function, argument } if is_mk_shell(function) => {
+            if let Some(shell) = shell {
+                let packages = shell_packages.entry(shell.to_string()).or_default();
+                find_packages_in_expr(argument, packages, scope);
+            }
+        }
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                if let Some(AttrPathPart::Identifier(name)) = binding.path.parts.last() {
+                    find_shell_packages_in_expr(Some(name), &binding.value, shell_packages, scope);
+                }
+            }
+        }
+        // `forEachSupportedSystem ({ pkgs }: { ... })` and similar wrappers
+        NixExpr::FunctionCall { argument, .. } => find_shell_packages_in_expr(None, argument, shell_packages, scope),
+        NixExpr::Lambda { body, .. } => find_shell_packages_in_expr(None, body, shell_packages, scope),
+        NixExpr::With { body, .. } => find_shell_packages_in_expr(shell, body, shell_packages, scope),
+        NixExpr::LetIn { bindings, body } => {
+            find_shell_packages_in_expr(shell, body, shell_packages, &extend_scope(scope, bindings));
+        }
+        _ => {}
+    }
 }
 
-fn find_packages_in_expr(expr: &NixExpr, packages: &mut Vec<String>) {
+// `mkShell`, `pkgs.mkShell` or `pkgs.mkShellNoCC`
+fn is_mk_shell(expr: &NixExpr) -> bool {
+    let name = match expr {
+        NixExpr::Identifier(name) => name,
+        NixExpr::Select { path, .. } => match path.parts.last() {
+            Some(AttrPathPart::Identifier(name)) => name,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    name == "mkShell" || name == "mkShellNoCC"
+}
+
+fn find_packages_in_expr(expr: &NixExpr, packages: &mut Vec<String>, scope: &LetScope) {
     match expr {
         NixExpr::AttrSet { bindings, .. } => {
             for binding in bindings {
                 if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
-                    if name == "packages" {
-                        extract_packages_from_value(&binding.value, packages);
+                    // Older templates list shell packages as `buildInputs`, which mkShell treats alike
+                    if name == "packages" || name == "buildInputs" {
+                        extract_packages_from_value(&binding.value, packages, scope);
                     }
                 }
-                find_packages_in_expr(&binding.value, packages);
+                find_packages_in_expr(&binding.value, packages, scope);
             }
         }
         NixExpr::List(items) => {
             for item in items {
-                find_packages_in_expr(item, packages);
+                find_packages_in_expr(item, packages, scope);
             }
         }
         NixExpr::With { body, .. } => {
-            find_packages_in_expr(body, packages);
+            find_packages_in_expr(body, packages, scope);
         }
         NixExpr::FunctionCall { argument, .. } => {
-            find_packages_in_expr(argument, packages);
+            find_packages_in_expr(argument, packages, scope);
         }
-        NixExpr::LetIn { body, .. } => {
-            find_packages_in_expr(body, packages);
+        NixExpr::LetIn { bindings, body } => {
+            find_packages_in_expr(body, packages, &extend_scope(scope, bindings));
         }
         NixExpr::Lambda { body, .. } => {
-            find_packages_in_expr(body, packages);
+            find_packages_in_expr(body, packages, scope);
         }
         _ => {}
     }
 }
 
-fn extract_packages_from_value(expr: &NixExpr, packages: &mut Vec<String>) {
+fn extract_packages_from_value(expr: &NixExpr, packages: &mut Vec<String>, scope: &LetScope) {
     match expr {
         NixExpr::With { body, .. } => {
             // Recursively extract from the body of the with expression
-            extract_packages_from_value(body, packages);
+            extract_packages_from_value(body, packages, scope);
         }
         NixExpr::List(items) => {
             for item in items {
-                if let NixExpr::Identifier(name) = item {
-                    packages.push(name.clone());
+                match item {
+                    NixExpr::Identifier(name) => packages.push(name.clone()),
+                    // `[ pkgs.hello ]`, common with `pkgs = nixpkgs.legacyPackages.${system}`
+                    NixExpr::Select { expr, path, default: None }
+                        if matches!(expr.as_ref(), NixExpr::Identifier(base) if base == "pkgs") =>
+                    {
+                        if let Some(parts) = identifier_path(&path.parts) {
+                            packages.push(parts.join("."));
+                        }
+                    }
+                    _ => {}
                 }
             }
         }
+        // `packages = devTools;` where `devTools` is a let-bound package list
+        NixExpr::Identifier(name) => {
+            if let Some(value) = scope.get(name) {
+                // Drop the name while resolving it so a self-referencing binding can't recurse forever
+                let mut inner_scope = scope.clone();
+                inner_scope.remove(name);
+                extract_packages_from_value(value, packages, &inner_scope);
+            }
+        }
         NixExpr::BinaryOp { left, op: BinaryOperator::Concat, right } => {
             // Handle concatenation operations recursively
-            extract_packages_from_value(left, packages);
-            extract_packages_from_value(right, packages);
+            extract_packages_from_value(left, packages, scope);
+            extract_packages_from_value(right, packages, scope);
         }
         NixExpr::If { then_expr, else_expr, .. } => {
             // Handle conditional expressions - extract from both branches
-            extract_packages_from_value(then_expr, packages);
-            extract_packages_from_value(else_expr, packages);
+            extract_packages_from_value(then_expr, packages, scope);
+            extract_packages_from_value(else_expr, packages, scope);
+        }
+        NixExpr::FunctionCall { function, argument } if is_concat_lists(function) => {
+            // builtins.concatLists [ [ ... ] [ ... ] ] - flatten the inner lists
+            if let NixExpr::List(lists) = argument.as_ref() {
+                for list in lists {
+                    extract_packages_from_value(list, packages, scope);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_concat_lists(expr: &NixExpr) -> bool {
+    match expr {
+        NixExpr::Identifier(name) => name == "concatLists",
+        NixExpr::Select { expr, path, .. } => {
+            matches!(expr.as_ref(), NixExpr::Identifier(base) if base == "builtins" || base == "lib")
+                && matches!(&path.parts[..], [AttrPathPart::Identifier(name)] if name == "concatLists")
+        }
+        _ => false,
+    }
+}
+
+
+fn find_inputs_from_in_expr(expr: &NixExpr, inputs_from: &mut Vec<String>) {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
+                    if name == "inputsFrom" {
+                        extract_inputs_from_value(&binding.value, inputs_from);
+                    }
+                }
+                find_inputs_from_in_expr(&binding.value, inputs_from);
+            }
+        }
+        NixExpr::Lambda { body, .. }
+        | NixExpr::LetIn { body, .. }
+        | NixExpr::With { body, .. } => find_inputs_from_in_expr(body, inputs_from),
+        NixExpr::FunctionCall { argument, .. } => find_inputs_from_in_expr(argument, inputs_from),
+        _ => {}
+    }
+}
+
+// `inputsFrom` entries are kept as opaque expressions, e.g. `pkgs.hello` or `self.packages.${system}.default`
+fn extract_inputs_from_value(expr: &NixExpr, inputs_from: &mut Vec<String>) {
+    match expr {
+        NixExpr::With { body, .. } => extract_inputs_from_value(body, inputs_from),
+        NixExpr::List(items) => {
+            for item in items {
+                let entry = item.to_nix_string();
+                if !inputs_from.contains(&entry) {
+                    inputs_from.push(entry);
+                }
+            }
+        }
+        NixExpr::BinaryOp { left, op: BinaryOperator::Concat, right } => {
+            extract_inputs_from_value(left, inputs_from);
+            extract_inputs_from_value(right, inputs_from);
         }
         _ => {}
     }
 }
 
+/// `mkShell` attributes besides packages, env, `inputsFrom` and `shellHook` that are carried
+/// into the merged shell
+const EXTRA_SHELL_ATTRS: &[&str] = &[
+    "hardeningDisable",
+    "hardeningEnable",
+    "NIX_CFLAGS_COMPILE",
+    "NIX_LDFLAGS",
+    "LD_LIBRARY_PATH",
+];
+
+fn find_shell_attrs_in_expr(expr: &NixExpr, shell_attrs: &mut HashMap<String, NixExpr>) {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
+                    if EXTRA_SHELL_ATTRS.contains(&name.as_str()) {
+                        shell_attrs.insert(name.clone(), binding.value.clone());
+                        continue;
+                    }
+                }
+                find_shell_attrs_in_expr(&binding.value, shell_attrs);
+            }
+        }
+        NixExpr::Lambda { body, .. }
+        | NixExpr::LetIn { body, .. }
+        | NixExpr::With { body, .. } => find_shell_attrs_in_expr(body, shell_attrs),
+        NixExpr::FunctionCall { argument, .. } => find_shell_attrs_in_expr(argument, shell_attrs),
+        _ => {}
+    }
+}
 
 fn find_env_in_expr(expr: &NixExpr, env_vars: &mut HashMap<String, String>) {
-    if let NixExpr::AttrSet { bindings, .. } = expr {
-        for binding in bindings {
-            if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
-                if name == "env" {
-                    if let NixExpr::AttrSet { bindings, .. } = &binding.value {
-                        for env_binding in bindings {
-                            if let [AttrPathPart::Identifier(env_name)] = &env_binding.path.parts[..] {
-                                if let NixExpr::String(env_value) = &env_binding.value {
-                                    env_vars.insert(env_name.clone(), env_value.clone());
-                                }
-                            }
-                        }
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for binding in bindings {
+                if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
+                    if name == "env" {
+                        extract_env_from_value(&binding.value, env_vars);
                     }
                 }
+                find_env_in_expr(&binding.value, env_vars);
             }
-            find_env_in_expr(&binding.value, env_vars);
         }
+        NixExpr::Lambda { body, .. } => {
+            find_env_in_expr(body, env_vars);
+        }
+        NixExpr::FunctionCall { argument, .. } => {
+            find_env_in_expr(argument, env_vars);
+        }
+        NixExpr::LetIn { body, .. } => {
+            find_env_in_expr(body, env_vars);
+        }
+        NixExpr::With { body, .. } => {
+            find_env_in_expr(body, env_vars);
+        }
+        _ => {}
     }
 }
 
-fn find_shell_hooks_in_expr(expr: &NixExpr, shell_hooks: &mut Vec<String>) {
+fn extract_env_from_value(expr: &NixExpr, env_vars: &mut HashMap<String, String>) {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            for env_binding in bindings {
+                if let [AttrPathPart::Identifier(env_name)] = &env_binding.path.parts[..] {
+                    if let NixExpr::String(env_value) = &env_binding.value {
+                        env_vars.insert(env_name.clone(), env_value.clone());
+                    }
+                }
+            }
+        }
+        // `base // { ... }`: evaluate statically, with the right-hand side winning like `//` does.
+        // Sides that aren't literal attrsets (e.g. a let-bound `baseEnv`) contribute nothing.
+        NixExpr::BinaryOp { left, op: BinaryOperator::Update, right } => {
+            extract_env_from_value(left, env_vars);
+            extract_env_from_value(right, env_vars);
+        }
+        _ => {}
+    }
+}
+
+fn find_shell_hooks_in_expr(expr: &NixExpr, shell_hooks: &mut Vec<String>, scope: &LetScope) {
     match expr {
         NixExpr::AttrSet { bindings, .. } => {
             for binding in bindings {
                 if let [AttrPathPart::Identifier(name)] = &binding.path.parts[..] {
                     if name == "shellHook" {
-                        if let NixExpr::String(hook) = &binding.value {
-                            shell_hooks.push(hook.clone());
+                        if let Some(parts) = hook_parts(&binding.value, scope) {
+                            shell_hooks.push(indented_string_body(&parts));
                         }
-                    } else if name.contains("venvShellHook") {
-                        shell_hooks.push("python-venv".to_string());
                     }
                 }
-                find_shell_hooks_in_expr(&binding.value, shell_hooks);
+                find_shell_hooks_in_expr(&binding.value, shell_hooks, scope);
             }
         }
         NixExpr::Lambda { body, .. } => {
-            find_shell_hooks_in_expr(body, shell_hooks);
+            find_shell_hooks_in_expr(body, shell_hooks, scope);
         }
         NixExpr::FunctionCall { argument, .. } => {
-            find_shell_hooks_in_expr(argument, shell_hooks);
+            find_shell_hooks_in_expr(argument, shell_hooks, scope);
         }
-        NixExpr::LetIn { body, .. } => {
-            find_shell_hooks_in_expr(body, shell_hooks);
+        NixExpr::LetIn { bindings, body } => {
+            find_shell_hooks_in_expr(body, shell_hooks, &extend_scope(scope, bindings));
         }
         NixExpr::With { body, .. } => {
-            find_shell_hooks_in_expr(body, shell_hooks);
+            find_shell_hooks_in_expr(body, shell_hooks, scope);
         }
         _ => {}
     }
+}
+
+// Parts of a shell hook built from literals, `+` concatenation and let-bound strings. Other
+// interpolations are kept as they are.
+fn hook_parts(expr: &NixExpr, scope: &LetScope) -> Option<Vec<StringPart>> {
+    match expr {
+        NixExpr::String(text) => Some(vec![StringPart::Literal(text.clone())]),
+        NixExpr::InterpolatedString(parts) => Some(
+            parts
+                .iter()
+                .flat_map(|part| match part {
+                    StringPart::Interpolation(inner) => {
+                        hook_parts(inner, scope).unwrap_or_else(|| vec![part.clone()])
+                    }
+                    StringPart::Literal(_) => vec![part.clone()],
+                })
+                .collect(),
+        ),
+        NixExpr::BinaryOp { left, op: BinaryOperator::Add, right } => {
+            let mut parts = hook_parts(left, scope)?;
+            parts.extend(hook_parts(right, scope)?);
+            Some(parts)
+        }
+        NixExpr::Identifier(name) => {
+            let value = scope.get(name)?;
+            let mut inner_scope = scope.clone();
+            inner_scope.remove(name);
+            hook_parts(value, &inner_scope)
+        }
+        _ => None,
+    }
+}
+
+// Source text between the quotes of an indented string with these parts. Adjacent literals are
+// escaped together, so a quote ending one can't pair with a quote starting the next.
+fn indented_string_body(parts: &[StringPart]) -> String {
+    let mut body = String::new();
+    let mut literal = String::new();
+    for part in parts {
+        match part {
+            StringPart::Literal(text) => literal.push_str(text),
+            StringPart::Interpolation(expr) => {
+                body.push_str(&escape_indented_string(&std::mem::take(&mut literal)));
+                body.push_str(&format!("${{{}}}", expr.to_nix_string()));
+            }
+        }
+    }
+    body.push_str(&escape_indented_string(&literal));
+    body
+}
+
+// The description as one line for the generated header. Interpolations that can't be resolved
+// keep their expression text without `${}`, so the header never refers to an unbound name.
+fn description_text(expr: &NixExpr, scope: &LetScope) -> Option<String> {
+    let text = match expr {
+        NixExpr::InterpolatedString(parts) => parts
+            .iter()
+            .map(|part| match part {
+                StringPart::Literal(text) => text.clone(),
+                StringPart::Interpolation(inner) => {
+                    static_string(inner, scope).unwrap_or_else(|| inner.to_nix_string())
+                }
+            })
+            .collect(),
+        _ => static_string(expr, scope)?,
+    };
+    Some(text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+// Text of a string built from literals, `+` concatenation and let-bound strings.
+// Interpolations of anything else are kept as `${...}`.
+fn static_string(expr: &NixExpr, scope: &LetScope) -> Option<String> {
+    match expr {
+        NixExpr::String(text) => Some(text.clone()),
+        NixExpr::InterpolatedString(parts) => Some(
+            parts
+                .iter()
+                .map(|part| match part {
+                    StringPart::Literal(text) => text.clone(),
+                    StringPart::Interpolation(inner) => static_string(inner, scope)
+                        .unwrap_or_else(|| format!("${{{}}}", inner.to_nix_string())),
+                })
+                .collect(),
+        ),
+        NixExpr::BinaryOp { left, op: BinaryOperator::Add, right } => {
+            Some(static_string(left, scope)? + &static_string(right, scope)?)
+        }
+        NixExpr::Identifier(name) => {
+            let value = scope.get(name)?;
+            // Drop the name while resolving so self-referencing bindings terminate
+            let mut inner_scope = scope.clone();
+            inner_scope.remove(name);
+            static_string(value, &inner_scope)
+        }
+        _ => None,
+    }
 }
\ No newline at end of file
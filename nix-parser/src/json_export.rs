@@ -0,0 +1,86 @@
+use crate::ast::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Flattened, serializable view of [`FlakeFragments`] for external tooling.
+///
+/// Overlay bindings are pre-rendered to Nix source instead of exposing the AST, and
+/// maps are ordered by key so the output is stable:
+///
+/// ```json
+/// {
+///   "header": "A Nix-flake-based Rust development environment",
+///   "inputs": { "nixpkgs": "github:NixOS/nixpkgs/nixos-unstable", "rust-overlay": "..." },
+///   "overlays": { "default": [ { "name": "rustToolchain", "value": "let ..." } ] },
+///   "recursive_overlays": [],
+///   "packages": [ "rustToolchain", "openssl" ],
+///   "env_vars": {},
+///   "shell_hooks": [],
+///   "allow_unfree": false,
+///   "let_bindings": { "supportedSystems": "[ \"x86_64-linux\" ... ]" },
+///   "apps": { "default": "{ type = \"app\"; program = ...; }" },
+///   "package_outputs": { "default": "pkgs.stdenv.mkDerivation { ... }" },
+///   "passthrough": {}
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FragmentsExport {
+    pub header: String,
+    pub inputs: BTreeMap<String, String>,
+    pub overlays: BTreeMap<String, Vec<OverlayAttrExport>>,
+    pub recursive_overlays: Vec<String>,
+    pub packages: Vec<String>,
+    pub env_vars: BTreeMap<String, String>,
+    pub shell_hooks: Vec<String>,
+    pub allow_unfree: bool,
+    pub let_bindings: BTreeMap<String, String>,
+    pub apps: BTreeMap<String, String>,
+    pub package_outputs: BTreeMap<String, String>,
+    pub passthrough: BTreeMap<String, String>,
+}
+
+/// A single overlay attribute with its path and value rendered as Nix source
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OverlayAttrExport {
+    pub name: String,
+    pub value: String,
+}
+
+impl From<&FlakeFragments> for FragmentsExport {
+    fn from(f: &FlakeFragments) -> Self {
+        let overlays = f.overlays.iter()
+            .map(|(name, bindings)| {
+                let attrs = bindings.iter()
+                    .map(|binding| OverlayAttrExport {
+                        name: binding.path.to_nix_string(),
+                        value: binding.value.to_nix_string(),
+                    })
+                    .collect();
+                (name.clone(), attrs)
+            })
+            .collect();
+
+        Self {
+            header: f.header.clone(),
+            inputs: f.inputs.clone().into_iter().collect(),
+            overlays,
+            recursive_overlays: f.recursive_overlays.clone(),
+            packages: f.packages.clone(),
+            env_vars: f.env_vars.clone().into_iter().collect(),
+            shell_hooks: f.shell_hooks.clone(),
+            allow_unfree: f.allow_unfree,
+            let_bindings: f.let_bindings.clone().into_iter().collect(),
+            apps: f.apps.clone().into_iter().collect(),
+            package_outputs: f.package_outputs.clone().into_iter().collect(),
+            passthrough: f.passthrough.iter()
+                .map(|(name, value)| (name.clone(), value.to_nix_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Serialize extraction results to pretty-printed JSON in the [`FragmentsExport`] shape
+pub fn fragments_to_json(f: &FlakeFragments) -> String {
+    serde_json::to_string_pretty(&FragmentsExport::from(f))
+        .expect("fragments export only contains strings, lists and maps")
+}
@@ -1,29 +1,146 @@
 mod ast;
 mod parser;
 mod flake_analysis;
+mod json_export;
 
 pub use ast::*;
-use parser::nix_expr;
+pub use json_export::{fragments_to_json, FragmentsExport, OverlayAttrExport};
+use parser::{nix_expr, parse_lenient};
 use flake_analysis::{extract_flake_data, extract_fragments_from_expr};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 
 
 // Main parsing functions
 pub fn parse_nix_expr(input: &str) -> Result<NixExpr, ParseError> {
-    match nix_expr(input.trim()) {
+    parse_with(input, nix_expr)
+}
+
+/// Like [`parse_nix_expr`], but a bracketed subexpression the parser doesn't support is kept
+/// as [`NixExpr::Raw`] text instead of failing the whole parse. Meant for extracting what it
+/// can from templates; syntax errors inside brackets are not reported.
+pub fn parse_nix_expr_lenient(input: &str) -> Result<NixExpr, ParseError> {
+    parse_with(input, parse_lenient)
+}
+
+fn parse_with(input: &str, parse: fn(&str) -> nom::IResult<&str, NixExpr>) -> Result<NixExpr, ParseError> {
+    // Some editors save a UTF-8 byte order mark, which `trim` doesn't remove
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    // Normalize CRLF line endings so strings and shell hooks never carry a stray '\r'
+    let input = input.replace("\r\n", "\n");
+    match parse(input.trim()) {
         Ok((remaining, expr)) => {
             let remaining_trimmed = remaining.trim();
-            if remaining_trimmed.is_empty() {
+            if let NixExpr::Raw(_) = expr {
+                // Raw recovery is only meant for subexpressions; a wholly unparseable input is an error
+                Err(ParseError::Parse("Parsing Error: could not parse expression".to_string()))
+            } else if remaining_trimmed.is_empty() {
                 Ok(expr)
             } else {
-                Err(ParseError::Parse(format!("Unexpected remaining input: '{}' (first 100 chars)", 
-                    &remaining_trimmed[..remaining_trimmed.len().min(100)])))
+                Err(trailing_input_error(&input, remaining_trimmed))
             }
         }
         Err(e) => Err(ParseError::Parse(format!("Parsing Error: {e}"))),
     }
 }
 
+/// Describe leftover input after a complete expression, with its position and a hint
+/// for the usual causes
+fn trailing_input_error(input: &str, remaining: &str) -> ParseError {
+    // `remaining` is always a suffix of `input` minus trailing whitespace
+    let offset = input.trim_end().len() - remaining.len();
+    let before = &input[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    let snippet: String = remaining.chars().take(100).collect();
+
+    let hint = if remaining.starts_with('=') && !remaining.starts_with("==") {
+        "; a binding appears where an expression ended - possibly a missing ';' separator between bindings or a binding outside an attribute set"
+    } else if remaining.starts_with(';') {
+        "; a ';' follows a complete expression - remove it, or wrap the bindings in '{ ... }'"
+    } else if remaining.starts_with(['}', ']', ')']) {
+        "; unbalanced closing delimiter"
+    } else {
+        ""
+    };
+
+    ParseError::Parse(format!(
+        "Unexpected remaining input at line {line}, column {column} (byte offset {offset}): '{snippet}'{hint}"
+    ))
+}
+
+/// Like [`parse_nix_expr`], but rejects attribute sets and `let` blocks that define the
+/// same attribute twice, as Nix does. The lenient parser keeps every binding.
+pub fn parse_nix_expr_strict(input: &str) -> Result<NixExpr, ParseError> {
+    let expr = parse_nix_expr(input)?;
+    check_duplicate_attrs(&expr)?;
+    Ok(expr)
+}
+
+fn check_duplicate_attrs(expr: &NixExpr) -> Result<(), ParseError> {
+    match expr {
+        NixExpr::AttrSet { bindings, .. } => {
+            check_duplicate_bindings(bindings)?;
+            bindings.iter().try_for_each(|binding| check_duplicate_attrs(&binding.value))
+        }
+        NixExpr::LetIn { bindings, body } => {
+            check_duplicate_bindings(bindings)?;
+            bindings.iter().try_for_each(|binding| check_duplicate_attrs(&binding.value))?;
+            check_duplicate_attrs(body)
+        }
+        NixExpr::List(items) => items.iter().try_for_each(check_duplicate_attrs),
+        NixExpr::InterpolatedString(parts) => parts.iter().try_for_each(|part| match part {
+            StringPart::Interpolation(expr) => check_duplicate_attrs(expr),
+            StringPart::Literal(_) => Ok(()),
+        }),
+        NixExpr::Lambda { param, body } => {
+            if let LambdaParam::Pattern { params, .. } = param {
+                params.iter()
+                    .filter_map(|param| param.default.as_deref())
+                    .try_for_each(check_duplicate_attrs)?;
+            }
+            check_duplicate_attrs(body)
+        }
+        NixExpr::FunctionCall { function: first, argument: second }
+        | NixExpr::With { env: first, body: second }
+        | NixExpr::Assert { condition: first, body: second }
+        | NixExpr::BinaryOp { left: first, right: second, .. } => {
+            check_duplicate_attrs(first)?;
+            check_duplicate_attrs(second)
+        }
+        NixExpr::If { condition, then_expr, else_expr } => {
+            check_duplicate_attrs(condition)?;
+            check_duplicate_attrs(then_expr)?;
+            check_duplicate_attrs(else_expr)
+        }
+        NixExpr::UnaryOp { expr, .. } | NixExpr::HasAttr { expr, .. } => check_duplicate_attrs(expr),
+        NixExpr::Select { expr, default, .. } => {
+            check_duplicate_attrs(expr)?;
+            default.as_deref().map_or(Ok(()), check_duplicate_attrs)
+        }
+        NixExpr::Inherit { from, .. } => from.as_deref().map_or(Ok(()), check_duplicate_attrs),
+        _ => Ok(()),
+    }
+}
+
+fn check_duplicate_bindings(bindings: &[Binding]) -> Result<(), ParseError> {
+    let mut seen = HashSet::new();
+    for binding in bindings {
+        let names = match &binding.value {
+            NixExpr::Inherit { attrs, .. } => attrs.clone(),
+            // Dynamic names like `${name} = ...` can't be checked without evaluating
+            _ if binding.path.parts.iter().any(|part| matches!(part, AttrPathPart::Interpolation(_))) => continue,
+            _ => vec![binding.path.to_nix_string()],
+        };
+        for name in names {
+            if !seen.insert(name.clone()) {
+                return Err(ParseError::InvalidSyntax(format!("attribute '{name}' already defined")));
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn parse_flake(input: &str) -> Result<FlakeData, ParseError> {
     let expr = parse_nix_expr(input)?;
     extract_flake_data(&expr)
@@ -34,6 +151,162 @@ pub fn extract_flake_fragments(input: &str) -> Result<FlakeFragments, ParseError
     extract_fragments_from_expr(&expr)
 }
 
+/// [`extract_flake_fragments`] over [`parse_nix_expr_lenient`], for templates that may use
+/// syntax the parser doesn't support yet
+pub fn extract_flake_fragments_lenient(input: &str) -> Result<FlakeFragments, ParseError> {
+    let expr = parse_nix_expr_lenient(input)?;
+    extract_fragments_from_expr(&expr)
+}
+
+/// Compare two flakes by what they provide rather than how they are written: inputs,
+/// dev shell packages, overlays and env vars, ignoring formatting and ordering
+pub fn flakes_semantically_equal(a: &str, b: &str) -> Result<bool, ParseError> {
+    let a = extract_flake_fragments(a)?;
+    let b = extract_flake_fragments(b)?;
+
+    let packages = |f: &FlakeFragments| f.packages.iter().cloned().collect::<HashSet<_>>();
+    let overlays = |f: &FlakeFragments| {
+        f.overlays.iter()
+            .map(|(name, bindings)| {
+                let attrs = bindings.iter()
+                    .map(|binding| (binding.path.to_nix_string(), binding.value.to_nix_string()))
+                    .collect::<HashSet<_>>();
+                (name.clone(), attrs)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+
+    Ok(a.inputs == b.inputs
+        && packages(&a) == packages(&b)
+        && overlays(&a) == overlays(&b)
+        && a.env_vars == b.env_vars)
+}
+
+/// Identifiers `expr` reads from its surroundings, i.e. those not bound by an
+/// enclosing `let`, `rec` set or lambda parameter inside `expr`.
+///
+/// Names brought into scope by `with` can't be known statically, so they are
+/// reported as free.
+pub fn free_identifiers(expr: &NixExpr) -> BTreeSet<String> {
+    let mut free = BTreeSet::new();
+    collect_free_identifiers(expr, &HashSet::new(), &mut free);
+    free
+}
+
+fn collect_free_identifiers(expr: &NixExpr, bound: &HashSet<String>, free: &mut BTreeSet<String>) {
+    match expr {
+        NixExpr::Identifier(name) if !bound.contains(name) => {
+            free.insert(name.clone());
+        }
+        NixExpr::AttrSet { recursive, bindings } => {
+            let inner = if *recursive { extend_bound(bound, binding_names(bindings)) } else { bound.clone() };
+            collect_free_in_bindings(bindings, bound, &inner, free);
+        }
+        NixExpr::LetIn { bindings, body } => {
+            let inner = extend_bound(bound, binding_names(bindings));
+            collect_free_in_bindings(bindings, bound, &inner, free);
+            collect_free_identifiers(body, &inner, free);
+        }
+        NixExpr::Lambda { param, body } => {
+            let inner = match param {
+                LambdaParam::Identifier(name) => extend_bound(bound, vec![name.clone()]),
+                LambdaParam::Pattern { params, bind, .. } => {
+                    let names = params.iter().map(|p| p.name.clone()).chain(bind.clone()).collect();
+                    let inner = extend_bound(bound, names);
+                    for default in params.iter().filter_map(|p| p.default.as_deref()) {
+                        collect_free_identifiers(default, &inner, free);
+                    }
+                    inner
+                }
+            };
+            collect_free_identifiers(body, &inner, free);
+        }
+        NixExpr::List(items) => {
+            for item in items {
+                collect_free_identifiers(item, bound, free);
+            }
+        }
+        NixExpr::InterpolatedString(parts) => {
+            for part in parts {
+                if let StringPart::Interpolation(inner) = part {
+                    collect_free_identifiers(inner, bound, free);
+                }
+            }
+        }
+        NixExpr::FunctionCall { function: first, argument: second }
+        | NixExpr::With { env: first, body: second }
+        | NixExpr::Assert { condition: first, body: second }
+        | NixExpr::BinaryOp { left: first, right: second, .. } => {
+            collect_free_identifiers(first, bound, free);
+            collect_free_identifiers(second, bound, free);
+        }
+        NixExpr::If { condition, then_expr, else_expr } => {
+            for inner in [condition, then_expr, else_expr] {
+                collect_free_identifiers(inner, bound, free);
+            }
+        }
+        NixExpr::UnaryOp { expr, .. } => collect_free_identifiers(expr, bound, free),
+        NixExpr::Select { expr, path, default } => {
+            collect_free_identifiers(expr, bound, free);
+            collect_free_in_path(path, bound, free);
+            if let Some(default) = default {
+                collect_free_identifiers(default, bound, free);
+            }
+        }
+        NixExpr::HasAttr { expr, path } => {
+            collect_free_identifiers(expr, bound, free);
+            collect_free_in_path(path, bound, free);
+        }
+        NixExpr::Inherit { from, attrs } => match from {
+            Some(from) => collect_free_identifiers(from, bound, free),
+            None => free.extend(attrs.iter().filter(|attr| !bound.contains(*attr)).cloned()),
+        },
+        _ => {}
+    }
+}
+
+fn collect_free_in_bindings(
+    bindings: &[Binding],
+    outer: &HashSet<String>,
+    inner: &HashSet<String>,
+    free: &mut BTreeSet<String>,
+) {
+    for binding in bindings {
+        collect_free_in_path(&binding.path, inner, free);
+        match &binding.value {
+            // A plain `inherit x;` reads `x` from outside the set or let it defines
+            NixExpr::Inherit { from: None, .. } => collect_free_identifiers(&binding.value, outer, free),
+            value => collect_free_identifiers(value, inner, free),
+        }
+    }
+}
+
+fn collect_free_in_path(path: &AttrPath, bound: &HashSet<String>, free: &mut BTreeSet<String>) {
+    for part in &path.parts {
+        if let AttrPathPart::Interpolation(inner) = part {
+            collect_free_identifiers(inner, bound, free);
+        }
+    }
+}
+
+/// Names defined by a list of bindings, including inherited ones
+fn binding_names(bindings: &[Binding]) -> Vec<String> {
+    bindings
+        .iter()
+        .flat_map(|binding| match (&binding.value, binding.path.parts.first()) {
+            (NixExpr::Inherit { attrs, .. }, _) => attrs.clone(),
+            (_, Some(AttrPathPart::Identifier(name) | AttrPathPart::String(name))) => vec![name.clone()],
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+fn extend_bound(bound: &HashSet<String>, names: Vec<String>) -> HashSet<String> {
+    let mut bound = bound.clone();
+    bound.extend(names);
+    bound
+}
+
 
 
 #[cfg(test)]
@@ -204,7 +477,7 @@ mod tests {
             Err(e) => {
                 eprintln!("Failed to parse go.nix template: {e:#?}");
                 // For now, let's not panic so we can see what's happening
-                assert!(false, "Failed to parse go.nix template");
+                panic!("Failed to parse go.nix template");
             }
         }
     }
@@ -1169,4 +1442,1382 @@ mod tests {
             _ => panic!("Expected Select expression"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let input = "{\r\n  description = \"A CRLF flake\"; # trailing comment\r\n  inputs.nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";\r\n  outputs = { self, nixpkgs }: {\r\n    devShells = {\r\n      default = pkgs.mkShell {\r\n        packages = [ hello ];\r\n        shellHook = ''\r\n          echo hello\r\n        '';\r\n      };\r\n    };\r\n  };\r\n}\r\n";
+        let result = extract_flake_fragments(input).unwrap();
+
+        assert_eq!(result.header, "A CRLF flake");
+        assert_eq!(result.shell_hooks.len(), 1);
+        assert!(result.shell_hooks[0].contains("echo hello"));
+        assert!(!result.header.contains('\r'));
+        assert!(result.shell_hooks.iter().all(|hook| !hook.contains('\r')));
+        assert!(result.inputs.values().all(|url| !url.contains('\r')));
+    }
+
+    #[test]
+    fn test_recursive_overlay_flag_is_extracted() {
+        let java = extract_flake_fragments(include_str!("templates/java.nix")).unwrap();
+        assert_eq!(java.recursive_overlays, vec!["default".to_string()]);
+
+        let go = extract_flake_fragments(include_str!("templates/go.nix")).unwrap();
+        assert!(go.recursive_overlays.is_empty());
+    }
+
+    #[test]
+    fn test_fragments_to_json_rust() {
+        let fragments = extract_flake_fragments(include_str!("templates/rust.nix")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fragments_to_json(&fragments)).unwrap();
+
+        assert!(json["inputs"]["nixpkgs"].is_string());
+        assert_eq!(json["inputs"]["rust-overlay"], "github:oxalica/rust-overlay");
+        let overlay_attrs = json["overlays"]["default"].as_array().unwrap();
+        assert!(overlay_attrs.iter().any(|attr| attr["name"] == "rustToolchain"));
+        assert!(overlay_attrs.iter().all(|attr| attr["value"].is_string()));
+    }
+
+    #[test]
+    fn test_keyword_as_attribute_name() {
+        let result = parse_nix_expr("{ or = 1; rec = 2; }").unwrap();
+
+        match result {
+            NixExpr::AttrSet { recursive, bindings } => {
+                assert!(!recursive);
+                assert_eq!(bindings.len(), 2);
+                assert_eq!(bindings[0].path.parts[0], AttrPathPart::Identifier("or".to_string()));
+                assert_eq!(bindings[0].value, NixExpr::Integer(1));
+                assert_eq!(bindings[1].path.parts[0], AttrPathPart::Identifier("rec".to_string()));
+            }
+            _ => panic!("Expected AttrSet"),
+        }
+    }
+
+    #[test]
+    fn test_if_then_else_keywords() {
+        let result = parse_nix_expr("if x then y else z").unwrap();
+
+        match result {
+            NixExpr::If { condition, then_expr, else_expr } => {
+                assert_eq!(*condition, NixExpr::Identifier("x".to_string()));
+                assert_eq!(*then_expr, NixExpr::Identifier("y".to_string()));
+                assert_eq!(*else_expr, NixExpr::Identifier("z".to_string()));
+            }
+            _ => panic!("Expected If expression"),
+        }
+    }
+
+    #[test]
+    fn test_keyword_prefixed_identifiers() {
+        let result = parse_nix_expr("let inputs = trueish; in inputs").unwrap();
+
+        match result {
+            NixExpr::LetIn { bindings, body } => {
+                assert_eq!(bindings[0].value, NixExpr::Identifier("trueish".to_string()));
+                assert_eq!(*body, NixExpr::Identifier("inputs".to_string()));
+            }
+            _ => panic!("Expected LetIn"),
+        }
+    }
+
+    #[test]
+    fn test_pattern_default_references_earlier_param() {
+        let result = parse_nix_expr("{ a, b ? a }: b").unwrap();
+
+        match &result {
+            NixExpr::Lambda { param: LambdaParam::Pattern { params, ellipsis, .. }, .. } => {
+                assert!(!ellipsis);
+                assert_eq!(params.len(), 2);
+                assert_eq!(params[0].name, "a");
+                assert!(params[0].default.is_none());
+                assert_eq!(params[1].name, "b");
+                assert_eq!(params[1].default.as_deref(), Some(&NixExpr::Identifier("a".to_string())));
+            }
+            _ => panic!("Expected Lambda with pattern"),
+        }
+
+        assert_eq!(result.to_nix_string(), "{ a, b ? a }: b");
+        assert_eq!(parse_nix_expr(&result.to_nix_string()).unwrap(), result);
+    }
+
+    #[test]
+    fn test_pattern_default_attrset() {
+        let result = parse_nix_expr("{ a ? {}, ... }: a").unwrap();
+
+        match &result {
+            NixExpr::Lambda { param: LambdaParam::Pattern { params, ellipsis, .. }, body } => {
+                assert!(ellipsis);
+                assert_eq!(params.len(), 1);
+                assert_eq!(
+                    params[0].default.as_deref(),
+                    Some(&NixExpr::AttrSet { recursive: false, bindings: vec![] })
+                );
+                assert_eq!(**body, NixExpr::Identifier("a".to_string()));
+            }
+            _ => panic!("Expected Lambda with pattern"),
+        }
+
+        let simple = parse_nix_expr("{ a ? {} }: a").unwrap();
+        assert_eq!(simple.to_nix_string(), "{ a ? { } }: a");
+        assert_eq!(parse_nix_expr(&simple.to_nix_string()).unwrap(), simple);
+    }
+
+    #[test]
+    fn test_with_body_extends_over_operators() {
+        let result = parse_nix_expr("with pkgs; a ++ b").unwrap();
+
+        match result {
+            NixExpr::With { env, body } => {
+                assert_eq!(*env, NixExpr::Identifier("pkgs".to_string()));
+                match *body {
+                    NixExpr::BinaryOp { left, op, right } => {
+                        assert_eq!(*left, NixExpr::Identifier("a".to_string()));
+                        assert_eq!(op, BinaryOperator::Concat);
+                        assert_eq!(*right, NixExpr::Identifier("b".to_string()));
+                    }
+                    _ => panic!("Expected BinaryOp in with body"),
+                }
+            }
+            _ => panic!("Expected With expression"),
+        }
+    }
+
+    #[test]
+    fn test_nested_with_expressions() {
+        let result = parse_nix_expr("with a; with b.c; body").unwrap();
+
+        match result {
+            NixExpr::With { env, body } => {
+                assert_eq!(*env, NixExpr::Identifier("a".to_string()));
+                match *body {
+                    NixExpr::With { env, body } => {
+                        assert!(matches!(*env, NixExpr::Select { .. }));
+                        assert_eq!(*body, NixExpr::Identifier("body".to_string()));
+                    }
+                    _ => panic!("Expected nested With expression"),
+                }
+            }
+            _ => panic!("Expected With expression"),
+        }
+    }
+
+    #[test]
+    fn test_stacked_lets_in_outputs() {
+        let input = r#"{
+  description = "Stacked lets";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      goVersion = 22;
+      shadowed = "outer";
+    in
+    let
+      supportedSystems = [ "x86_64-linux" ];
+      shadowed = "inner";
+    in
+    {
+      devShells = { default = pkgs.mkShell { packages = with pkgs; [ go ]; }; };
+    };
+}"#;
+        let result = extract_flake_fragments(input).unwrap();
+
+        assert_eq!(result.let_bindings.get("goVersion"), Some(&"22".to_string()));
+        assert_eq!(result.let_bindings.get("supportedSystems"), Some(&r#"[ "x86_64-linux" ]"#.to_string()));
+        // The innermost let shadows outer bindings of the same name
+        assert_eq!(result.let_bindings.get("shadowed"), Some(&r#""inner""#.to_string()));
+        assert_eq!(result.packages, vec!["go".to_string()]);
+    }
+
+    #[test]
+    fn test_extract_apps() {
+        let input = r#"{
+  description = "Apps";
+  outputs = { self, nixpkgs }: {
+    apps = forEachSupportedSystem ({ pkgs }: {
+      default = { type = "app"; program = "${pkgs.hello}/bin/hello"; };
+    });
+  };
+}"#;
+        let result = extract_flake_fragments(input).unwrap();
+
+        let app = result.apps.get("default").expect("default app should be extracted");
+        assert!(app.contains(r#"type = "app";"#));
+        assert!(app.contains("${pkgs.hello}/bin/hello"));
+    }
+
+    #[test]
+    fn test_extract_packages_from_concat_lists() {
+        let input = r#"{
+  description = "concatLists packages";
+  outputs = { self, nixpkgs }: {
+    devShells = forEachSupportedSystem ({ pkgs }: {
+      default = pkgs.mkShell {
+        packages = with pkgs; builtins.concatLists [
+          [ go gotools ]
+          [ nodejs ]
+        ] ++ [ jq ];
+      };
+    });
+  };
+}"#;
+        let result = extract_flake_fragments(input).unwrap();
+
+        assert_eq!(result.packages, vec!["go", "gotools", "nodejs", "jq"]);
+    }
+
+    #[test]
+    fn test_select_on_parenthesized_expressions() {
+        let result = parse_nix_expr("(f x).attr").unwrap();
+        match result {
+            NixExpr::Select { expr, path, .. } => {
+                assert!(matches!(*expr, NixExpr::FunctionCall { .. }));
+                assert_eq!(path.parts, vec![AttrPathPart::Identifier("attr".to_string())]);
+            }
+            _ => panic!("Expected Select on function call result"),
+        }
+
+        let result = parse_nix_expr("(import ./x.nix).y.z").unwrap();
+        match result {
+            NixExpr::Select { expr, path, .. } => {
+                match *expr {
+                    NixExpr::FunctionCall { function, argument } => {
+                        assert_eq!(*function, NixExpr::Identifier("import".to_string()));
+                        assert_eq!(*argument, NixExpr::Path("./x.nix".to_string()));
+                    }
+                    _ => panic!("Expected import call inside parentheses"),
+                }
+                assert_eq!(path.parts.len(), 2);
+                assert_eq!(path.parts[1], AttrPathPart::Identifier("z".to_string()));
+            }
+            _ => panic!("Expected Select on import result"),
+        }
+
+        let result = parse_nix_expr("({ a = 1; }).a").unwrap();
+        match result {
+            NixExpr::Select { expr, .. } => assert!(matches!(*expr, NixExpr::AttrSet { .. })),
+            _ => panic!("Expected Select on attrset"),
+        }
+
+        let result = parse_nix_expr("(import nixpkgs { inherit system; }).lib").unwrap();
+        match result {
+            NixExpr::Select { expr, path, .. } => {
+                assert!(matches!(*expr, NixExpr::FunctionCall { .. }));
+                assert_eq!(path.parts, vec![AttrPathPart::Identifier("lib".to_string())]);
+            }
+            _ => panic!("Expected Select on import nixpkgs result"),
+        }
+    }
+
+    #[test]
+    fn test_extract_passthrough_attributes() {
+        let input = r#"{
+  description = "Package-style flake";
+  meta = { license = "MIT"; };
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: { };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.passthrough.len(), 1);
+        assert!(fragments.passthrough["meta"].to_nix_string().contains(r#"license = "MIT";"#));
+        assert!(!fragments.passthrough.contains_key("description"));
+        assert!(!fragments.passthrough.contains_key("outputs"));
+    }
+
+    #[test]
+    fn test_trailing_input_reports_missing_separator() {
+        let err = parse_nix_expr("{ a = 1; }\nfoo = 2;").unwrap_err().to_string();
+        assert!(err.contains("line 2, column 5"), "unexpected error: {err}");
+        assert!(err.contains("missing ';' separator"), "unexpected error: {err}");
+
+        let err = parse_nix_expr("[ 1 ] ]").unwrap_err().to_string();
+        assert!(err.contains("line 1, column 7"), "unexpected error: {err}");
+        assert!(err.contains("unbalanced closing delimiter"), "unexpected error: {err}");
+    }
+
+    #[test]
+    fn test_let_extraction_keeps_negative_numbers_and_interpolated_strings() {
+        let input = r#"{
+  description = "Let bindings";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      version = "1.0";
+      threshold = -1;
+      ratio = -0.5;
+      name = "pre-${version}";
+    in
+    { };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.let_bindings["threshold"], "-1");
+        assert_eq!(fragments.let_bindings["ratio"], "-0.5");
+        assert_eq!(fragments.let_bindings["name"], r#""pre-${version}""#);
+    }
+
+    #[test]
+    fn test_extract_env_from_update_operator() {
+        let input = r#"{
+  description = "Env via update";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      baseEnv = { IGNORED = "let-bound"; };
+    in
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell {
+          packages = with pkgs; [ hello ];
+          env = baseEnv // { BASE = "0"; } // { BASE = "1"; EXTRA = "2"; };
+        };
+      });
+    };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.env_vars.get("EXTRA"), Some(&"2".to_string()));
+        assert_eq!(fragments.env_vars.get("BASE"), Some(&"1".to_string()));
+    }
+
+    #[test]
+    fn test_pipe_operators() {
+        let result = parse_nix_expr("a |> f |> g").unwrap();
+        match &result {
+            NixExpr::BinaryOp { left, op: BinaryOperator::PipeForward, right } => {
+                assert_eq!(**right, NixExpr::Identifier("g".to_string()));
+                assert!(matches!(
+                    **left,
+                    NixExpr::BinaryOp { op: BinaryOperator::PipeForward, .. }
+                ));
+            }
+            _ => panic!("Expected left-associative pipe, got {result:?}"),
+        }
+        assert_eq!(result.to_nix_string(), "a |> f |> g");
+
+        let result = parse_nix_expr("f <| x").unwrap();
+        assert!(matches!(result, NixExpr::BinaryOp { op: BinaryOperator::PipeBackward, .. }));
+        assert_eq!(result.to_nix_string(), "f <| x");
+
+        // `<` and `||` still parse as before
+        assert!(matches!(
+            parse_nix_expr("a < b || c").unwrap(),
+            NixExpr::BinaryOp { op: BinaryOperator::Or, .. }
+        ));
+    }
+
+    #[test]
+    fn test_extract_from_flake_utils_flake() {
+        let input = r#"{
+  description = "A flake-utils based environment";
+
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    flake-utils.url = "github:numtide/flake-utils";
+  };
+
+  outputs = { self, nixpkgs, flake-utils }:
+    flake-utils.lib.eachDefaultSystem (system:
+      let
+        pkgs = import nixpkgs { inherit system; };
+      in
+      {
+        devShell = pkgs.mkShell {
+          packages = with pkgs; [ hello jq ];
+        };
+      });
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.header, "A flake-utils based environment");
+        assert!(fragments.inputs.contains_key("flake-utils"));
+        assert!(fragments.packages.contains(&"hello".to_string()));
+        assert!(fragments.packages.contains(&"jq".to_string()));
+
+        let input = r#"{
+  description = "eachSystem with devShells.default";
+  inputs.flake-utils.url = "github:numtide/flake-utils";
+  outputs = { self, nixpkgs, flake-utils }:
+    flake-utils.lib.eachSystem [ "x86_64-linux" ] (system:
+      let pkgs = nixpkgs.legacyPackages.${system}; in
+      {
+        devShells.default = pkgs.mkShell { packages = with pkgs; [ ripgrep ]; };
+      }) // { overlays.default = final: prev: { };
+    };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert!(fragments.packages.contains(&"ripgrep".to_string()));
+        assert!(fragments.overlays.contains_key("default"));
+    }
+
+    #[test]
+    fn test_quoted_numeric_attr_path_segments() {
+        let result = parse_nix_expr(r#"pkgs."0""#).unwrap();
+        match &result {
+            NixExpr::Select { path, .. } => {
+                assert_eq!(path.parts, vec![AttrPathPart::String("0".to_string())]);
+            }
+            _ => panic!("Expected Select, got {result:?}"),
+        }
+        assert_eq!(result.to_nix_string(), r#"pkgs."0""#);
+
+        let result = parse_nix_expr(r#"x.y."1.2.3""#).unwrap();
+        match &result {
+            NixExpr::Select { path, .. } => {
+                assert_eq!(
+                    path.parts,
+                    vec![
+                        AttrPathPart::Identifier("y".to_string()),
+                        AttrPathPart::String("1.2.3".to_string()),
+                    ]
+                );
+            }
+            _ => panic!("Expected Select, got {result:?}"),
+        }
+        assert_eq!(result.to_nix_string(), r#"x.y."1.2.3""#);
+
+        let result = parse_nix_expr(r#"{ "0" = 1; x."1.2.3" = 2; }"#).unwrap();
+        assert_eq!(parse_nix_expr(&result.to_nix_string()).unwrap(), result);
+    }
+
+    #[test]
+    fn test_extract_package_outputs() {
+        let input = r#"{
+  description = "Buildable";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: {
+    packages.x86_64-linux.default = nixpkgs.legacyPackages.x86_64-linux.hello;
+    packages.aarch64-darwin = { tool = nixpkgs.legacyPackages.aarch64-darwin.jq; };
+  };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(
+            fragments.package_outputs.get("default"),
+            Some(&"nixpkgs.legacyPackages.x86_64-linux.hello".to_string())
+        );
+        assert!(fragments.package_outputs.contains_key("tool"));
+        assert!(fragments.packages.is_empty());
+    }
+
+    #[test]
+    fn test_raw_fallback_for_unsupported_subexpression() {
+        let result = parse_nix_expr_lenient(r#"{ a = ( 1 ~ "(" ); b = 2; }"#).unwrap();
+        match &result {
+            NixExpr::AttrSet { bindings, .. } => {
+                assert_eq!(bindings[0].value, NixExpr::Raw(r#"( 1 ~ "(" )"#.to_string()));
+                assert_eq!(bindings[1].value, NixExpr::Integer(2));
+            }
+            _ => panic!("Expected AttrSet, got {result:?}"),
+        }
+        assert!(result.to_nix_string().contains(r#"a = ( 1 ~ "(" );"#));
+
+        // Unbalanced input or a wholly unparseable expression is still an error
+        assert!(parse_nix_expr_lenient("{ a = ( 1 ~ 2; }").is_err());
+        assert!(parse_nix_expr_lenient("( 1 ~ 2 )").is_err());
+
+        // The default parser reports syntax errors inside brackets instead of keeping them
+        assert!(parse_nix_expr(r#"{ a = ( 1 ~ "(" ); b = 2; }"#).is_err());
+        assert!(parse_nix_expr("{ env = { A = = 1; }; }").is_err());
+        assert!(parse_nix_expr_strict("{ env = { A = = 1; }; }").is_err());
+        assert!(extract_flake_fragments("{ outputs = { self }: { x = [ = ]; }; }").is_err());
+        assert!(extract_flake_fragments_lenient("{ outputs = { self }: { x = [ = ]; }; }").is_ok());
+
+        // Lenient mode ends with the call that asked for it, even when that call fails
+        assert!(parse_nix_expr_lenient("{ a = ( 1 ~ 2; }").is_err());
+        assert!(parse_nix_expr(r#"{ a = ( 1 ~ "(" ); b = 2; }"#).is_err());
+    }
+
+    #[test]
+    fn test_extraction_survives_unsupported_subexpression() {
+        let input = r#"{
+  description = "Partially unsupported";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      supportedSystems = [ "x86_64-linux" ];
+      exotic = ( 1 ~ 2 );
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs supportedSystems (system: f { pkgs = import nixpkgs { inherit system; }; });
+    in
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell { packages = with pkgs; [ hello jq ]; };
+      });
+    };
+}"#;
+        assert!(extract_flake_fragments(input).is_err());
+        let fragments = extract_flake_fragments_lenient(input).unwrap();
+        assert_eq!(fragments.header, "Partially unsupported");
+        assert!(fragments.packages.contains(&"jq".to_string()));
+        assert!(fragments.let_bindings.contains_key("supportedSystems"));
+        assert!(!fragments.let_bindings.contains_key("exotic"));
+    }
+
+    #[test]
+    fn test_comment_only_empty_collections() {
+        let list = parse_nix_expr("[ # nothing yet\n]").unwrap();
+        assert_eq!(list, NixExpr::List(vec![]));
+        assert_eq!(list.to_nix_string(), "[ ]");
+        assert_eq!(parse_nix_expr(&list.to_nix_string()).unwrap(), list);
+
+        let attrs = parse_nix_expr("{ # todo\n}").unwrap();
+        assert_eq!(attrs, NixExpr::AttrSet { recursive: false, bindings: vec![] });
+        assert_eq!(attrs.to_nix_string(), "{ }");
+        assert_eq!(parse_nix_expr(&attrs.to_nix_string()).unwrap(), attrs);
+
+        assert_eq!(parse_nix_expr("[]").unwrap().to_nix_string(), "[ ]");
+        assert_eq!(parse_nix_expr("{}").unwrap().to_nix_string(), "{ }");
+    }
+
+    #[test]
+    fn test_top_level_inherit_is_skipped() {
+        let input = r#"{
+  inherit (import ./meta.nix) description;
+  inherit nixConfig;
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: { };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert!(fragments.inputs.contains_key("nixpkgs"));
+        assert!(fragments.passthrough.is_empty());
+        assert!(fragments.header.is_empty());
+    }
+
+    #[test]
+    fn test_packages_with_throw_branch() {
+        let input = r#"{
+  description = "Darwin only";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell {
+          packages = if stdenv.isDarwin then [ a ] else throw "no";
+          nativeBuildInputs = if stdenv.isLinux then [ b ] else abort "unsupported";
+        };
+      });
+    };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.packages, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn test_imported_outputs_degrade_gracefully() {
+        let input = r#"{
+  description = "Outputs live in another file";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    flake-utils.url = "github:numtide/flake-utils";
+  };
+  outputs = inputs: import ./nix/outputs.nix inputs // (builtins.fromJSON (builtins.readFile ./extra.json));
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.header, "Outputs live in another file");
+        assert_eq!(fragments.inputs.len(), 2);
+        assert!(fragments.packages.is_empty());
+        assert!(fragments.overlays.is_empty());
+
+        let input = r#"{
+  description = "Section via import";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: {
+    overlays = import ./overlays.nix;
+    devShells = (import ./shells.nix { inherit nixpkgs; }).devShells;
+  };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.header, "Section via import");
+        assert!(fragments.inputs.contains_key("nixpkgs"));
+    }
+
+    #[test]
+    fn test_flakes_semantically_equal() {
+        let a = r#"{
+  description = "A";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: {
+    overlays.default = final: prev: { tool = prev.hello; };
+    devShells = forEachSupportedSystem ({ pkgs }: {
+      default = pkgs.mkShell { packages = with pkgs; [ hello jq ]; env = { FOO = "1"; }; };
+    });
+  };
+}"#;
+        let b = r#"{ description = "Same thing, reformatted";
+  inputs = { nixpkgs = { url = "github:NixOS/nixpkgs/nixos-unstable"; }; };
+  outputs = { self, nixpkgs }:
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell {
+          env = { FOO = "1"; };
+          packages = with pkgs; [
+            jq
+            hello
+          ];
+        };
+      });
+      overlays.default = final: prev: {
+        tool = prev.hello;
+      };
+    };
+}"#;
+        assert!(flakes_semantically_equal(a, b).unwrap());
+
+        let extra_package = a.replace("[ hello jq ]", "[ hello jq ripgrep ]");
+        assert!(!flakes_semantically_equal(a, &extra_package).unwrap());
+        assert!(flakes_semantically_equal(a, "{ invalid").is_err());
+    }
+
+    #[test]
+    fn test_packages_from_let_bound_list() {
+        let input = r#"{
+  description = "Indirect packages";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      sharedTools = with pkgs; [ git jq ];
+    in
+    {
+      devShells = forEachSupportedSystem ({ pkgs }:
+        let
+          devTools = with pkgs; [ a b ];
+          loop = loop;
+        in
+        {
+          default = pkgs.mkShell { packages = devTools ++ sharedTools ++ loop; };
+        });
+    };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        for package in ["a", "b", "git", "jq"] {
+            assert!(fragments.packages.contains(&package.to_string()), "missing {package}");
+        }
+        assert_eq!(fragments.packages.len(), 4);
+    }
+
+    #[test]
+    fn test_strict_parsing_rejects_duplicate_attributes() {
+        let input = "{ a = 1; a = 2; }";
+        let err = parse_nix_expr_strict(input).unwrap_err().to_string();
+        assert!(err.contains("attribute 'a' already defined"), "unexpected error: {err}");
+
+        // Lenient parsing keeps both bindings
+        match parse_nix_expr(input).unwrap() {
+            NixExpr::AttrSet { bindings, .. } => assert_eq!(bindings.len(), 2),
+            other => panic!("Expected AttrSet, got {other:?}"),
+        }
+
+        // Duplicates are found in nested sets, let blocks and via inherit
+        assert!(parse_nix_expr_strict("{ x = { b.c = 1; b.c = 2; }; }").is_err());
+        assert!(parse_nix_expr_strict("let a = 1; a = 2; in a").is_err());
+        assert!(parse_nix_expr_strict("{ inherit a; a = 2; }").is_err());
+
+        // Distinct nested paths are fine
+        assert!(parse_nix_expr_strict("{ a.b = 1; a.c = 2; }").is_ok());
+    }
+
+    #[test]
+    fn test_extract_packages_from_flat_devshells_paths() {
+        let input = r#"{
+  description = "Flat devShells paths";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      pkgs = nixpkgs.legacyPackages.x86_64-linux;
+    in
+    {
+      devShells.default = pkgs.mkShell { packages = with pkgs; [ hello ]; };
+      devShells.x86_64-linux.ci = pkgs.mkShell { packages = with pkgs; [ jq ]; };
+      devShells.${system}.docs = pkgs.mkShell { packages = with pkgs; [ mdbook ]; };
+    };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        for package in ["hello", "jq", "mdbook"] {
+            assert!(fragments.packages.contains(&package.to_string()), "missing {package}");
+        }
+    }
+
+    #[test]
+    fn test_extract_concatenated_shell_hook() {
+        let input = r#"{
+  description = "Concatenated shell hook";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      pkgs = nixpkgs.legacyPackages.x86_64-linux;
+      preHook = "echo pre";
+    in
+    {
+      devShells.default = pkgs.mkShell {
+        packages = with pkgs; [ hello ];
+        shellHook = preHook + ''
+          echo ${pkgs.hello}
+        '' + "echo post";
+      };
+    };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.shell_hooks.len(), 1);
+        let hook = &fragments.shell_hooks[0];
+        assert!(hook.starts_with("echo pre"), "{hook}");
+        assert!(hook.contains("echo ${pkgs.hello}"), "{hook}");
+        assert!(hook.ends_with("echo post"), "{hook}");
+    }
+
+    #[test]
+    fn test_free_identifiers() {
+        let free = |input: &str| {
+            free_identifiers(&parse_nix_expr(input).unwrap())
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(free("let x = 1; in x + y"), vec!["y"]);
+        assert_eq!(free("{ pkgs, lib ? pkgs.lib }: [ pkgs.hello lib rustc ]"), vec!["rustc"]);
+        assert_eq!(free("rec { a = b; b = c; }"), vec!["c"]);
+        assert_eq!(free("{ a = b; b = 1; }"), vec!["b"]);
+        assert_eq!(free("let inherit (src) version; in x: x + version"), vec!["src"]);
+        assert_eq!(free("let inherit name; in name"), vec!["name"]);
+        assert_eq!(free("with pkgs; [ \"${hello}/bin\" ]"), vec!["hello", "pkgs"]);
+    }
+
+    #[test]
+    fn test_overlay_let_helpers_are_not_overlay_attrs() {
+        let input = r#"{
+  description = "Overlay with let helpers";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: {
+    overlays.default = final: prev:
+      let
+        basePython = prev.python312;
+      in
+      {
+        python = basePython.override { x11Support = false; };
+        nodejs = prev.nodejs_22;
+      };
+    devShells = { default = pkgs.mkShell { packages = with pkgs; [ python ]; }; };
+  };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        let bindings = &fragments.overlays["default"];
+        let names: Vec<String> = bindings.iter().map(|b| b.path.to_nix_string()).collect();
+        assert_eq!(names, vec!["python", "nodejs"]);
+
+        // The helper travels with the attribute that uses it
+        let python = bindings[0].value.to_nix_string();
+        assert!(python.starts_with("let\n  basePython = prev.python312;\nin\n"), "{python}");
+        assert_eq!(bindings[1].value.to_nix_string(), "prev.nodejs_22");
+        parse_nix_expr(&python).unwrap();
+    }
+
+    #[test]
+    fn test_extract_nested_input_follows() {
+        let input = r#"{
+  description = "Nested follows";
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    flake-utils.url = "github:numtide/flake-utils";
+    crane = {
+      url = "github:ipetkov/crane";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+    fenix.url = "github:nix-community/fenix";
+    fenix.inputs.rust-analyzer-src.inputs.flake-utils.follows = "flake-utils";
+  };
+  inputs.fenix.inputs.nixpkgs.follows = "nixpkgs";
+  outputs = { self, nixpkgs, flake-utils, crane, fenix }: {
+    devShells = { default = pkgs.mkShell { packages = with pkgs; [ cargo ]; }; };
+  };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+        let follows = |input: &str| {
+            let mut follows: Vec<(String, String)> = fragments.input_follows[input]
+                .iter()
+                .map(|f| (f.path.join("."), f.target.clone()))
+                .collect();
+            follows.sort();
+            follows
+        };
+
+        assert_eq!(follows("crane"), vec![("nixpkgs".to_string(), "nixpkgs".to_string())]);
+        assert_eq!(
+            follows("fenix"),
+            vec![
+                ("nixpkgs".to_string(), "nixpkgs".to_string()),
+                ("rust-analyzer-src.flake-utils".to_string(), "flake-utils".to_string()),
+            ]
+        );
+        assert!(!fragments.input_follows.contains_key("flake-utils"));
+    }
+
+    #[test]
+    fn test_or_as_attribute_name_and_select_default() {
+        let attrset = parse_nix_expr("{ or = 1; }").unwrap();
+        match &attrset {
+            NixExpr::AttrSet { bindings, .. } => {
+                assert_eq!(bindings[0].path.parts, vec![AttrPathPart::Identifier("or".to_string())]);
+            }
+            other => panic!("Expected attrset, got {other:?}"),
+        }
+
+        let select = parse_nix_expr("pkgs.or").unwrap();
+        assert_eq!(select, NixExpr::Select {
+            expr: Box::new(NixExpr::Identifier("pkgs".to_string())),
+            path: AttrPath { parts: vec![AttrPathPart::Identifier("or".to_string())] },
+            default: None,
+        });
+
+        let default = parse_nix_expr("a.b or c").unwrap();
+        assert_eq!(default, NixExpr::Select {
+            expr: Box::new(NixExpr::Identifier("a".to_string())),
+            path: AttrPath { parts: vec![AttrPathPart::Identifier("b".to_string())] },
+            default: Some(Box::new(NixExpr::Identifier("c".to_string()))),
+        });
+
+        // Both meanings in one expression
+        let both = parse_nix_expr("x.or or y").unwrap();
+        assert!(matches!(&both, NixExpr::Select { default: Some(_), .. }));
+
+        for expr in [attrset, select, default, both] {
+            assert_eq!(parse_nix_expr(&expr.to_nix_string()).unwrap(), expr);
+        }
+    }
+
+    #[test]
+    fn test_extract_from_mkshell_variants() {
+        let shell_body = r#"{
+            packages = with pkgs; [ hello ];
+            env = { GREETING = "hi"; };
+            shellHook = "echo ready";
+          }"#;
+        for call in ["pkgs.mkShellNoCC", "pkgs.mkShell.override { stdenv = pkgs.clangStdenv; }", "pkgs.stdenv.mkDerivation"] {
+            let input = format!(r#"{{
+  description = "Shell variant";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = {{ self, nixpkgs }}:
+    let
+      pkgs = nixpkgs.legacyPackages.x86_64-linux;
+    in
+    {{
+      devShells.x86_64-linux.default = {call} {shell_body};
+    }};
+}}"#);
+            let fragments = extract_flake_fragments(&input).unwrap();
+            assert_eq!(fragments.packages, vec!["hello".to_string()], "{call}");
+            assert_eq!(fragments.env_vars.get("GREETING").map(String::as_str), Some("hi"), "{call}");
+            assert_eq!(fragments.shell_hooks, vec!["echo ready".to_string()], "{call}");
+        }
+    }
+
+    #[test]
+    fn test_quoted_attr_keys_with_escapes() {
+        let key = |input: &str| match parse_nix_expr(input).unwrap() {
+            NixExpr::AttrSet { bindings, .. } => bindings[0].path.parts[0].clone(),
+            other => panic!("Expected attrset, got {other:?}"),
+        };
+
+        // An escaped quote is stored as a plain quote
+        assert_eq!(key(r#"{ "a\"b" = 1; }"#), AttrPathPart::String("a\"b".to_string()));
+        // `\${` is a literal dollar, not an interpolation
+        assert_eq!(key(r#"{ "go_\${version}" = 1; }"#), AttrPathPart::String(r"go_\${version}".to_string()));
+        // `''` has no special meaning in a double-quoted key, so `${version}` still interpolates
+        assert_eq!(key(r#"{ "go_''${version}" = 1; }"#), AttrPathPart::String("go_''${version}".to_string()));
+        assert_eq!(
+            key(r#"{ "${version}" = 1; }"#),
+            AttrPathPart::Interpolation(Box::new(NixExpr::Identifier("version".to_string())))
+        );
+
+        for input in [
+            r#"{ "a\"b" = 1; }"#,
+            r#"{ "go_\${version}" = 1; }"#,
+            r#"{ "go_''${version}" = 1; }"#,
+            r#"{ "a\"${version}" = "x\"y"; }"#,
+            r#"pkgs."go_\${version}""#,
+        ] {
+            let expr = parse_nix_expr(input).unwrap();
+            assert_eq!(parse_nix_expr(&expr.to_nix_string()).unwrap(), expr, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_parse_at_patterns() {
+        let leading = parse_nix_expr("inputs@{ self, nixpkgs, ... }: inputs").unwrap();
+        match &leading {
+            NixExpr::Lambda { param: LambdaParam::Pattern { params, ellipsis, bind }, .. } => {
+                assert!(ellipsis);
+                assert_eq!(params.len(), 2);
+                assert_eq!(bind.as_deref(), Some("inputs"));
+            }
+            _ => panic!("Expected Lambda with pattern"),
+        }
+        assert_eq!(leading.to_nix_string(), "inputs@{ self, nixpkgs, ... }: inputs");
+        assert_eq!(parse_nix_expr(&leading.to_nix_string()).unwrap(), leading);
+
+        let trailing = parse_nix_expr("{ self, ... } @ args: args").unwrap();
+        match &trailing {
+            NixExpr::Lambda { param: LambdaParam::Pattern { bind, .. }, .. } => {
+                assert_eq!(bind.as_deref(), Some("args"));
+            }
+            _ => panic!("Expected Lambda with pattern"),
+        }
+        assert!(free_identifiers(&trailing).is_empty());
+    }
+
+    #[test]
+    fn test_extract_resolves_inputs_through_at_pattern() {
+        let input = r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    flake-utils.url = "github:numtide/flake-utils";
+  };
+  outputs = inputs@{ self, ... }:
+    let
+      pinnedNixpkgs = inputs.nixpkgs;
+      nixpkgsPath = "${inputs.nixpkgs}/lib";
+    in
+    inputs.flake-utils.lib.eachDefaultSystem (system: {
+      devShells.default = pkgs.mkShell { packages = with pkgs; [ hello ]; };
+    });
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+
+        assert_eq!(fragments.let_bindings["pinnedNixpkgs"], "nixpkgs");
+        assert_eq!(fragments.let_bindings["nixpkgsPath"], "\"${nixpkgs}/lib\"");
+        assert_eq!(fragments.packages, vec!["hello".to_string()]);
+    }
+
+    #[test]
+    fn test_string_escapes_follow_nix() {
+        let string = |input: &str| parse_nix_expr(input).unwrap();
+
+        // Only `\n`, `\r` and `\t` are special; any other `\c` is `c`, so there are no unicode escapes
+        assert_eq!(string(r#""\u00e9""#), NixExpr::String("u00e9".to_string()));
+        assert_eq!(string(r#""\a""#), NixExpr::String("a".to_string()));
+        assert_eq!(string(r#""a\nb\tc\\d""#), NixExpr::String("a\nb\tc\\d".to_string()));
+        // `\$` is a plain dollar, so `\${` does not interpolate
+        assert_eq!(string(r#""\$HOME""#), NixExpr::String("$HOME".to_string()));
+        assert_eq!(string(r#""\${HOME}""#), NixExpr::String("${HOME}".to_string()));
+        assert_eq!(
+            string(r#""\${a}${b}""#),
+            NixExpr::InterpolatedString(vec![
+                StringPart::Literal("${a}".to_string()),
+                StringPart::Interpolation(Box::new(NixExpr::Identifier("b".to_string()))),
+            ])
+        );
+
+        for input in [r#""\u00e9""#, r#""a\nb\\c""#, r#""\${HOME}""#, r#""\${a}${b}\"""#] {
+            let expr = string(input);
+            assert_eq!(parse_nix_expr(&expr.to_nix_string()).unwrap(), expr, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_indented_string_escapes_and_interpolation() {
+        let expr = parse_nix_expr("''\n  run ${pkgs.hello}/bin/hello ''${HOME} it'''s ''\\n\n''").unwrap();
+        assert_eq!(
+            expr,
+            NixExpr::InterpolatedString(vec![
+                StringPart::Literal("run ".to_string()),
+                StringPart::Interpolation(Box::new(parse_nix_expr("pkgs.hello").unwrap())),
+                StringPart::Literal("/bin/hello ${HOME} it''s \n\n".to_string()),
+            ])
+        );
+        assert_eq!(parse_nix_expr(&expr.to_nix_string()).unwrap(), expr);
+        assert_eq!(parse_nix_expr("''it's''").unwrap(), NixExpr::String("it's".to_string()));
+    }
+
+    #[test]
+    fn test_escape_indented_string() {
+        assert_eq!(escape_indented_string("echo ${HOME}"), "echo ''${HOME}");
+        assert_eq!(escape_indented_string("echo ''quoted''"), "echo '''quoted'''");
+        assert_eq!(escape_indented_string("plain $VAR"), "plain $VAR");
+        assert_eq!(escape_indented_string("echo '${HOME}'"), "echo ''\\'''${HOME}'");
+
+        // The escaped text reads back as the original
+        let hook = "echo '${HOME}' ''${x}";
+        let parsed = parse_nix_expr(&format!("''{}''", escape_indented_string(hook))).unwrap();
+        assert_eq!(parsed, NixExpr::String(hook.to_string()));
+    }
+
+    #[test]
+    fn test_indented_string_strips_indentation() {
+        let string = |input: &str| parse_nix_expr(input).unwrap();
+        assert_eq!(
+            string("''\n    echo a\n\n      echo b\n   \n    echo c\n  ''"),
+            NixExpr::String("echo a\n\n  echo b\n\necho c\n".to_string())
+        );
+        // An interpolation counts as text, so it sets the indentation like any other line
+        assert_eq!(
+            string("''\n      run\n    ${cmd}\n''"),
+            NixExpr::InterpolatedString(vec![
+                StringPart::Literal("  run\n".to_string()),
+                StringPart::Interpolation(Box::new(NixExpr::Identifier("cmd".to_string()))),
+                StringPart::Literal("\n".to_string()),
+            ])
+        );
+        // Only spaces are indentation: a line starting with a tab leaves nothing to strip
+        assert_eq!(string("''  a\n  \tb''"), NixExpr::String("a\n\tb".to_string()));
+        assert_eq!(string("''  a\n\t  b''"), NixExpr::String("  a\n\t  b".to_string()));
+    }
+
+    #[test]
+    fn test_application_chain_folds_left() {
+        let ident = |name: &str| NixExpr::Identifier(name.to_string());
+        let call = |function: NixExpr, argument: NixExpr| NixExpr::FunctionCall {
+            function: Box::new(function),
+            argument: Box::new(argument),
+        };
+
+        assert_eq!(
+            parse_nix_expr("f a b c d").unwrap(),
+            call(call(call(call(ident("f"), ident("a")), ident("b")), ident("c")), ident("d"))
+        );
+        // Selects bind tighter than application, and a leading `.` can still start a path argument
+        assert_eq!(
+            parse_nix_expr("f a.b ./src").unwrap(),
+            call(call(ident("f"), parse_nix_expr("a.b").unwrap()), NixExpr::Path("./src".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_nested_application_parses_in_linear_time() {
+        let chain = |depth: usize| format!("{}x{}", "genAttrs systems (system: f ".repeat(depth), ")".repeat(depth));
+        // Best of a few runs, to keep scheduling noise out of the comparison
+        let parse_time = |source: &str| {
+            (0..5)
+                .map(|_| {
+                    let start = std::time::Instant::now();
+                    parse_nix_expr(source).unwrap();
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+
+        let short = parse_time(&chain(10));
+        let long = parse_time(&chain(40));
+        // Four times the depth should take about four times as long; quadratic growth would be 16x
+        assert!(long < short * 10, "depth 10 took {short:?}, depth 40 took {long:?}");
+    }
+
+    #[test]
+    fn test_path_boundaries() {
+        let path = |p: &str| Box::new(NixExpr::Path(p.to_string()));
+
+        // `-` is a path character, so only spaced subtraction splits a path
+        assert_eq!(parse_nix_expr("./a-b").unwrap(), *path("./a-b"));
+        assert_eq!(
+            parse_nix_expr("./a - b").unwrap(),
+            NixExpr::BinaryOp { left: path("./a"), op: BinaryOperator::Sub, right: Box::new(NixExpr::Identifier("b".to_string())) }
+        );
+        assert_eq!(
+            parse_nix_expr("[ ./foo.nix ../bar ]").unwrap(),
+            NixExpr::List(vec![*path("./foo.nix"), *path("../bar")])
+        );
+        // Segments can't be empty, so `//` is the update operator and a trailing `/` is rejected
+        assert_eq!(
+            parse_nix_expr("./a//b").unwrap(),
+            NixExpr::BinaryOp { left: path("./a"), op: BinaryOperator::Update, right: Box::new(NixExpr::Identifier("b".to_string())) }
+        );
+        assert!(parse_nix_expr("./a/").is_err());
+    }
+
+    #[test]
+    fn test_structural_equality_ignores_number_formatting() {
+        assert_ne!(NixExpr::Float(1.0), NixExpr::Integer(1));
+        assert!(NixExpr::Float(1.0).structurally_eq(&NixExpr::Integer(1)));
+        assert!(NixExpr::Integer(1).structurally_eq(&NixExpr::Float(1.0)));
+        assert!(!NixExpr::Float(1.5).structurally_eq(&NixExpr::Integer(1)));
+        assert!(NixExpr::Float(0.1 + 0.2).structurally_eq(&NixExpr::Float(0.3)));
+
+        let original = parse_nix_expr("{ a = [ 1.0 2.5 ]; b = x: x * 3.0; }").unwrap();
+        let reformatted = parse_nix_expr("{ a = [ 1 2.5 ]; b = x: x * 3; }").unwrap();
+        assert_ne!(original, reformatted);
+        assert!(original.structurally_eq(&reformatted));
+
+        let different = parse_nix_expr("{ a = [ 1 2.5 ]; c = x: x * 3; }").unwrap();
+        assert!(!original.structurally_eq(&different));
+    }
+
+    #[test]
+    fn test_extract_let_wrapped_flake() {
+        let input = r#"let
+  goVersion = 22;
+in
+{
+  description = "Let-wrapped environment";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: {
+    devShells.default = pkgs.mkShell { packages = with pkgs; [ go gotools ]; };
+  };
+}"#;
+        let flake = parse_flake(input).unwrap();
+        assert_eq!(flake.description.as_deref(), Some("Let-wrapped environment"));
+        assert!(flake.outputs.is_some());
+
+        let fragments = extract_flake_fragments(input).unwrap();
+        assert_eq!(fragments.header, "Let-wrapped environment");
+        assert_eq!(fragments.inputs["nixpkgs"], "github:NixOS/nixpkgs/nixos-unstable");
+        assert_eq!(fragments.let_bindings["goVersion"], "22");
+        assert_eq!(fragments.packages, vec!["go".to_string(), "gotools".to_string()]);
+
+        let named = extract_flake_fragments(
+            r#"let desc = "Named"; in { description = desc; outputs = { self }: { }; }"#,
+        )
+        .unwrap();
+        assert_eq!(named.header, "Named");
+    }
+
+    #[test]
+    fn test_render_parenthesizes_compound_operands() {
+        for source in [
+            "genAttrs systems (system: f { inherit system; })",
+            "[ (f x) (-1) a.b ]",
+            "(f x).y",
+            "(a + b) * c",
+            "a - (b - c)",
+            "(a ++ b) ++ c",
+            "a == b && !c",
+        ] {
+            let expr = parse_nix_expr(source).unwrap();
+            let rendered = expr.to_nix_string();
+            assert_eq!(parse_nix_expr(&rendered).unwrap(), expr, "{source} rendered as {rendered}");
+        }
+        assert_eq!(parse_nix_expr("a == b && !c").unwrap().to_nix_string(), "a == b && !c");
+    }
+
+    #[test]
+    fn test_extract_legacy_packages_template() {
+        let input = r#"{
+  description = "legacyPackages-based environment";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }:
+    let
+      supportedSystems = [ "x86_64-linux" "aarch64-darwin" ];
+      forEachSupportedSystem = f: nixpkgs.lib.genAttrs supportedSystems (system: f {
+        pkgs = nixpkgs.legacyPackages.${system};
+      });
+    in
+    {
+      devShells = forEachSupportedSystem ({ pkgs }: {
+        default = pkgs.mkShell {
+          packages = with pkgs; [ ripgrep jq ];
+          shellHook = "echo ready";
+        };
+      });
+    };
+}"#;
+        let fragments = extract_flake_fragments(input).unwrap();
+
+        assert_eq!(fragments.packages, vec!["ripgrep".to_string(), "jq".to_string()]);
+        assert_eq!(fragments.shell_hooks, vec!["echo ready".to_string()]);
+        assert_eq!(
+            fragments.let_bindings["supportedSystems"],
+            r#"[ "x86_64-linux" "aarch64-darwin" ]"#
+        );
+        // There is no `import nixpkgs { config = ...; }` to read options from
+        assert!(!fragments.allow_unfree);
+
+        let per_shell = r#"{
+  outputs = { self, nixpkgs }: {
+    devShells.x86_64-linux.default =
+      let pkgs = nixpkgs.legacyPackages.x86_64-linux;
+      in pkgs.mkShell { packages = [ pkgs.hello ]; };
+  };
+}"#;
+        assert_eq!(extract_flake_fragments(per_shell).unwrap().packages, vec!["hello".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_strips_byte_order_mark() {
+        let flake = include_str!("templates/go.nix");
+        let expected = parse_nix_expr(flake).unwrap();
+
+        assert_eq!(parse_nix_expr(&format!("\u{feff}{flake}")).unwrap(), expected);
+        // A `#!` line is an ordinary comment to Nix
+        assert_eq!(parse_nix_expr(&format!("\u{feff}#!/usr/bin/env nix\n{flake}")).unwrap(), expected);
+        assert_eq!(
+            extract_flake_fragments(&format!("\u{feff}{flake}")).unwrap().packages,
+            extract_flake_fragments(flake).unwrap().packages
+        );
+    }
+
+    #[test]
+    fn test_rust_overlay_toolchain_round_trips() {
+        let fragments = extract_flake_fragments(include_str!("templates/rust.nix")).unwrap();
+        let binding = fragments.overlays["default"]
+            .iter()
+            .find(|binding| binding.path.to_nix_string() == "rustToolchain")
+            .expect("rust overlay should define rustToolchain");
+
+        let rendered = binding.value.to_nix_string();
+        assert!(rendered.contains("if builtins.pathExists ./rust-toolchain.toml then"), "{rendered}");
+        assert!(rendered.contains("rust.fromRustupToolchainFile ./rust-toolchain"), "{rendered}");
+        assert_eq!(parse_nix_expr(&rendered).unwrap(), binding.value);
+
+        let NixExpr::LetIn { body, .. } = &binding.value else {
+            panic!("expected a let block, got {:?}", binding.value);
+        };
+        let NixExpr::If { condition, .. } = body.as_ref() else {
+            panic!("expected an if, got {body:?}");
+        };
+        assert_eq!(condition.to_nix_string(), "builtins.pathExists ./rust-toolchain.toml");
+    }
+
+    #[test]
+    fn test_nested_interpolation() {
+        let input = r#""a${f "b${g "c${x}"}"}d""#;
+        let expr = parse_nix_expr(input).unwrap();
+
+        let interpolated = |parts: Vec<StringPart>| NixExpr::InterpolatedString(parts);
+        let call = |function: &str, argument: NixExpr| NixExpr::FunctionCall {
+            function: Box::new(NixExpr::Identifier(function.to_string())),
+            argument: Box::new(argument),
+        };
+        let literal = |text: &str| StringPart::Literal(text.to_string());
+        let innermost = interpolated(vec![literal("c"), StringPart::Interpolation(Box::new(NixExpr::Identifier("x".to_string())))]);
+        let middle = interpolated(vec![literal("b"), StringPart::Interpolation(Box::new(call("g", innermost)))]);
+        let expected = interpolated(vec![
+            literal("a"),
+            StringPart::Interpolation(Box::new(call("f", middle))),
+            literal("d"),
+        ]);
+        assert_eq!(expr, expected);
+
+        // The closing brace of each interpolation is the one after its own string, not one inside it
+        assert_eq!(expr.to_nix_string(), input);
+        assert_eq!(parse_nix_expr(r#""${ "${"}"}" }""#).unwrap().to_nix_string(), r#""${"${"}"}"}""#);
+    }
+
+    #[test]
+    fn test_search_paths_and_comparisons_coexist() {
+        let ident = |name: &str| Box::new(NixExpr::Identifier(name.to_string()));
+        let search_path = |path: &str| Box::new(NixExpr::Path(path.to_string()));
+        let compare = |left, op, right| NixExpr::BinaryOp { left, op, right };
+
+        assert_eq!(parse_nix_expr("x < y").unwrap(), compare(ident("x"), BinaryOperator::Lt, ident("y")));
+        assert_eq!(parse_nix_expr("x<y").unwrap(), compare(ident("x"), BinaryOperator::Lt, ident("y")));
+        assert_eq!(parse_nix_expr("x > y").unwrap(), compare(ident("x"), BinaryOperator::Gt, ident("y")));
+        assert_eq!(parse_nix_expr("x <= y").unwrap(), compare(ident("x"), BinaryOperator::Le, ident("y")));
+
+        assert_eq!(
+            parse_nix_expr("import <nixpkgs>").unwrap(),
+            NixExpr::FunctionCall { function: ident("import"), argument: search_path("<nixpkgs>") }
+        );
+        assert_eq!(*search_path("<nixpkgs/lib>"), parse_nix_expr("<nixpkgs/lib>").unwrap());
+        assert_eq!(
+            parse_nix_expr("a < <nixpkgs>").unwrap(),
+            compare(ident("a"), BinaryOperator::Lt, search_path("<nixpkgs>"))
+        );
+        assert_eq!(
+            parse_nix_expr("<nixpkgs> > b").unwrap(),
+            compare(search_path("<nixpkgs>"), BinaryOperator::Gt, ident("b"))
+        );
+
+        let expr = parse_nix_expr("import <nixpkgs> { }").unwrap();
+        assert_eq!(expr.to_nix_string(), "import <nixpkgs> { }");
+    }
+
+    #[test]
+    fn test_interpolated_description_becomes_single_line_header() {
+        let resolved = extract_flake_fragments(
+            r#"let lang = "Zig"; in { description = "Dev env for ${lang}"; outputs = { self }: { }; }"#,
+        )
+        .unwrap();
+        assert_eq!(resolved.header, "Dev env for Zig");
+
+        let multiline = extract_flake_fragments(
+            "{\n  description = ''\n    Dev env for ${lang}\n    and friends\n  '';\n  outputs = { self }: { };\n}",
+        )
+        .unwrap();
+        assert_eq!(multiline.header, "Dev env for lang and friends");
+    }
+
+    #[test]
+    fn test_inherit_several_names() {
+        let expr = parse_nix_expr("{ inherit (prev) cmake ninja; inherit a b c; }").unwrap();
+        let NixExpr::AttrSet { bindings, .. } = &expr else {
+            panic!("expected an attribute set, got {expr:?}");
+        };
+        assert_eq!(
+            bindings[0].value,
+            NixExpr::Inherit {
+                from: Some(Box::new(NixExpr::Identifier("prev".to_string()))),
+                attrs: vec!["cmake".to_string(), "ninja".to_string()],
+            }
+        );
+        assert_eq!(bindings[1].value, NixExpr::Inherit { from: None, attrs: vec!["a".into(), "b".into(), "c".into()] });
+    }
+
+    #[test]
+    fn test_binary_operators_group_by_precedence() {
+        let ident = |name: &str| Box::new(NixExpr::Identifier(name.to_string()));
+        let int = |value: i64| Box::new(NixExpr::Integer(value));
+        let op = |left, op, right| Box::new(NixExpr::BinaryOp { left, op, right });
+
+        assert_eq!(
+            parse_nix_expr("x == 1 || y == 2").unwrap(),
+            *op(op(ident("x"), BinaryOperator::Eq, int(1)), BinaryOperator::Or, op(ident("y"), BinaryOperator::Eq, int(2)))
+        );
+        assert_eq!(
+            parse_nix_expr("a + b * c").unwrap(),
+            *op(ident("a"), BinaryOperator::Add, op(ident("b"), BinaryOperator::Mul, ident("c")))
+        );
+        assert_eq!(
+            parse_nix_expr("a - b - c").unwrap(),
+            *op(op(ident("a"), BinaryOperator::Sub, ident("b")), BinaryOperator::Sub, ident("c"))
+        );
+        assert_eq!(
+            parse_nix_expr("a ++ b ++ c").unwrap(),
+            *op(ident("a"), BinaryOperator::Concat, op(ident("b"), BinaryOperator::Concat, ident("c")))
+        );
+
+        // Rendering keeps the meaning without adding parentheses the source didn't need
+        for source in [
+            "x == 1 || y == 2",
+            "a + b * c",
+            "a * b + c",
+            "a || b && c -> d",
+            "a // b // c",
+            "x < y + 1 && z",
+            "xs ++ ys == zs",
+        ] {
+            let expr = parse_nix_expr(source).unwrap();
+            assert_eq!(expr.to_nix_string(), source);
+            assert_eq!(parse_nix_expr(&expr.to_nix_string()).unwrap(), expr);
+        }
+    }
+
+    #[test]
+    fn test_pipe_operators_bind_loosest() {
+        let ident = |name: &str| Box::new(NixExpr::Identifier(name.to_string()));
+        let op = |left, op, right| Box::new(NixExpr::BinaryOp { left, op, right });
+
+        // `<|` groups to the right, `|>` to the left
+        assert_eq!(
+            parse_nix_expr("f <| g <| x").unwrap(),
+            *op(ident("f"), BinaryOperator::PipeBackward, op(ident("g"), BinaryOperator::PipeBackward, ident("x")))
+        );
+        assert_eq!(
+            parse_nix_expr("x |> f |> g").unwrap(),
+            *op(op(ident("x"), BinaryOperator::PipeForward, ident("f")), BinaryOperator::PipeForward, ident("g"))
+        );
+
+        // Every other operator binds tighter than a pipe
+        assert_eq!(
+            parse_nix_expr("a ++ b |> f").unwrap(),
+            *op(op(ident("a"), BinaryOperator::Concat, ident("b")), BinaryOperator::PipeForward, ident("f"))
+        );
+        assert_eq!(
+            parse_nix_expr("f <| a -> b").unwrap(),
+            *op(ident("f"), BinaryOperator::PipeBackward, op(ident("a"), BinaryOperator::Implication, ident("b")))
+        );
+
+        for source in ["f <| g <| x", "x |> f |> g", "a ++ b |> f", "(f <| x) |> g", "f <| (x |> g)"] {
+            let expr = parse_nix_expr(source).unwrap();
+            assert_eq!(expr.to_nix_string(), source);
+        }
+    }
+
+    #[test]
+    fn test_list_item_selects_from_parenthesized_expression() {
+        let list = parse_nix_expr("[ (f x).c d ]").unwrap();
+        assert_eq!(list.to_nix_string(), "[ (f x).c d ]");
+        assert!(matches!(&list, NixExpr::List(items) if items.len() == 2));
+    }
+}
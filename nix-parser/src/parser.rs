@@ -2,12 +2,13 @@ use crate::ast::*;
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_until, take_while, take_while1},
-    character::complete::{alpha1, char, digit1, multispace1},
-    combinator::{map, opt, recognize, value},
+    character::complete::{alpha1, anychar, char, digit1, multispace1, satisfy},
+    combinator::{map, not, opt, recognize, value, verify},
     multi::{many0, many1, separated_list0, separated_list1},
     sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
+use std::cell::Cell;
 
 // Core parser combinators
 pub fn nix_expr(input: &str) -> IResult<&str, NixExpr> {
@@ -22,16 +23,38 @@ pub fn nix_expr(input: &str) -> IResult<&str, NixExpr> {
 }
 
 fn nix_binary_expr(input: &str) -> IResult<&str, NixExpr> {
-    let (input, left) = nix_unary_expr(input)?;
-    let (input, ops) = many0(pair(ws(binary_operator), nix_unary_expr))(input)?;
-    
-    Ok((input, ops.into_iter().fold(left, |acc, (op, right)| {
-        NixExpr::BinaryOp {
-            left: Box::new(acc),
-            op,
-            right: Box::new(right),
+    let (input, first) = nix_unary_expr(input)?;
+    let (input, rest) = many0(pair(ws(binary_operator), nix_unary_expr))(input)?;
+    Ok((input, group_by_precedence(first, rest)))
+}
+
+// Operator precedence parsing over `first op1 e1 op2 e2 ...`: an operator is applied once the next
+// one binds looser, or equally tight and left-associative
+fn group_by_precedence(first: NixExpr, rest: Vec<(BinaryOperator, NixExpr)>) -> NixExpr {
+    fn apply(operands: &mut Vec<NixExpr>, op: BinaryOperator) {
+        let right = operands.pop().expect("an operator has a right operand");
+        let left = operands.pop().expect("an operator has a left operand");
+        operands.push(NixExpr::BinaryOp { left: Box::new(left), op, right: Box::new(right) });
+    }
+
+    let mut operands = vec![first];
+    let mut operators: Vec<BinaryOperator> = Vec::new();
+    for (op, operand) in rest {
+        while let Some(top) = operators.last() {
+            if top.precedence() > op.precedence() || (top.precedence() == op.precedence() && !op.is_right_associative()) {
+                let top = operators.pop().expect("checked above");
+                apply(&mut operands, top);
+            } else {
+                break;
+            }
         }
-    })))
+        operators.push(op);
+        operands.push(operand);
+    }
+    while let Some(op) = operators.pop() {
+        apply(&mut operands, op);
+    }
+    operands.pop().expect("one operand remains")
 }
 
 fn nix_unary_expr(input: &str) -> IResult<&str, NixExpr> {
@@ -48,13 +71,8 @@ fn nix_unary_expr(input: &str) -> IResult<&str, NixExpr> {
 
 fn nix_postfix_expr(input: &str) -> IResult<&str, NixExpr> {
     let (input, base) = nix_primary_expr(input)?;
-    let (input, ops) = many0(alt((
-        map(preceded(ws(char('.')), attr_path), PostfixOp::Select),
-        map(preceded(ws(tag(" or ")), nix_primary_expr), PostfixOp::SelectDefault),
-        map(preceded(ws(char('?')), attr_path), PostfixOp::HasAttr),
-        // Fix: Use skip_whitespace_and_comments for function call arguments to handle multi-line whitespace  
-        map(preceded(skip_whitespace_and_comments, nix_primary_expr), PostfixOp::FunctionCall),
-    )))(input)?;
+    // Skip whitespace (which may span lines and comments) once per operator, not once per alternative
+    let (input, ops) = many0(preceded(skip_whitespace_and_comments, postfix_op))(input)?;
     
     Ok((input, ops.into_iter().fold(base, |acc, op| match op {
         PostfixOp::FunctionCall(arg) => NixExpr::FunctionCall {
@@ -84,6 +102,50 @@ fn nix_postfix_expr(input: &str) -> IResult<&str, NixExpr> {
     })))
 }
 
+// Dispatch on the next character so plain application, by far the most common case,
+// goes straight to parsing the argument instead of failing through every operator first
+fn postfix_op(input: &str) -> IResult<&str, PostfixOp> {
+    // Selects bind tighter than application, so `f a.b` passes `a.b` to `f`
+    let call = |input| map(nix_select_default, PostfixOp::FunctionCall)(input);
+    match input.chars().next() {
+        // A leading `.` may also start a path argument, e.g. `f ./src`
+        Some('.') => alt((map(preceded(ws(char('.')), attr_path), PostfixOp::Select), call))(input),
+        Some('?') => map(preceded(ws(char('?')), attr_path), PostfixOp::HasAttr)(input),
+        Some('o') => alt((
+            map(preceded(ws(keyword("or")), nix_select_default), PostfixOp::SelectDefault),
+            call,
+        ))(input),
+        _ => call(input),
+    }
+}
+
+// A primary expression with an optional selection, which may itself have a default: the
+// default of `a.b or c.d`, and each argument of a function application
+fn nix_select_default(input: &str) -> IResult<&str, NixExpr> {
+    let (input, base) = nix_primary_expr(input)?;
+    select_from(input, base)
+}
+
+// Parse a parenthesized expression with an optional select, like `(f x).attr` in a list
+fn nix_parenthesized_select(input: &str) -> IResult<&str, NixExpr> {
+    let (input, base) = delimited(char('('), nix_expr, char(')'))(input)?;
+    select_from(input, base)
+}
+
+// Parse an optional `.path` or `.path or default` following `base`
+fn select_from(input: &str, base: NixExpr) -> IResult<&str, NixExpr> {
+    let (input, path) = opt(preceded(ws(char('.')), attr_path))(input)?;
+    let Some(path) = path else {
+        return Ok((input, base));
+    };
+    let (input, default) = opt(preceded(ws(keyword("or")), nix_select_default))(input)?;
+    Ok((input, NixExpr::Select {
+        expr: Box::new(base),
+        path,
+        default: default.map(Box::new),
+    }))
+}
+
 #[derive(Debug)]
 enum PostfixOp {
     FunctionCall(NixExpr),
@@ -92,6 +154,24 @@ enum PostfixOp {
     HasAttr(AttrPath),
 }
 
+thread_local! {
+    // Set for the duration of a `parse_lenient` call
+    static LENIENT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Parse like [`nix_expr`], but keep a bracketed construct that doesn't parse as
+/// `NixExpr::Raw` text instead of failing
+pub fn parse_lenient(input: &str) -> IResult<&str, NixExpr> {
+    struct Reset(bool);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            LENIENT.with(|lenient| lenient.set(self.0));
+        }
+    }
+    let _reset = Reset(LENIENT.with(|lenient| lenient.replace(true)));
+    nix_expr(input)
+}
+
 fn nix_primary_expr(input: &str) -> IResult<&str, NixExpr> {
     ws(alt((
         nix_attrset,
@@ -100,9 +180,78 @@ fn nix_primary_expr(input: &str) -> IResult<&str, NixExpr> {
         nix_literal,
         nix_identifier,
         delimited(char('('), nix_expr, char(')')),
+        raw_recovery,
     )))(input)
 }
 
+// Lenient parsing only: keep a bracketed construct we can't parse yet as raw text
+fn raw_recovery(input: &str) -> IResult<&str, NixExpr> {
+    if !LENIENT.with(Cell::get) {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
+    map(raw_balanced, |s: &str| NixExpr::Raw(s.to_string()))(input)
+}
+
+// Recognize a `(...)`, `[...]` or `{...}` span with balanced brackets, skipping over
+// strings and comments so brackets inside them don't count
+fn raw_balanced(input: &str) -> IResult<&str, &str> {
+    let fail = || nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify));
+    if !input.starts_with(['(', '[', '{']) {
+        return Err(fail());
+    }
+
+    let bytes = input.as_bytes();
+    let mut stack = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' => stack.push(b')'),
+            b'[' => stack.push(b']'),
+            b'{' => stack.push(b'}'),
+            b')' | b']' | b'}' => {
+                if stack.pop() != Some(bytes[i]) {
+                    return Err(fail());
+                }
+                if stack.is_empty() {
+                    return Ok((&input[i + 1..], &input[..=i]));
+                }
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            b'\'' if bytes.get(i + 1) == Some(&b'\'') => {
+                i += 2;
+                while i + 1 < bytes.len() {
+                    if bytes[i] == b'\'' && bytes[i + 1] == b'\'' {
+                        // `'''`, `''$` and `''\` are escapes, not the end of the string
+                        if matches!(bytes.get(i + 2), Some(b'\'' | b'$' | b'\\')) {
+                            i += 3;
+                            continue;
+                        }
+                        break;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Err(fail())
+}
+
 fn nix_literal(input: &str) -> IResult<&str, NixExpr> {
     alt((
         nix_string,
@@ -121,52 +270,129 @@ fn nix_string(input: &str) -> IResult<&str, NixExpr> {
             map(take_until("\""), |s: &str| NixExpr::String(s.to_string())),
             char('"'),
         ),
+        // Like Nix, a first line holding only spaces is not part of the string
         delimited(
-            tag("''"),
-            map(take_until("''"), |s: &str| NixExpr::String(s.to_string())),
+            pair(tag("''"), opt(pair(take_while(|c| c == ' '), char('\n')))),
+            map(many0(indented_string_part), |parts| string_from_parts(strip_indentation(parts))),
             tag("''"),
         ),
     ))(input)
 }
 
+/// Remove the indentation shared by every line of an indented string, as Nix does: lines holding
+/// only spaces don't count, an interpolation counts as text, and a last line of spaces is dropped
+fn strip_indentation(parts: Vec<StringPart>) -> Vec<StringPart> {
+    let mut min_indent = usize::MAX;
+    let (mut at_line_start, mut indent) = (true, 0);
+    for part in &parts {
+        match part {
+            StringPart::Literal(text) => {
+                for c in text.chars() {
+                    match (at_line_start, c) {
+                        (true, ' ') => indent += 1,
+                        (true, '\n') => indent = 0,
+                        (true, _) => {
+                            at_line_start = false;
+                            min_indent = min_indent.min(indent);
+                        }
+                        (false, '\n') => (at_line_start, indent) = (true, 0),
+                        (false, _) => {}
+                    }
+                }
+            }
+            StringPart::Interpolation(_) if at_line_start => {
+                at_line_start = false;
+                min_indent = min_indent.min(indent);
+            }
+            StringPart::Interpolation(_) => {}
+        }
+    }
+    if min_indent == usize::MAX {
+        min_indent = 0;
+    }
+
+    let last = parts.len().saturating_sub(1);
+    let (mut at_line_start, mut dropped) = (true, 0);
+    let mut stripped = Vec::with_capacity(parts.len());
+    for (i, part) in parts.into_iter().enumerate() {
+        match part {
+            StringPart::Literal(text) => {
+                let mut kept = String::with_capacity(text.len());
+                for c in text.chars() {
+                    if at_line_start && c == ' ' {
+                        dropped += 1;
+                        if dropped > min_indent {
+                            kept.push(c);
+                        }
+                        continue;
+                    }
+                    at_line_start = c == '\n';
+                    dropped = 0;
+                    kept.push(c);
+                }
+                if i == last {
+                    if let Some(newline) = kept.rfind('\n') {
+                        if kept[newline + 1..].chars().all(|c| c == ' ') {
+                            kept.truncate(newline + 1);
+                        }
+                    }
+                }
+                stripped.push(StringPart::Literal(kept));
+            }
+            interpolation => {
+                at_line_start = false;
+                dropped = 0;
+                stripped.push(interpolation);
+            }
+        }
+    }
+    stripped
+}
+
 fn nix_interpolated_string(input: &str) -> IResult<&str, NixExpr> {
     delimited(
         char('"'),
-        map(many0(string_part), |parts| {
-            if parts.len() == 1 && matches!(&parts[0], StringPart::Literal(_)) {
-                if let StringPart::Literal(s) = &parts[0] {
-                    NixExpr::String(s.clone())
-                } else {
-                    NixExpr::String(String::new())
-                }
-            } else if parts.is_empty() {
-                NixExpr::String(String::new())
-            } else if parts.iter().any(|p| matches!(p, StringPart::Interpolation(_))) {
-                NixExpr::InterpolatedString(parts)
-            } else {
-                // All literal parts, concatenate them
-                let s = parts.into_iter()
-                    .filter_map(|p| match p {
-                        StringPart::Literal(s) => Some(s),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join("");
-                NixExpr::String(s)
-            }
-        }),
+        map(many0(string_part), string_from_parts),
         char('"'),
     )(input)
 }
 
+/// Join adjacent literal parts, collapsing to a plain string when nothing is interpolated
+fn string_from_parts(parts: Vec<StringPart>) -> NixExpr {
+    let mut merged: Vec<StringPart> = Vec::new();
+    for part in parts {
+        match (merged.last_mut(), part) {
+            (Some(StringPart::Literal(text)), StringPart::Literal(next)) => text.push_str(&next),
+            (_, part) => merged.push(part),
+        }
+    }
+    match merged.as_slice() {
+        [] => NixExpr::String(String::new()),
+        [StringPart::Literal(text)] => NixExpr::String(text.clone()),
+        _ => NixExpr::InterpolatedString(merged),
+    }
+}
+
+/// Character produced by `\c` in a string; Nix only gives `n`, `r` and `t` special meaning
+fn unescape(c: char) -> String {
+    match c {
+        'n' => "\n".to_string(),
+        'r' => "\r".to_string(),
+        't' => "\t".to_string(),
+        c => c.to_string(),
+    }
+}
+
 fn string_part(input: &str) -> IResult<&str, StringPart> {
     alt((
         map(
             delimited(tag("${"), nix_expr, char('}')),
             |expr| StringPart::Interpolation(Box::new(expr)),
         ),
+        // Escapes: `\n`, `\r` and `\t` are control characters, any other `\c` is just `c`
+        map(preceded(char('\\'), anychar), |c| StringPart::Literal(unescape(c))),
         map(
-            take_while1(|c| c != '"' && c != '$'),
+            take_while1(|c| c != '"' && c != '$' && c != '\\'),
             |s: &str| StringPart::Literal(s.to_string()),
         ),
         map(
@@ -176,16 +402,56 @@ fn string_part(input: &str) -> IResult<&str, StringPart> {
     ))(input)
 }
 
+// Escapes and interpolations; indentation is stripped once the whole string is read
+fn indented_string_part(input: &str) -> IResult<&str, StringPart> {
+    alt((
+        map(tag("''$"), |_| StringPart::Literal("$".to_string())),
+        map(tag("'''"), |_| StringPart::Literal("''".to_string())),
+        map(preceded(tag("''\\"), anychar), |c| StringPart::Literal(unescape(c))),
+        map(
+            delimited(tag("${"), nix_expr, char('}')),
+            |expr| StringPart::Interpolation(Box::new(expr)),
+        ),
+        map(
+            take_while1(|c| c != '\'' && c != '$'),
+            |s: &str| StringPart::Literal(s.to_string()),
+        ),
+        map(terminated(char('\''), not(char('\''))), |_| StringPart::Literal("'".to_string())),
+        map(char('$'), |_| StringPart::Literal("$".to_string())),
+    ))(input)
+}
+
 fn nix_path(input: &str) -> IResult<&str, NixExpr> {
+    // Like Nix, a path is `.`, `..` or nothing followed by one or more non-empty `/` segments.
+    // So a bare `/`, `//` (update) and a trailing `/` are never part of a path, while `-` is:
+    // `./a-b` is one path and `./a - b` is a subtraction.
     map(
-        recognize(pair(
-            alt((tag("./"), tag("../"), tag("/"))),
-            take_while(|c: char| c.is_alphanumeric() || "/-_.".contains(c)),
+        alt((
+            recognize(pair(
+                opt(alt((tag(".."), tag(".")))),
+                many1(preceded(char('/'), take_while1(is_path_char))),
+            )),
+            search_path,
         )),
         |s: &str| NixExpr::Path(s.to_string()),
     )(input)
 }
 
+// `<nixpkgs>` or `<nixpkgs/lib>`, with no space inside the brackets. Only tried where an operand
+// may start, so `a < b` is still a comparison; `a <b>` is an application, as in Nix.
+fn search_path(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        char('<'),
+        take_while1(is_path_char),
+        many0(preceded(char('/'), take_while1(is_path_char))),
+        char('>'),
+    )))(input)
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_alphanumeric() || "._-+".contains(c)
+}
+
 fn nix_uri(input: &str) -> IResult<&str, NixExpr> {
     map(
         recognize(tuple((
@@ -209,21 +475,34 @@ fn nix_number(input: &str) -> IResult<&str, NixExpr> {
 
 fn nix_bool(input: &str) -> IResult<&str, NixExpr> {
     alt((
-        value(NixExpr::Bool(true), tag("true")),
-        value(NixExpr::Bool(false), tag("false")),
+        value(NixExpr::Bool(true), keyword("true")),
+        value(NixExpr::Bool(false), keyword("false")),
     ))(input)
 }
 
 fn nix_null(input: &str) -> IResult<&str, NixExpr> {
-    value(NixExpr::Null, tag("null"))(input)
+    value(NixExpr::Null, keyword("null"))(input)
+}
+
+// Reserved words that can never be used as a plain identifier in expression position.
+// They are still accepted as attribute names, e.g. `{ or = 1; }` or `x.then`.
+const KEYWORDS: &[&str] = &["if", "then", "else", "assert", "with", "let", "in", "rec", "inherit", "or"];
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+// Match a keyword only when it is not the prefix of a longer identifier (`in` vs `inputs`)
+fn keyword<'a>(kw: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(tag(kw), not(satisfy(is_identifier_char)))
 }
 
 fn nix_identifier(input: &str) -> IResult<&str, NixExpr> {
     map(
-        recognize(pair(
-            alt((alpha1, tag("_"))),
-            take_while(|c: char| c.is_alphanumeric() || c == '_' || c == '-'),
-        )),
+        verify(
+            recognize(pair(alt((alpha1, tag("_"))), take_while(is_identifier_char))),
+            |s: &str| !KEYWORDS.contains(&s),
+        ),
         |s: &str| NixExpr::Identifier(s.to_string()),
     )(input)
 }
@@ -239,7 +518,7 @@ fn identifier_string(input: &str) -> IResult<&str, String> {
 }
 
 fn nix_attrset(input: &str) -> IResult<&str, NixExpr> {
-    let (input, recursive) = opt(ws(tag("rec")))(input)?;
+    let (input, recursive) = opt(ws(keyword("rec")))(input)?;
     let (input, _) = ws(char('{'))(input)?;
     let (input, bindings) = separated_list0(ws(char(';')), binding)(input)?;
     let (input, _) = opt(ws(char(';')))(input)?; // Optional trailing semicolon
@@ -267,7 +546,7 @@ fn nix_list_item(input: &str) -> IResult<&str, NixExpr> {
         // Handle attribute access like "self.overlays.default" but not function calls
         nix_select_expr,
         nix_identifier,
-        delimited(char('('), nix_expr, char(')')),
+        nix_parenthesized_select,
     )))(input)
 }
 
@@ -275,21 +554,34 @@ fn nix_list_item(input: &str) -> IResult<&str, NixExpr> {
 fn nix_select_expr(input: &str) -> IResult<&str, NixExpr> {
     let (input, base) = nix_identifier(input)?;
     let (input, selects) = many0(preceded(ws(char('.')), attr_path_part))(input)?;
+    let (input, default) = if selects.is_empty() {
+        (input, None)
+    } else {
+        opt(preceded(ws(keyword("or")), nix_select_default))(input)?
+    };
     
-    Ok((input, selects.into_iter().fold(base, |acc, part| {
+    let selected = selects.into_iter().fold(base, |acc, part| {
         let path = AttrPath { parts: vec![part] };
         NixExpr::Select {
             expr: Box::new(acc),
             path,
             default: None,
         }
-    })))
+    });
+    Ok((input, match (selected, default) {
+        (NixExpr::Select { expr, path, .. }, Some(default)) => NixExpr::Select {
+            expr,
+            path,
+            default: Some(Box::new(default)),
+        },
+        (selected, _) => selected,
+    }))
 }
 
 fn nix_let_in(input: &str) -> IResult<&str, NixExpr> {
-    let (input, _) = ws(tag("let"))(input)?;
+    let (input, _) = ws(keyword("let"))(input)?;
     let (input, bindings) = many1(terminated(binding, ws(char(';'))))(input)?;
-    let (input, _) = ws(tag("in"))(input)?;
+    let (input, _) = ws(keyword("in"))(input)?;
     let (input, body) = nix_expr(input)?;
     
     Ok((input, NixExpr::LetIn {
@@ -299,8 +591,10 @@ fn nix_let_in(input: &str) -> IResult<&str, NixExpr> {
 }
 
 fn nix_with(input: &str) -> IResult<&str, NixExpr> {
-    let (input, _) = ws(tag("with"))(input)?;
-    let (input, env) = nix_expr(input)?;
+    let (input, _) = ws(keyword("with"))(input)?;
+    // The scope is an operator-level expression so it always stops at the `;`;
+    // `with` binds loosest, so the body takes everything after it
+    let (input, env) = ws(nix_binary_expr)(input)?;
     let (input, _) = ws(char(';'))(input)?;
     let (input, body) = nix_expr(input)?;
     
@@ -311,11 +605,11 @@ fn nix_with(input: &str) -> IResult<&str, NixExpr> {
 }
 
 fn nix_if(input: &str) -> IResult<&str, NixExpr> {
-    let (input, _) = ws(tag("if"))(input)?;
+    let (input, _) = ws(keyword("if"))(input)?;
     let (input, condition) = nix_expr(input)?;
-    let (input, _) = ws(tag("then"))(input)?;
+    let (input, _) = ws(keyword("then"))(input)?;
     let (input, then_expr) = nix_expr(input)?;
-    let (input, _) = ws(tag("else"))(input)?;
+    let (input, _) = ws(keyword("else"))(input)?;
     let (input, else_expr) = nix_expr(input)?;
     
     Ok((input, NixExpr::If {
@@ -326,7 +620,7 @@ fn nix_if(input: &str) -> IResult<&str, NixExpr> {
 }
 
 fn nix_assert(input: &str) -> IResult<&str, NixExpr> {
-    let (input, _) = ws(tag("assert"))(input)?;
+    let (input, _) = ws(keyword("assert"))(input)?;
     let (input, condition) = nix_expr(input)?;
     let (input, _) = ws(char(';'))(input)?;
     let (input, body) = nix_expr(input)?;
@@ -350,23 +644,15 @@ fn nix_lambda(input: &str) -> IResult<&str, NixExpr> {
 
 fn lambda_param(input: &str) -> IResult<&str, LambdaParam> {
     alt((
+        // `name@{ ... }`
         map(
-            delimited(
-                ws(char('{')),
-                pair(
-                    separated_list0(ws(char(',')), pattern_param),
-                    alt((
-                        preceded(ws(char(',')), tag("...")),
-                        // Handle trailing comma without ellipsis
-                        map(opt(ws(char(','))), |_| ""),
-                    )),
-                ),
-                ws(char('}')),
-            ),
-            |(params, ellipsis)| LambdaParam::Pattern {
-                params,
-                ellipsis: ellipsis == "...",
-            },
+            pair(terminated(identifier_string, ws(char('@'))), lambda_pattern),
+            |(bind, (params, ellipsis))| LambdaParam::Pattern { params, ellipsis, bind: Some(bind) },
+        ),
+        // `{ ... }` or `{ ... }@name`
+        map(
+            pair(lambda_pattern, opt(preceded(ws(char('@')), identifier_string))),
+            |((params, ellipsis), bind)| LambdaParam::Pattern { params, ellipsis, bind },
         ),
         map(
             recognize(pair(
@@ -378,6 +664,24 @@ fn lambda_param(input: &str) -> IResult<&str, LambdaParam> {
     ))(input)
 }
 
+fn lambda_pattern(input: &str) -> IResult<&str, (Vec<PatternParam>, bool)> {
+    map(
+        delimited(
+            ws(char('{')),
+            pair(
+                separated_list0(ws(char(',')), pattern_param),
+                alt((
+                    preceded(ws(char(',')), tag("...")),
+                    // Handle trailing comma without ellipsis
+                    map(opt(ws(char(','))), |_| ""),
+                )),
+            ),
+            ws(char('}')),
+        ),
+        |(params, ellipsis)| (params, ellipsis == "..."),
+    )(input)
+}
+
 fn pattern_param(input: &str) -> IResult<&str, PatternParam> {
     let (input, name) = ws(recognize(pair(
         alt((alpha1, tag("_"))),
@@ -395,10 +699,10 @@ pub fn binding(input: &str) -> IResult<&str, Binding> {
     alt((
         map(
             tuple((
-                ws(tag("inherit")),
+                ws(keyword("inherit")),
                 opt(delimited(ws(char('(')), nix_expr, ws(char(')')))),
-                // Fix: Parse identifiers separated by whitespace
-                separated_list1(multispace1, ws(identifier_string)),
+                // `ws` already consumes the whitespace between names
+                many1(ws(identifier_string)),
             )),
             |(_, from, attrs)| Binding {
                 path: AttrPath { parts: vec![AttrPathPart::Identifier("inherit".to_string())] },
@@ -422,23 +726,29 @@ fn attr_path(input: &str) -> IResult<&str, AttrPath> {
     )(input)
 }
 
+/// Quoted attribute names are stored as source text with only `\"` decoded, since
+/// reconstructed names carry their interpolations as `${...}`
+fn key_source(text: &str) -> String {
+    text.replace('\\', "\\\\").replace("${", "\\${")
+}
+
 fn attr_path_part(input: &str) -> IResult<&str, AttrPathPart> {
     alt((
         // Handle interpolated strings like "go_1_${toString goVersion}"
         map(nix_interpolated_string, |expr| match expr {
-            NixExpr::String(s) => AttrPathPart::String(s),
+            NixExpr::String(s) => AttrPathPart::String(key_source(&s)),
             NixExpr::InterpolatedString(parts) => {
                 // Convert to a single interpolation for simplicity
                 if parts.len() == 1 {
                     match &parts[0] {
-                        StringPart::Literal(s) => AttrPathPart::String(s.clone()),
+                        StringPart::Literal(s) => AttrPathPart::String(key_source(s)),
                         StringPart::Interpolation(expr) => AttrPathPart::Interpolation(expr.clone()),
                     }
                 } else {
                     // For complex interpolated strings, reconstruct the string
                     let reconstructed = parts.iter()
                         .map(|part| match part {
-                            StringPart::Literal(s) => s.clone(),
+                            StringPart::Literal(s) => key_source(s),
                             StringPart::Interpolation(expr) => format!("${{{}}}", expr.to_nix_string()),
                         })
                         .collect::<String>();
@@ -463,6 +773,9 @@ fn attr_path_part(input: &str) -> IResult<&str, AttrPathPart> {
 
 fn binary_operator(input: &str) -> IResult<&str, BinaryOperator> {
     alt((
+        // Pipes come first so `<|` isn't read as `<`
+        value(BinaryOperator::PipeForward, tag("|>")),
+        value(BinaryOperator::PipeBackward, tag("<|")),
         value(BinaryOperator::Eq, tag("==")),
         value(BinaryOperator::Ne, tag("!=")),
         value(BinaryOperator::Le, tag("<=")),
@@ -498,8 +811,9 @@ where
 fn skip_whitespace_and_comments(input: &str) -> IResult<&str, ()> {
     let (input, _) = many0(alt((
         map(multispace1, |_| ()),
-        map(preceded(char('#'), take_until("\n")), |_| ()),
-        map(preceded(char('#'), take_while(|_| true)), |_| ()), // Handle comment at end of file
+        // Line comments stop at either half of a CRLF pair so no stray '\r' is swallowed,
+        // and also cover a comment at end of file
+        map(preceded(char('#'), take_while(|c| c != '\n' && c != '\r')), |_| ()),
     )))(input)?;
     Ok((input, ()))
 }
\ No newline at end of file